@@ -2,21 +2,62 @@ extern crate cgmath;
 
 use cgmath::{Vector, Vector3, EuclideanVector};
 
-static ANCHOR_FIELD_LEN: f32 = 10.0;
-static ANCHOR_FIELD_STR: f32 = 10.0;
+use std::f32::consts::PI;
+use std::num::Float;
+
+pub static ANCHOR_FIELD_LEN: f32 = 10.0;
+pub static ANCHOR_FIELD_STR: f32 = 10.0;
 static ANCHOR_FIELD_DAMP: f32 = 0.2;
 
+// How an anchor's position changes over simulated time
+#[deriving(Clone, Show)]
+pub enum AnchorMotion {
+  Fixed,
+  // Centre, radius and seconds per lap, counter clockwise in xy from +x
+  Orbit(Vector3<f32>, f32, f32),
+  // Constant velocity away from the origin
+  Linear(Vector3<f32>),
+  // Swings origin +- amplitude, seconds per full swing
+  Oscillate(Vector3<f32>, f32),
+}
+
+impl AnchorMotion {
+  pub fn position_at(&self, origin: Vector3<f32>, time: f32) -> Vector3<f32> {
+    return match *self {
+      AnchorMotion::Fixed => origin,
+      AnchorMotion::Orbit(center, radius, period) => {
+        let angle = 2.0 * PI * time / period;
+        center.add_v(&Vector3::new(radius * angle.cos(), radius * angle.sin(), 0.0))
+      },
+      AnchorMotion::Linear(velocity) => origin.add_v(&velocity.mul_s(time)),
+      AnchorMotion::Oscillate(amplitude, period) => {
+        origin.add_v(&amplitude.mul_s((2.0 * PI * time / period).sin()))
+      },
+    };
+  }
+}
+
+#[deriving(Clone)]
 pub struct AnchorEnt {
-  pos: Vector3<f32>,
-  strength: f32,
-  distance: f32,
+  pub pos: Vector3<f32>,
+  // Where the motion is measured from
+  pub origin: Vector3<f32>,
+  pub strength: f32,
+  pub distance: f32,
+  pub motion: AnchorMotion,
 }
 
 impl AnchorEnt {
   pub fn default() -> AnchorEnt {
-    let pos = Vector3::new(0.0, 0.0, 0.0);
+    return AnchorEnt::new(Vector3::new(0.0, 0.0, 0.0), ANCHOR_FIELD_STR, ANCHOR_FIELD_LEN);
+  }
 
-    return AnchorEnt{pos: pos, strength: ANCHOR_FIELD_STR, distance: ANCHOR_FIELD_LEN};
+  pub fn new(pos: Vector3<f32>, strength: f32, distance: f32) -> AnchorEnt {
+    return AnchorEnt{pos: pos, origin: pos, strength: strength, distance: distance, motion: AnchorMotion::Fixed};
+  }
+
+  pub fn move_to_time(&mut self, time: f32) -> () {
+    self.pos = self.motion.position_at(self.origin, time);
   }
 
   pub fn damped_force_at(&self, other_pos: Vector3<f32>, other_vel: Vector3<f32>) -> Vector3<f32> {
@@ -35,4 +76,19 @@ impl AnchorEnt {
 
     return delta.sub_v(&idle_pos).mul_s(self.strength);
   }
+
+  pub fn distance_to(&self, other_pos: Vector3<f32>) -> f32 {
+    return self.pos.sub_v(&other_pos).length();
+  }
+
+  // Spring energy, matching force_at
+  pub fn potential_at(&self, other_pos: Vector3<f32>) -> f32 {
+    let stretch = self.pos.sub_v(&other_pos).length() - self.distance;
+    return 0.5 * self.strength * stretch * stretch;
+  }
+}
+
+// Infinite when there are no anchors at all
+pub fn nearest_anchor_distance(anchors: &[AnchorEnt], pos: Vector3<f32>) -> f32 {
+  return anchors.iter().fold(Float::infinity(), |nearest: f32, anchor| nearest.min(anchor.distance_to(pos)));
 }
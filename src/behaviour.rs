@@ -0,0 +1,169 @@
+extern crate cgmath;
+
+use swarm_ent::SwarmEnt;
+use force::FieldContext;
+use anchor_ent::nearest_anchor_distance;
+
+use cgmath::{Vector2, EuclideanVector};
+
+use std::collections::HashMap;
+
+#[deriving(Clone, PartialEq, Eq, Hash, Show)]
+pub enum BehaviourState {
+  Idle,
+  FollowAnchor,
+  Explore,
+  Return,
+  Avoid,
+}
+
+impl BehaviourState {
+  pub fn all() -> Vec<BehaviourState> {
+    return vec![BehaviourState::Idle,
+                BehaviourState::FollowAnchor,
+                BehaviourState::Explore,
+                BehaviourState::Return,
+                BehaviourState::Avoid];
+  }
+
+  // Stable position within all(), handy for lookup tables
+  pub fn index(&self) -> uint {
+    return match *self {
+      BehaviourState::Idle => 0,
+      BehaviourState::FollowAnchor => 1,
+      BehaviourState::Explore => 2,
+      BehaviourState::Return => 3,
+      BehaviourState::Avoid => 4,
+    };
+  }
+
+  pub fn name(&self) -> &'static str {
+    return match *self {
+      BehaviourState::Idle => "idle",
+      BehaviourState::FollowAnchor => "follow-anchor",
+      BehaviourState::Explore => "explore",
+      BehaviourState::Return => "return",
+      BehaviourState::Avoid => "avoid",
+    };
+  }
+
+  pub fn from_name(name: &str) -> Option<BehaviourState> {
+    return BehaviourState::all().into_iter().find(|state| state.name() == name);
+  }
+}
+
+pub enum Condition {
+  TimeInState(f32),
+  AnchorFurtherThan(f32),
+  AnchorCloserThan(f32),
+  // At least this many others within the radius
+  NeighboursWithin(f32, uint),
+  NoNeighboursWithin(f32),
+  FieldHeightAbove(f32),
+  SpeedBelow(f32),
+}
+
+impl Condition {
+  pub fn holds(&self, ent: &SwarmEnt, status: &BehaviourStatus, ctx: &FieldContext) -> bool {
+    return match *self {
+      Condition::TimeInState(seconds) => status.time_in_state >= seconds,
+      Condition::AnchorFurtherThan(dist) => nearest_anchor_distance(ctx.anchors, ent.pos) > dist,
+      Condition::AnchorCloserThan(dist) => nearest_anchor_distance(ctx.anchors, ent.pos) < dist,
+      Condition::NeighboursWithin(radius, count) => ctx.neighbours.neighbors_within(ent.id, radius).len() >= count,
+      Condition::NoNeighboursWithin(radius) => ctx.neighbours.neighbors_within(ent.id, radius).is_empty(),
+      Condition::FieldHeightAbove(height) => ctx.world.height_at(Vector2::new(ent.pos.x, ent.pos.y)) > height,
+      Condition::SpeedBelow(speed) => ent.vel.length() < speed,
+    };
+  }
+}
+
+pub struct Transition {
+  pub from: BehaviourState,
+  pub to: BehaviourState,
+  pub condition: Condition,
+}
+
+// Where a single entity is within its machine
+#[deriving(Clone)]
+pub struct BehaviourStatus {
+  // Index into the field's behaviours
+  pub machine: uint,
+  pub state: BehaviourState,
+  pub time_in_state: f32,
+}
+
+impl BehaviourStatus {
+  pub fn start(machine_idx: uint, machine: &StateMachine) -> BehaviourStatus {
+    return BehaviourStatus{machine: machine_idx, state: machine.initial, time_in_state: 0.0};
+  }
+}
+
+pub struct StateMachine {
+  pub initial: BehaviourState,
+  // Checked in order, the first one that holds wins
+  transitions: Vec<Transition>,
+  // Registry weight overrides while in a state, forces not listed keep their usual weight
+  weights: HashMap<BehaviourState, Vec<(String, f32)>>,
+}
+
+impl StateMachine {
+  pub fn new(initial: BehaviourState) -> StateMachine {
+    return StateMachine{initial: initial, transitions: Vec::new(), weights: HashMap::new()};
+  }
+
+  // Settle, gather on the anchor, wander off for a while, come back. Back off when crowded.
+  pub fn default() -> StateMachine {
+    let mut machine = StateMachine::new(BehaviourState::Idle);
+    machine.add_transition(BehaviourState::Idle, BehaviourState::FollowAnchor, Condition::TimeInState(1.0));
+    machine.add_transition(BehaviourState::FollowAnchor, BehaviourState::Avoid, Condition::NeighboursWithin(2.5, 3));
+    machine.add_transition(BehaviourState::FollowAnchor, BehaviourState::Explore, Condition::TimeInState(10.0));
+    machine.add_transition(BehaviourState::Avoid, BehaviourState::FollowAnchor, Condition::NoNeighboursWithin(3.0));
+    machine.add_transition(BehaviourState::Explore, BehaviourState::Return, Condition::AnchorFurtherThan(25.0));
+    machine.add_transition(BehaviourState::Explore, BehaviourState::Return, Condition::TimeInState(8.0));
+    machine.add_transition(BehaviourState::Return, BehaviourState::FollowAnchor, Condition::AnchorCloserThan(12.0));
+
+    machine.set_weights(BehaviourState::Idle, &[("anchor", 0.0), ("manifold", 0.0), ("boids", 0.0)]);
+    machine.set_weights(BehaviourState::Explore, &[("anchor", 0.0), ("boids", 1.0)]);
+    machine.set_weights(BehaviourState::Return, &[("anchor", 2.0), ("boids", 0.0)]);
+    machine.set_weights(BehaviourState::Avoid, &[("anchor", 0.5), ("manifold", 1.0)]);
+    return machine;
+  }
+
+  pub fn add_transition(&mut self, from: BehaviourState, to: BehaviourState, condition: Condition) -> () {
+    self.transitions.push(Transition{from: from, to: to, condition: condition});
+  }
+
+  pub fn set_weights(&mut self, state: BehaviourState, weights: &[(&str, f32)]) -> () {
+    let weights = weights.iter().map(|&(name, weight)| (name.to_string(), weight)).collect();
+    self.weights.insert(state, weights);
+  }
+
+  pub fn weight_overrides(&self, state: BehaviourState) -> &[(String, f32)] {
+    return match self.weights.get(&state) {
+      Some(weights) => weights.as_slice(),
+      None => &[],
+    };
+  }
+
+  pub fn next_state(&self, ent: &SwarmEnt, status: &BehaviourStatus, ctx: &FieldContext) -> Option<BehaviourState> {
+    return self.transitions.iter()
+      .find(|transition| transition.from == status.state && transition.condition.holds(ent, status, ctx))
+      .map(|transition| transition.to);
+  }
+}
+
+// What every field starts out with, spawns refer to these by index
+pub fn default_machines() -> Vec<StateMachine> {
+  return vec![StateMachine::default()];
+}
+
+// Tint used by the viewer for each state
+pub fn state_color(state: BehaviourState) -> [u8, ..4] {
+  return match state {
+    BehaviourState::Idle => [0x80u8, 0x80u8, 0x80u8, 0x00u8],
+    BehaviourState::FollowAnchor => [0x20u8, 0xA0u8, 0xC0u8, 0x00u8],
+    BehaviourState::Explore => [0x40u8, 0xC0u8, 0x40u8, 0x00u8],
+    BehaviourState::Return => [0xE0u8, 0xC0u8, 0x20u8, 0x00u8],
+    BehaviourState::Avoid => [0xE0u8, 0x30u8, 0x30u8, 0x00u8],
+  };
+}
@@ -0,0 +1,102 @@
+extern crate cgmath;
+
+use swarm_ent::SwarmEnt;
+use force::{Force, FieldContext};
+
+use cgmath::{Vector, Vector3, EuclideanVector};
+
+use std::f32::consts::PI;
+use std::num::Float;
+
+// Reynolds' separation, alignment and cohesion, as one registry force
+pub struct Boids {
+  pub neighbour_radius: f32,
+  // Too close for comfort, only these get pushed away from
+  pub separation_radius: f32,
+  // Full viewing angle in radians centred on the direction of travel, 2*PI sees everything
+  pub field_of_view: f32,
+  pub separation_weight: f32,
+  pub alignment_weight: f32,
+  pub cohesion_weight: f32,
+}
+
+impl Boids {
+  pub fn default() -> Boids {
+    return Boids{
+      neighbour_radius: 6.0,
+      separation_radius: 2.5,
+      field_of_view: 1.5 * PI,
+      separation_weight: 20.0,
+      alignment_weight: 1.0,
+      cohesion_weight: 0.5,
+    };
+  }
+
+  fn can_see(&self, ent: &SwarmEnt, offset: Vector3<f32>) -> bool {
+    let speed = ent.vel.length();
+    if speed == 0.0 || self.field_of_view >= 2.0 * PI {
+      // No heading to look along
+      return true;
+    }
+    let cos_angle = ent.vel.dot(&offset) / (speed * offset.length());
+    return cos_angle >= (0.5 * self.field_of_view).cos();
+  }
+}
+
+impl Force for Boids {
+  fn accel(&self, ent: &SwarmEnt, ctx: &FieldContext) -> Vector3<f32> {
+    let mut separation = Vector3::new(0.0, 0.0, 0.0);
+    let mut vel_sum = Vector3::new(0.0, 0.0, 0.0);
+    let mut pos_sum = Vector3::new(0.0, 0.0, 0.0);
+    let mut neighbours = 0u;
+
+    for &other_id in ctx.neighbours.neighbors_within(ent.id, self.neighbour_radius).iter() {
+      let other = match ctx.swarm.get(other_id) {
+        Some(other) => other,
+        None => continue,
+      };
+
+      let offset = other.pos.sub_v(&ent.pos);
+      let dist = offset.length();
+      if dist == 0.0 || dist > self.neighbour_radius || !self.can_see(ent, offset) {
+        continue;
+      }
+
+      if dist < self.separation_radius {
+        // Falls off with the square of distance
+        separation = separation.sub_v(&offset.div_s(dist * dist));
+      }
+      vel_sum = vel_sum.add_v(&other.vel);
+      pos_sum = pos_sum.add_v(&other.pos);
+      neighbours = neighbours + 1;
+    }
+
+    if neighbours == 0 {
+      return Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    let count = neighbours as f32;
+    let alignment = vel_sum.div_s(count).sub_v(&ent.vel);
+    let cohesion = pos_sum.div_s(count).sub_v(&ent.pos);
+
+    return separation.mul_s(self.separation_weight)
+      .add_v(&alignment.mul_s(self.alignment_weight))
+      .add_v(&cohesion.mul_s(self.cohesion_weight));
+  }
+
+  fn params(&self) -> Vec<f32> {
+    return vec![self.neighbour_radius, self.separation_radius, self.field_of_view,
+                self.separation_weight, self.alignment_weight, self.cohesion_weight];
+  }
+
+  fn set_params(&mut self, params: &[f32]) -> () {
+    if params.len() >= 6 {
+      self.neighbour_radius = params[0];
+      self.separation_radius = params[1];
+      self.field_of_view = params[2];
+      self.separation_weight = params[3];
+      self.alignment_weight = params[4];
+      self.cohesion_weight = params[5];
+    }
+  }
+}
@@ -0,0 +1,310 @@
+extern crate getopts;
+
+use std::cmp;
+use std::fmt;
+use std::from_str::FromStr;
+use std::io::IoError;
+use std::os;
+
+use swarm::scenario::ScenarioError;
+use swarm::replay::ReplayError;
+use swarm::snapshot::SnapshotError;
+use swarm::trajectory::EntitySelection;
+use swarm::sweep::{Param, ParamRange, SweepError};
+use swarm::optimize::{Method, Objective};
+
+static DEFAULT_WIDTH: u32 = 960;
+static DEFAULT_HEIGHT: u32 = 1080;
+static DEFAULT_GENERATIONS: uint = 20;
+// Every simulation running at once holds its own manifold (world_manifold::FIELD_BYTES,
+// about 400MB), so the default stops short of every core on big machines. --threads goes higher.
+static MAX_DEFAULT_THREADS: uint = 4;
+
+pub enum Command {
+  Run,
+  Headless,
+  Record,
+  Replay,
+  Bench,
+  Sweep,
+  Optimize,
+}
+
+impl Command {
+  fn from_name(name: &str) -> Option<Command> {
+    return match name {
+      "run" => Some(Command::Run),
+      "headless" => Some(Command::Headless),
+      "record" => Some(Command::Record),
+      "replay" => Some(Command::Replay),
+      "bench" => Some(Command::Bench),
+      "sweep" => Some(Command::Sweep),
+      "optimize" => Some(Command::Optimize),
+      _ => None,
+    };
+  }
+}
+
+pub struct Options {
+  pub command: Command,
+  // Each of these overrides the scenario's own setting when given.
+  // --entities swaps the scenario's spawn groups for one box of that many.
+  pub entities: Option<uint>,
+  // Simulated seconds, the windowed run ignores this and goes until closed
+  pub duration: Option<f32>,
+  pub tick_rate: Option<f32>,
+  pub seed: Option<u64>,
+  pub config: Option<Path>,
+  pub output: Option<Path>,
+  pub input: Option<Path>,
+  // Start from this snapshot instead of the scenario's initial state
+  pub resume: Option<Path>,
+  // Where headless writes the final state as a snapshot
+  pub snapshot: Option<Path>,
+  // Sampled entity state is appended here as the run goes, in whichever mode
+  pub trajectory: Option<Path>,
+  // Swarm metrics time series is appended here as the run goes
+  pub metrics: Option<Path>,
+  // For both the trajectory and metrics
+  pub sample_every: u64,
+  pub selection: EntitySelection,
+  // What a sweep varies, and how
+  pub ranges: Vec<ParamRange>,
+  // Random points instead of the grid
+  pub samples: Option<uint>,
+  pub threads: uint,
+  // Searching the same --param ranges
+  pub method: Method,
+  pub objective: Objective,
+  pub generations: uint,
+  // 0 lets the method pick
+  pub population: uint,
+  pub width: u32,
+  pub height: u32,
+  pub vertex_shader: Path,
+  pub fragment_shader: Path,
+}
+
+pub enum CliError {
+  // --help, not a failure but it stops the run the same way
+  Help,
+  Usage(String),
+  // Flag, the value it was given, what we wanted instead
+  BadValue(String, String, &'static str),
+  Io(Path, IoError),
+  Scenario(Path, ScenarioError),
+  Replay(Path, ReplayError),
+  Snapshot(Path, SnapshotError),
+  Sweep(Path, SweepError),
+  // Window, context or shader trouble in the viewer
+  Graphics(String),
+  Unsupported(String),
+}
+
+impl fmt::Show for CliError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      CliError::Help => write!(f, "{}", usage()),
+      CliError::Usage(ref message) => write!(f, "{}\n\n{}", message, usage()),
+      CliError::BadValue(ref flag, ref value, expected) => write!(f, "--{} {}: expected {}", flag, value, expected),
+      CliError::Io(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+      CliError::Scenario(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+      CliError::Replay(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+      CliError::Snapshot(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+      CliError::Sweep(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+      CliError::Graphics(ref what) => write!(f, "{}", what),
+      CliError::Unsupported(ref what) => write!(f, "{}", what),
+    }
+  }
+}
+
+fn opts() -> Vec<getopts::OptGroup> {
+  return vec![
+    getopts::optopt("n", "entities", "number of swarm entities to spawn", "N"),
+    getopts::optopt("d", "duration", "simulated seconds to run for", "SECONDS"),
+    getopts::optopt("r", "tick-rate", "fixed simulation ticks per second", "HZ"),
+    getopts::optopt("s", "seed", "seed for every random draw in the simulation", "SEED"),
+    getopts::optopt("c", "config", "scenario file to load, .toml or .json", "PATH"),
+    getopts::optopt("o", "output", "where to write results", "PATH"),
+    getopts::optopt("i", "input", "recording to replay", "PATH"),
+    getopts::optopt("", "resume", "snapshot to start from, .json or binary", "PATH"),
+    getopts::optopt("", "snapshot", "where headless saves its final state, .json or binary", "PATH"),
+    getopts::optopt("", "trajectory", "append entity positions, velocities and accelerations here, .csv or columnar", "PATH"),
+    getopts::optopt("", "metrics", "write the swarm metrics time series here as CSV", "PATH"),
+    getopts::optopt("", "sample-every", "ticks between trajectory and metrics samples", "TICKS"),
+    getopts::optopt("", "groups", "only sample entities in these groups", "G,G,.."),
+    getopts::optopt("", "slots", "only sample the entities in these store slots", "I,I,.."),
+    getopts::optmulti("p", "param", "sweep NAME over MIN..MAX in STEPS grid points: anchor-strength, deform-strength, manifold-weight, kernel-sigma or weight:FORCE", "NAME=MIN:MAX[:STEPS]"),
+    getopts::optopt("", "samples", "sweep this many random points instead of the grid", "N"),
+    getopts::optopt("j", "threads", "simulations to run at once, about 400MB each, defaults to the number of cores up to 4", "N"),
+    getopts::optopt("", "method", "optimiser to use, cma-es or genetic", "METHOD"),
+    getopts::optopt("", "objective", "what to optimise: ring-time, min-collisions, polarization or COLUMN:WEIGHT,..", "OBJECTIVE"),
+    getopts::optopt("", "generations", "generations to search for", "N"),
+    getopts::optopt("", "population", "candidates per generation", "N"),
+    getopts::optopt("", "width", "window width", "PIXELS"),
+    getopts::optopt("", "height", "window height", "PIXELS"),
+    getopts::optopt("", "vertex-shader", "vertex shader source", "PATH"),
+    getopts::optopt("", "fragment-shader", "fragment shader source", "PATH"),
+    getopts::optflag("h", "help", "print this message"),
+  ];
+}
+
+pub fn usage() -> String {
+  let brief = "Usage: swarm-cli <run|headless|record|replay|bench|sweep|optimize> [options]";
+  return getopts::usage(brief, opts().as_slice());
+}
+
+// args includes the program name, same as os::args()
+pub fn parse(args: &[String]) -> Result<Options, CliError> {
+  let command = match args.get(1) {
+    Some(name) if name.as_slice() == "-h" || name.as_slice() == "--help" => return Err(CliError::Help),
+    Some(name) => match Command::from_name(name.as_slice()) {
+      Some(command) => command,
+      None => return Err(CliError::Usage(format!("Unknown command '{}'", name))),
+    },
+    None => return Err(CliError::Usage("Missing command".to_string())),
+  };
+
+  let matches = match getopts::getopts(args.slice_from(2), opts().as_slice()) {
+    Ok(matches) => matches,
+    Err(fail) => return Err(CliError::Usage(fail.to_string())),
+  };
+  if matches.opt_present("help") {
+    return Err(CliError::Help);
+  }
+  if !matches.free.is_empty() {
+    return Err(CliError::Usage(format!("Unexpected argument '{}'", matches.free[0])));
+  }
+
+  let entities = match matches.opt_str("entities") {
+    Some(_) => Some(try!(parse_flag(&matches, "entities", 0u, "a whole number"))),
+    None => None,
+  };
+  let seed = match matches.opt_str("seed") {
+    Some(_) => Some(try!(parse_flag(&matches, "seed", 0u64, "a whole number"))),
+    None => None,
+  };
+  let duration = match matches.opt_str("duration") {
+    Some(_) => Some(try!(parse_flag(&matches, "duration", 0.0f32, "a number of seconds"))),
+    None => None,
+  };
+  let tick_rate = match matches.opt_str("tick-rate") {
+    Some(_) => Some(try!(parse_flag(&matches, "tick-rate", 0.0f32, "a number of ticks per second"))),
+    None => None,
+  };
+  let sample_every: u64 = try!(parse_flag(&matches, "sample-every", 1u64, "a whole number of ticks"));
+  if sample_every == 0 {
+    return Err(CliError::BadValue("sample-every".to_string(), "0".to_string(), "at least one tick"));
+  }
+  let selection = match (matches.opt_str("groups"), matches.opt_str("slots")) {
+    (Some(_), Some(_)) => return Err(CliError::Usage("Pick one of --groups and --slots".to_string())),
+    (Some(list), None) => EntitySelection::Groups(try!(parse_list("groups", list))),
+    (None, Some(list)) => EntitySelection::Slots(try!(parse_list("slots", list))),
+    (None, None) => EntitySelection::All,
+  };
+  let mut ranges = Vec::new();
+  for text in matches.opt_strs("param").into_iter() {
+    match ParamRange::parse(text.as_slice()) {
+      Some(range) => ranges.push(range),
+      None => {
+        let name = text.as_slice().split('=').next().unwrap_or("").trim();
+        return match Param::unknown_force(name) {
+          Some(message) => Err(CliError::Usage(format!("--param {}", message))),
+          None => Err(CliError::BadValue("param".to_string(), text, "NAME=MIN:MAX[:STEPS], see --help for names")),
+        };
+      },
+    }
+  }
+  let samples = match matches.opt_str("samples") {
+    Some(_) => Some(try!(parse_flag(&matches, "samples", 0u, "a whole number"))),
+    None => None,
+  };
+  let threads: uint = try!(parse_flag(&matches, "threads", cmp::min(os::num_cpus(), MAX_DEFAULT_THREADS), "a whole number"));
+  if threads == 0 {
+    return Err(CliError::BadValue("threads".to_string(), "0".to_string(), "at least 1"));
+  }
+  let method = match matches.opt_str("method") {
+    Some(name) => match Method::from_name(name.as_slice()) {
+      Some(method) => method,
+      None => return Err(CliError::BadValue("method".to_string(), name, "cma-es or genetic")),
+    },
+    None => Method::CmaEs,
+  };
+  let objective = match matches.opt_str("objective") {
+    Some(text) => match Objective::parse(text.as_slice()) {
+      Some(objective) => objective,
+      None => return Err(CliError::BadValue("objective".to_string(), text, "ring-time, min-collisions, polarization or COLUMN:WEIGHT,..")),
+    },
+    None => Objective::RingTime,
+  };
+  let generations: uint = try!(parse_flag(&matches, "generations", DEFAULT_GENERATIONS, "a whole number"));
+  if generations == 0 {
+    return Err(CliError::BadValue("generations".to_string(), "0".to_string(), "at least 1"));
+  }
+  let population: uint = try!(parse_flag(&matches, "population", 0u, "a whole number"));
+  let width: u32 = try!(parse_flag(&matches, "width", DEFAULT_WIDTH, "a whole number of pixels"));
+  let height: u32 = try!(parse_flag(&matches, "height", DEFAULT_HEIGHT, "a whole number of pixels"));
+
+  match duration {
+    Some(seconds) if seconds < 0.0 => {
+      return Err(CliError::BadValue("duration".to_string(), seconds.to_string(), "zero or more seconds"));
+    },
+    _ => (),
+  }
+  match tick_rate {
+    Some(rate) if rate <= 0.0 => {
+      return Err(CliError::BadValue("tick-rate".to_string(), rate.to_string(), "more than zero ticks per second"));
+    },
+    _ => (),
+  }
+
+  return Ok(Options{
+    command: command,
+    entities: entities,
+    duration: duration,
+    tick_rate: tick_rate,
+    seed: seed,
+    config: matches.opt_str("config").map(|path| Path::new(path)),
+    output: matches.opt_str("output").map(|path| Path::new(path)),
+    input: matches.opt_str("input").map(|path| Path::new(path)),
+    resume: matches.opt_str("resume").map(|path| Path::new(path)),
+    snapshot: matches.opt_str("snapshot").map(|path| Path::new(path)),
+    trajectory: matches.opt_str("trajectory").map(|path| Path::new(path)),
+    metrics: matches.opt_str("metrics").map(|path| Path::new(path)),
+    sample_every: sample_every,
+    selection: selection,
+    ranges: ranges,
+    samples: samples,
+    threads: threads,
+    method: method,
+    objective: objective,
+    generations: generations,
+    population: population,
+    width: width,
+    height: height,
+    vertex_shader: Path::new(matches.opt_str("vertex-shader").unwrap_or("vertex-shader.glsl".to_string())),
+    fragment_shader: Path::new(matches.opt_str("fragment-shader").unwrap_or("fragment-shader.glsl".to_string())),
+  });
+}
+
+fn parse_flag<T: FromStr>(matches: &getopts::Matches, flag: &str, default: T, expected: &'static str) -> Result<T, CliError> {
+  return match matches.opt_str(flag) {
+    Some(value) => match from_str(value.as_slice()) {
+      Some(parsed) => Ok(parsed),
+      None => Err(CliError::BadValue(flag.to_string(), value, expected)),
+    },
+    None => Ok(default),
+  };
+}
+
+// Comma separated whole numbers
+fn parse_list(flag: &str, list: String) -> Result<Vec<uint>, CliError> {
+  let mut values = Vec::new();
+  for item in list.as_slice().split(',') {
+    match from_str(item.trim()) {
+      Some(value) => values.push(value),
+      None => return Err(CliError::BadValue(flag.to_string(), list.clone(), "comma separated whole numbers")),
+    }
+  }
+  return Ok(values);
+}
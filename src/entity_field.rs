@@ -2,80 +2,305 @@ extern crate cgmath;
 
 use anchor_ent::AnchorEnt;
 use world_manifold::WorldManifold;
-use swarm_ent::SwarmEnt;
+use swarm_ent::{SwarmEnt, DEFAULT_RADIUS};
+use zone_ent::{ZoneEnt, ZoneInfluence, combined_zone_force_at};
+use force::{FieldContext, ForceRegistry};
+use telemetry::{ForceSample, ForceTelemetry};
+use metrics::{MetricsLog, SwarmMetrics};
+use integrator::Integrator;
+use spatial_hash::SpatialHash;
+use entity_store::{EntityId, EntityStore};
+use spawn::{Distribution, EntitySpec, SpecError};
+use interaction::InteractionMatrix;
+use neighbour_index::NeighbourIndex;
+use behaviour::{BehaviourStatus, StateMachine, default_machines};
+use scenario::Scenario;
+use sim_rng::{SimRng, DEFAULT_SEED};
 
 use cgmath::{Vector, Vector3, EuclideanVector};
 
-use std::rand;
+use std::collections::HashMap;
 use std::rand::Rng;
+use std::num::Float;
+use std::io::IoResult;
 
-static SWARM_FIELD_STR: int = 100;
-//static GRAVITY_STR: f32 = -10.0;
-static COLL_DIAMETER: f32 = 2.0;
+pub static SWARM_FIELD_STR: int = 100;
+pub static DEFAULT_RESTITUTION: f32 = 0.5;
+pub static DEFAULT_FRICTION: f32 = 0.1;
+static NEIGHBOUR_CELL_SIZE: f32 = 4.0;
 
 pub struct Collision {
-  pub ent1_id: int,
-  pub ent2_id: int,
+  pub ent1_id: EntityId,
+  pub ent2_id: EntityId,
 }
 
 pub struct EntityField {
-  pub anchor: AnchorEnt,
+  pub anchors: Vec<AnchorEnt>,
   pub world: WorldManifold,
-  pub swarm: Vec<SwarmEnt>
+  // How hard each swarm entity presses into the manifold
+  pub deform_strength: int,
+  pub swarm: EntityStore<SwarmEnt>,
+  pub zones: Vec<ZoneEnt>,
+  pub forces: ForceRegistry,
+  pub interactions: InteractionMatrix,
+  pub telemetry: ForceTelemetry,
+  pub metrics: MetricsLog,
+  pub integrator: Integrator,
+  // Machines that swarm entities' behaviour statuses index into
+  pub behaviours: Vec<StateMachine>,
+  // Print each behaviour state change to stderr, off so headless runs and sweeps stay quiet
+  pub log_transitions: bool,
+  pub collisions_enabled: bool,
+  // 0 is perfectly inelastic, 1 perfectly elastic
+  pub restitution: f32,
+  // Coulomb friction coefficient applied along the contact tangent
+  pub friction: f32,
+  // Contacts found last tick before any were resolved, 0 with collisions off
+  pub collisions: uint,
+  collision_grid: SpatialHash,
+  neighbours: NeighbourIndex,
+  // Each swarm entity's zone influence at its start of tick position, shared by forces and dominant_zones
+  zone_influences: HashMap<EntityId, (Vector3<f32>, ZoneInfluence)>,
+  // Which zone dominated each swarm entity's acceleration last tick
+  pub dominant_zones: Vec<(EntityId, Option<uint>)>,
+  // The acceleration forces gave each swarm entity last tick, before collisions
+  pub accels: Vec<(EntityId, Vector3<f32>)>,
+  // Simulated seconds so far, drives anchor motion
+  pub time: f32,
+  pub ticks: u64,
+  // Every random draw the simulation makes comes from here, nothing else may use task_rng
+  pub rng: SimRng,
 }
 
 impl EntityField {
   pub fn default() -> EntityField {
-    let anchor = AnchorEnt::default();
-    let world = WorldManifold::default();
-    let mut rng = rand::task_rng();
-    let swarm = vec![SwarmEnt{id: 0, pos: Vector3::new(0.0,5.0,0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 1, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 2, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 3, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 4, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 5, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 6, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 7, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 8, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 9, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 9, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 10, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 11, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 12, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-                     SwarmEnt{id: 13, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-        ];
-                     //SwarmEnt{id: 1, pos: Vector3::new(rng.gen_range(-5.0, 5.0),rng.gen_range(-5.0, 5.0),0.0), vel: Vector3::new(0.0, 0.0, 0.0)},
-
-    return EntityField{anchor: anchor, world: world, swarm: swarm};
+    return Scenario::default().build_field().ok().expect("the default scenario should spawn");
+  }
+
+  // A field with no swarm in it yet
+  pub fn empty() -> EntityField {
+    return EntityField{
+      anchors: vec![AnchorEnt::default()],
+      world: WorldManifold::default(),
+      deform_strength: SWARM_FIELD_STR,
+      swarm: EntityStore::new(),
+      zones: Vec::new(),
+      forces: ForceRegistry::default(),
+      interactions: InteractionMatrix::new(1),
+      telemetry: ForceTelemetry::new(),
+      metrics: MetricsLog::new(),
+      integrator: Integrator::default(),
+      behaviours: default_machines(),
+      log_transitions: false,
+      collisions_enabled: false,
+      restitution: DEFAULT_RESTITUTION,
+      friction: DEFAULT_FRICTION,
+      collisions: 0,
+      collision_grid: SpatialHash::new(2.0 * DEFAULT_RADIUS),
+      neighbours: NeighbourIndex::new(NEIGHBOUR_CELL_SIZE),
+      zone_influences: HashMap::new(),
+      dominant_zones: Vec::new(),
+      accels: Vec::new(),
+      time: 0.0,
+      ticks: 0,
+      rng: SimRng::new(DEFAULT_SEED),
+    };
+  }
+
+  // Safe between any two ticks: the manifold is rebuilt from scratch and collisions are
+  // recomputed at the start of the next one
+  pub fn spawn(&mut self, spec: &EntitySpec) -> Result<EntityId, SpecError> {
+    try!(spec.validate());
+    if !WorldManifold::in_bounds(spec.pos) {
+      return Err(SpecError::OutOfBounds(spec.pos));
+    }
+    return Ok(self.swarm.insert_with(|id| SwarmEnt::from_spec(id, spec)));
+  }
+
+  // The spec's position is ignored in favour of the distribution. Nothing is spawned if any
+  // sampled position is off the manifold.
+  pub fn spawn_many(&mut self, distribution: &Distribution, count: uint, spec: &EntitySpec) -> Result<Vec<EntityId>, SpecError> {
+    try!(spec.validate());
+    let positions: Vec<Vector3<f32>> = range(0, count).map(|index| distribution.sample(&mut self.rng, index)).collect();
+    match positions.iter().find(|&&pos| !WorldManifold::in_bounds(pos)) {
+      Some(&pos) => return Err(SpecError::OutOfBounds(pos)),
+      None => (),
+    }
+    return Ok(positions.into_iter().map(|pos| {
+      self.swarm.insert_with(|id| {
+        let mut entity = SwarmEnt::from_spec(id, spec);
+        entity.pos = pos;
+        entity
+      })
+    }).collect());
+  }
+
+  // Throws the whole swarm somewhere new within +-half_width on the xy plane, at rest
+  pub fn scatter(&mut self, half_width: f32) -> () {
+    for entity in self.swarm.iter_mut() {
+      let new_x = self.rng.gen_range(-half_width, half_width);
+      let new_y = self.rng.gen_range(-half_width, half_width);
+      entity.vel = Vector3::new(0.0, 0.0, 0.0);
+      entity.pos = Vector3::new(new_x, new_y, 0.0);
+    }
+  }
+
+  pub fn despawn(&mut self, id: EntityId) -> bool {
+    if self.swarm.remove(id).is_none() {
+      return false;
+    }
+
+    // Don't leave diagnostics around for an entity that no longer exists
+    self.neighbours.forget(id);
+    self.zone_influences.remove(&id);
+    self.dominant_zones.retain(|&(ent_id, _)| ent_id != id);
+    self.accels.retain(|&(ent_id, _)| ent_id != id);
+    self.telemetry.forget(id);
+    return true;
   }
 
   pub fn tick(&mut self, delta_t: f32) -> () {
+    for anchor in self.anchors.iter_mut() {
+      anchor.move_to_time(self.time);
+    }
     self.world.flatten();
+    // Per-group layers only matter to ManifoldForce when groups see each other differently
+    self.world.split_groups = !self.interactions.is_uniform();
 
     for entity in self.swarm.iter() {
       // I dont think this should use delta t, dt is factored in @ integration time
-      self.world.deform(entity.pos, SWARM_FIELD_STR);
+      self.world.deform(entity.pos, self.deform_strength, entity.group);
     }
+    self.neighbours.rebuild(&self.swarm);
 
-    for entity in self.swarm.iter_mut() {
-      let anchor_accel = self.anchor.damped_force_at(entity.pos, entity.vel);
-      let swarm_accel = self.world.gradient_at(entity.pos).mul_s(0.2);
-      let gravity_accel = Vector3::new(0.0, 0.0, 0.0);
-      let total_accel = anchor_accel.add_v(&swarm_accel).add_v(&gravity_accel);
+    let zone_influences = self.swarm.iter().map(|entity| {
+      (entity.id, (entity.pos, combined_zone_force_at(self.zones.as_slice(), entity.pos, &self.world)))
+    }).collect();
+    self.zone_influences = zone_influences;
+
+    let behaviour_updates: Vec<Option<BehaviourStatus>> = {
+      let ctx = self.context();
+      self.swarm.iter().map(|entity| {
+        entity.behaviour.map(|status| self.advance_behaviour(entity, status, delta_t, &ctx))
+      }).collect()
+    };
+    for (entity, update) in self.swarm.iter_mut().zip(behaviour_updates.into_iter()) {
+      entity.behaviour = update;
+    }
+
+    let accels: Vec<Vector3<f32>> = if self.telemetry.enabled {
+      let samples: Vec<ForceSample> = {
+        let ctx = self.context();
+        self.swarm.iter().map(|entity| {
+          ForceSample::new(entity, self.forces.breakdown_with(entity, &ctx, self.weight_overrides_for(entity)))
+        }).collect()
+      };
+      let accels = samples.iter().map(|sample| sample.total).collect();
+      // Numbered the way self.ticks will be once this tick is done
+      self.telemetry.record(self.ticks + 1, samples);
+      accels
+    } else {
+      let ctx = self.context();
+      self.swarm.iter().map(|entity| self.forces.accel_with(entity, &ctx, self.weight_overrides_for(entity))).collect()
+    };
+
+    self.dominant_zones = self.swarm.iter().map(|entity| {
+      (entity.id, self.zone_influences.get(&entity.id).and_then(|&(_, ref influence)| influence.dominant))
+    }).collect();
+    self.accels = self.swarm.iter().zip(accels.iter()).map(|(entity, accel)| (entity.id, *accel)).collect();
+
+    let next_states: Vec<SwarmEnt> = {
+      let ctx = self.context();
+      self.swarm.iter().zip(accels.iter()).map(|(entity, accel)| {
+        let overrides = self.weight_overrides_for(entity);
+        let next_state = self.integrator.step(entity, delta_t, *accel, |trial| self.forces.accel_with(trial, &ctx, overrides));
+        entity.kinematics.constrain(entity, next_state, delta_t)
+      }).collect()
+    };
+
+    for (entity, next_state) in self.swarm.iter_mut().zip(next_states.into_iter()) {
+      *entity = next_state;
+    }
+
+    self.collisions = 0;
+    if self.collisions_enabled {
+      self.resolve_all_collisions();
+    }
+
+    self.time = self.time + delta_t;
+    self.ticks = self.ticks + 1;
+
+    if self.metrics.wants(self.ticks) {
+      let sample = SwarmMetrics::measure(self);
+      self.metrics.record(sample);
+    }
+  }
+
+  pub fn context<'a>(&'a self) -> FieldContext<'a> {
+    return FieldContext{
+      anchors: self.anchors.as_slice(),
+      world: &self.world,
+      zones: self.zones.as_slice(),
+      zone_influences: &self.zone_influences,
+      swarm: &self.swarm,
+      interactions: &self.interactions,
+      neighbours: &self.neighbours,
+    };
+  }
+
+  fn weight_overrides_for(&self, entity: &SwarmEnt) -> &[(String, f32)] {
+    return match entity.behaviour {
+      Some(status) if status.machine < self.behaviours.len() => {
+        self.behaviours[status.machine].weight_overrides(status.state)
+      },
+      _ => &[],
+    };
+  }
+
+  fn advance_behaviour(&self, entity: &SwarmEnt, status: BehaviourStatus, delta_t: f32, ctx: &FieldContext) -> BehaviourStatus {
+    let mut status = status;
+    status.time_in_state = status.time_in_state + delta_t;
+    if status.machine >= self.behaviours.len() {
+      return status;
+    }
+
+    match self.behaviours[status.machine].next_state(entity, &status, ctx) {
+      Some(next) => {
+        if self.log_transitions {
+          let _ = writeln!(&mut ::std::io::stderr(), "ent {}: {} -> {}", entity.id, status.state.name(), next.name());
+        }
+        status.state = next;
+        status.time_in_state = 0.0;
+      },
+      None => (),
+    }
+    return status;
+  }
 
-      //println!("accels: anchor_accel: {}, swarm_accel: {}", anchor_accel, swarm_accel)
-      entity.integrate(delta_t, total_accel);
+  // One row per swarm entity, for dumping results out of headless runs
+  pub fn write_state_csv<W: Writer>(&self, out: &mut W) -> IoResult<()> {
+    try!(writeln!(out, "id,group,x,y,z,vx,vy,vz"));
+    for entity in self.swarm.iter() {
+      try!(writeln!(out, "{},{},{},{},{},{},{},{}", entity.id, entity.group,
+                    entity.pos.x, entity.pos.y, entity.pos.z,
+                    entity.vel.x, entity.vel.y, entity.vel.z));
     }
+    return out.flush();
+  }
 
-    // Remember, no collisions
-    //self.resolve_all_collisions();
+  // Neighbour queries see positions as of the start of the last tick, the same view forces get
+  pub fn neighbors_within(&self, id: EntityId, radius: f32) -> Vec<EntityId> {
+    return self.neighbours.neighbors_within(id, radius);
+  }
 
+  pub fn k_nearest(&self, id: EntityId, k: uint) -> Vec<EntityId> {
+    return self.neighbours.k_nearest(id, k);
   }
 
   fn resolve_all_collisions(&mut self) {
     let mut collisions = self.find_collisions();
     let mut iterations: int = 0;
+    self.collisions = collisions.len();
 
     while !collisions.is_empty() && iterations < 5 {
 
@@ -88,47 +313,171 @@ impl EntityField {
     }
   }
 
-  fn find_collisions(&self) -> Vec<Collision> {
+  fn find_collisions(&mut self) -> Vec<Collision> {
+    // Broadphase: anything that can touch shares a cell or sits in a neighbouring one
+    // Cells have to be big enough for the largest pair of entities to touch across one
+    let ids = self.swarm.ids();
+    let positions: Vec<Vector3<f32>> = self.swarm.iter().map(|entity| entity.pos).collect();
+    let radii: Vec<f32> = self.swarm.iter().map(|entity| entity.radius).collect();
+    let max_radius = radii.iter().fold(DEFAULT_RADIUS, |max, &radius| max.max(radius));
+    self.collision_grid.rebuild(2.0 * max_radius, positions.as_slice());
+
     let mut collisions: Vec<Collision> = Vec::new();
-    for first_ent in self.swarm.iter() {
-      for second_ent in self.swarm.iter() {
-        if first_ent.id < second_ent.id {
-          // Collision detection
-          if first_ent.pos.sub_v(&second_ent.pos).length() < COLL_DIAMETER {
-            collisions.push(Collision{ent1_id: first_ent.id, ent2_id: second_ent.id})
-          }
-        }
+    for &(first_idx, second_idx) in self.collision_grid.candidate_pairs().iter() {
+      // Narrowphase
+      let contact_dist = radii[first_idx] + radii[second_idx];
+      if positions[first_idx].sub_v(&positions[second_idx]).length() < contact_dist {
+        collisions.push(Collision{ent1_id: ids[first_idx], ent2_id: ids[second_idx]})
       }
     }
     return collisions;
   }
 
   fn resolve_single_collision(&mut self, collision: &Collision) -> () {
-    let first_id = collision.ent1_id;
-    let second_id = collision.ent2_id;
-
-    // weird optimization
-    let first_ent = self.swarm[first_id as uint];
-    let second_ent = self.swarm[second_id as uint];
+    let (first_ent, second_ent) = match (self.swarm.get(collision.ent1_id), self.swarm.get(collision.ent2_id)) {
+      (Some(first_ent), Some(second_ent)) => (*first_ent, *second_ent),
+      // One of them went away since the collision was found
+      _ => return,
+    };
 
-    // Move the two ents
     let collision_vec = first_ent.pos.sub_v(&second_ent.pos);
-    let overlap = COLL_DIAMETER - collision_vec.length() + 0.05*COLL_DIAMETER;
-    let new_first_ent_pos = first_ent.pos.add_v(&collision_vec.normalize_to(overlap/2.0));
-    let new_second_ent_pos = second_ent.pos.add_v(&collision_vec.neg().normalize_to(overlap/2.0));
+    let normal = if collision_vec.length() == 0.0 {
+      Vector3::new(1.0, 0.0, 0.0)
+    } else {
+      collision_vec.normalize()
+    };
+    let inv_first_mass = 1.0 / first_ent.mass;
+    let inv_second_mass = 1.0 / second_ent.mass;
+    let inv_total_mass = inv_first_mass + inv_second_mass;
 
-    self.swarm[first_id as uint].pos = new_first_ent_pos;
-    self.swarm[second_id as uint].pos = new_second_ent_pos;
+    // Move the two ents, the lighter one gives up more ground
+    let contact_dist = first_ent.radius + second_ent.radius;
+    let overlap = contact_dist - collision_vec.length() + 0.05*contact_dist;
+    let new_first_ent_pos = first_ent.pos.add_v(&normal.mul_s(overlap * inv_first_mass / inv_total_mass));
+    let new_second_ent_pos = second_ent.pos.sub_v(&normal.mul_s(overlap * inv_second_mass / inv_total_mass));
+
+    self.swarm.get_mut(collision.ent1_id).unwrap().pos = new_first_ent_pos;
+    self.swarm.get_mut(collision.ent2_id).unwrap().pos = new_second_ent_pos;
 
     // Make them bounce
-    let total_vel = first_ent.vel.add_v(&second_ent.vel);
+    let relative_vel = first_ent.vel.sub_v(&second_ent.vel);
+    let normal_speed = relative_vel.dot(&normal);
+    if normal_speed >= 0.0 {
+      // Already separating
+      return;
+    }
 
-    //let total_mass = first_ent.mass + second_ent.mass
+    let normal_impulse = -(1.0 + self.restitution) * normal_speed / inv_total_mass;
+    let mut impulse = normal.mul_s(normal_impulse);
 
-    self.swarm[first_id as uint].vel = total_vel.mul_s(-0.5);
-    self.swarm[second_id as uint].vel = total_vel.mul_s(0.5);
+    let tangent_vel = relative_vel.sub_v(&normal.mul_s(normal_speed));
+    if tangent_vel.length() > 0.0 {
+      let tangent = tangent_vel.normalize();
+      let max_friction = self.friction * normal_impulse;
+      let tangent_impulse = (-relative_vel.dot(&tangent) / inv_total_mass).max(-max_friction).min(max_friction);
+      impulse = impulse.add_v(&tangent.mul_s(tangent_impulse));
+    }
+
+    // Equal and opposite, so momentum is conserved
+    self.swarm.get_mut(collision.ent1_id).unwrap().vel = first_ent.vel.add_v(&impulse.mul_s(inv_first_mass));
+    self.swarm.get_mut(collision.ent2_id).unwrap().vel = second_ent.vel.sub_v(&impulse.mul_s(inv_second_mass));
   }
 }
 
+#[cfg(test)]
+mod test {
+  use super::{Collision, EntityField};
+  use entity_store::EntityId;
+  use spawn::EntitySpec;
+
+  use cgmath::{Vector, Vector3};
+
+  use std::num::Float;
+
+  // Two overlapping entities, the first three times as heavy as the second
+  fn pair(field: &mut EntityField, first_pos: Vector3<f32>, first_vel: Vector3<f32>,
+          second_pos: Vector3<f32>, second_vel: Vector3<f32>) -> (EntityId, EntityId) {
+    let mut spec = EntitySpec::at(first_pos);
+    spec.vel = first_vel;
+    spec.mass = 3.0;
+    let first = field.spawn(&spec).ok().unwrap();
 
+    let mut spec = EntitySpec::at(second_pos);
+    spec.vel = second_vel;
+    spec.mass = 1.0;
+    let second = field.spawn(&spec).ok().unwrap();
+    return (first, second);
+  }
+
+  fn momentum(field: &EntityField) -> Vector3<f32> {
+    return field.swarm.iter().fold(Vector3::new(0.0, 0.0, 0.0), |total, entity| total.add_v(&entity.vel.mul_s(entity.mass)));
+  }
+
+  fn kinetic_energy(field: &EntityField) -> f32 {
+    return field.swarm.iter().fold(0.0, |total, entity| total + 0.5 * entity.mass * entity.vel.dot(&entity.vel));
+  }
 
+  fn collide(field: &mut EntityField, ids: (EntityId, EntityId)) -> () {
+    let (first, second) = ids;
+    field.resolve_single_collision(&Collision{ent1_id: first, ent2_id: second});
+  }
+
+  fn assert_close(actual: f32, expected: f32) -> () {
+    assert!((actual - expected).abs() <= 1e-4 * expected.abs().max(1.0), "{} != {}", actual, expected);
+  }
+
+  fn assert_momentum_kept(restitution: f32, friction: f32, second_pos: Vector3<f32>) -> () {
+    let mut field = EntityField::empty();
+    field.restitution = restitution;
+    field.friction = friction;
+    let ids = pair(&mut field, Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.5, 0.0),
+                   second_pos, Vector3::new(-3.0, 0.0, 0.0));
+
+    let before = momentum(&field);
+    collide(&mut field, ids);
+    let after = momentum(&field);
+    assert_close(after.x, before.x);
+    assert_close(after.y, before.y);
+    assert_close(after.z, before.z);
+  }
+
+  #[test]
+  fn head_on_collision_conserves_momentum() {
+    assert_momentum_kept(0.5, 0.1, Vector3::new(0.1, 0.0, 0.0));
+  }
+
+  #[test]
+  fn glancing_collision_conserves_momentum() {
+    assert_momentum_kept(0.5, 0.1, Vector3::new(0.08, 0.06, 0.0));
+  }
+
+  #[test]
+  fn elastic_collisions_conserve_kinetic_energy() {
+    for second_pos in [Vector3::new(0.1, 0.0, 0.0), Vector3::new(0.08, 0.06, 0.0)].iter() {
+      let mut field = EntityField::empty();
+      field.restitution = 1.0;
+      field.friction = 0.0;
+      let ids = pair(&mut field, Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.5, 0.0),
+                     *second_pos, Vector3::new(-3.0, 0.0, 0.0));
+
+      let before = kinetic_energy(&field);
+      collide(&mut field, ids);
+      assert_close(kinetic_energy(&field), before);
+    }
+  }
+
+  #[test]
+  fn spawn_rejects_bad_specs() {
+    let mut field = EntityField::empty();
+    let mut spec = EntitySpec::default();
+    spec.mass = 0.0;
+    assert!(field.spawn(&spec).is_err());
+
+    let mut spec = EntitySpec::default();
+    spec.radius = -1.0;
+    assert!(field.spawn(&spec).is_err());
+
+    assert!(field.spawn(&EntitySpec::at(Vector3::new(5000.0, 0.0, 0.0))).is_err());
+    assert_eq!(field.swarm.len(), 0);
+  }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::slice::{Items, MutItems};
+
+// A handle into an EntityStore. The generation makes handles to removed entities go
+// stale instead of silently pointing at whatever reused the slot.
+#[deriving(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntityId {
+  pub index: uint,
+  pub generation: uint,
+}
+
+impl fmt::Show for EntityId {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}v{}", self.index, self.generation)
+  }
+}
+
+struct Slot<T> {
+  generation: uint,
+  value: Option<T>,
+}
+
+pub struct EntityStore<T> {
+  slots: Vec<Slot<T>>,
+  free: Vec<uint>,
+  len: uint,
+}
+
+impl<T> EntityStore<T> {
+  pub fn new() -> EntityStore<T> {
+    return EntityStore{slots: Vec::new(), free: Vec::new(), len: 0};
+  }
+
+  // The value gets built with its own id, so entities can carry their handle around
+  pub fn insert_with(&mut self, make: |EntityId| -> T) -> EntityId {
+    let id = match self.free.pop() {
+      Some(index) => EntityId{index: index, generation: self.slots[index].generation},
+      None => {
+        self.slots.push(Slot{generation: 0, value: None});
+        EntityId{index: self.slots.len() - 1, generation: 0}
+      },
+    };
+
+    self.slots[id.index].value = Some(make(id));
+    self.len = self.len + 1;
+    return id;
+  }
+
+  pub fn remove(&mut self, id: EntityId) -> Option<T> {
+    if !self.contains(id) {
+      return None;
+    }
+
+    let slot = &mut self.slots[id.index];
+    slot.generation = slot.generation + 1;
+    self.free.push(id.index);
+    self.len = self.len - 1;
+    return slot.value.take();
+  }
+
+  pub fn contains(&self, id: EntityId) -> bool {
+    return self.get(id).is_some();
+  }
+
+  pub fn get(&self, id: EntityId) -> Option<&T> {
+    if id.index >= self.slots.len() {
+      return None;
+    }
+
+    let slot = &self.slots[id.index];
+    if slot.generation != id.generation {
+      return None;
+    }
+    return slot.value.as_ref();
+  }
+
+  pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+    if id.index >= self.slots.len() {
+      return None;
+    }
+
+    let slot = &mut self.slots[id.index];
+    if slot.generation != id.generation {
+      return None;
+    }
+    return slot.value.as_mut();
+  }
+
+  pub fn len(&self) -> uint {
+    return self.len;
+  }
+
+  pub fn is_empty(&self) -> bool {
+    return self.len == 0;
+  }
+
+  // Live ids in slot order, the same order iter() walks in
+  pub fn ids(&self) -> Vec<EntityId> {
+    return self.slots.iter().enumerate()
+      .filter(|&(_, slot)| slot.value.is_some())
+      .map(|(index, slot)| EntityId{index: index, generation: slot.generation})
+      .collect();
+  }
+
+  // Each slot's generation and contents in order, with the free list. Enough for
+  // from_slots to rebuild a store that hands out the same ids from then on.
+  pub fn slots(&self) -> Vec<(uint, Option<&T>)> {
+    return self.slots.iter().map(|slot| (slot.generation, slot.value.as_ref())).collect();
+  }
+
+  pub fn free_list(&self) -> &[uint] {
+    return self.free.as_slice();
+  }
+
+  // The free list has to name every empty slot exactly once. A slot missing from it is never
+  // reused, and one named twice or holding a value gets handed out over a live entity.
+  pub fn from_slots(slots: Vec<(uint, Option<T>)>, free: Vec<uint>) -> Result<EntityStore<T>, String> {
+    let mut listed = Vec::from_elem(slots.len(), false);
+    for &index in free.iter() {
+      if index >= slots.len() {
+        return Err(format!("free list names slot {}, there are only {}", index, slots.len()));
+      }
+      let (_, ref value) = slots[index];
+      if value.is_some() {
+        return Err(format!("free list names slot {}, which isn't free", index));
+      }
+      if listed[index] {
+        return Err(format!("free list names slot {} twice", index));
+      }
+      listed[index] = true;
+    }
+    for (index, &(_, ref value)) in slots.iter().enumerate() {
+      if value.is_none() && !listed[index] {
+        return Err(format!("slot {} is empty but not on the free list", index));
+      }
+    }
+
+    let len = slots.iter().filter(|&&(_, ref value)| value.is_some()).count();
+    let slots = slots.into_iter().map(|(generation, value)| Slot{generation: generation, value: value}).collect();
+    return Ok(EntityStore{slots: slots, free: free, len: len});
+  }
+
+  pub fn clear(&mut self) -> () {
+    for id in self.ids().into_iter() {
+      self.remove(id);
+    }
+  }
+
+  pub fn iter(&self) -> Entities<T> {
+    return Entities{slots: self.slots.iter()};
+  }
+
+  pub fn iter_mut(&mut self) -> EntitiesMut<T> {
+    return EntitiesMut{slots: self.slots.iter_mut()};
+  }
+}
+
+pub struct Entities<'a, T: 'a> {
+  slots: Items<'a, Slot<T>>,
+}
+
+impl<'a, T> Iterator<&'a T> for Entities<'a, T> {
+  fn next(&mut self) -> Option<&'a T> {
+    loop {
+      match self.slots.next() {
+        Some(slot) => match slot.value {
+          Some(ref value) => return Some(value),
+          None => (),
+        },
+        None => return None,
+      }
+    }
+  }
+}
+
+pub struct EntitiesMut<'a, T: 'a> {
+  slots: MutItems<'a, Slot<T>>,
+}
+
+impl<'a, T> Iterator<&'a mut T> for EntitiesMut<'a, T> {
+  fn next(&mut self) -> Option<&'a mut T> {
+    loop {
+      match self.slots.next() {
+        Some(slot) => match slot.value {
+          Some(ref mut value) => return Some(value),
+          None => (),
+        },
+        None => return None,
+      }
+    }
+  }
+}
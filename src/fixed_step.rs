@@ -0,0 +1,104 @@
+extern crate cgmath;
+
+use entity_field::EntityField;
+use entity_store::EntityId;
+
+use cgmath::{Vector, Vector3};
+
+use std::collections::HashMap;
+
+static DEFAULT_STEP: f32 = 1.0 / 120.0;
+// Past this many catch-up ticks in one frame we drop the backlog instead of spiralling
+static DEFAULT_MAX_SUBSTEPS: uint = 8;
+
+pub struct FixedStepRunner {
+  pub step: f32,
+  pub max_substeps: uint,
+  pub ticks: u64,
+  accumulator: f32,
+  // Positions before the most recent tick
+  previous: HashMap<EntityId, Vector3<f32>>,
+}
+
+impl FixedStepRunner {
+  pub fn new(step: f32, max_substeps: uint) -> FixedStepRunner {
+    return FixedStepRunner{step: step, max_substeps: max_substeps, ticks: 0, accumulator: 0.0, previous: HashMap::new()};
+  }
+
+  pub fn default() -> FixedStepRunner {
+    return FixedStepRunner::new(DEFAULT_STEP, DEFAULT_MAX_SUBSTEPS);
+  }
+
+  pub fn with_rate(ticks_per_second: f32) -> FixedStepRunner {
+    return FixedStepRunner::with_step(1.0 / ticks_per_second);
+  }
+
+  // For picking up a saved run on exactly the step it used
+  pub fn with_step(step: f32) -> FixedStepRunner {
+    return FixedStepRunner::new(step, DEFAULT_MAX_SUBSTEPS);
+  }
+
+  // Feeds wall clock time in, returns how many ticks were run
+  pub fn advance(&mut self, field: &mut EntityField, frame_delta: f32) -> uint {
+    return self.advance_with(field, frame_delta, |_| ());
+  }
+
+  // Same as advance, calling after_tick with the field after every tick it runs
+  pub fn advance_with(&mut self, field: &mut EntityField, frame_delta: f32, after_tick: |&EntityField|) -> uint {
+    self.accumulator = self.accumulator + frame_delta;
+
+    let mut substeps = 0u;
+    while self.accumulator >= self.step && substeps < self.max_substeps {
+      self.single_tick(field);
+      after_tick(field);
+      self.accumulator = self.accumulator - self.step;
+      substeps = substeps + 1;
+    }
+
+    if self.accumulator >= self.step {
+      // Hitch (window drag, breakpoint...), let the simulation fall behind wall time
+      self.accumulator = self.accumulator % self.step;
+    }
+
+    return substeps;
+  }
+
+  // For headless runs, where there is no wall clock to keep up with
+  pub fn run_ticks(&mut self, field: &mut EntityField, count: uint) -> () {
+    self.run_ticks_with(field, count, |_| ());
+  }
+
+  pub fn run_ticks_with(&mut self, field: &mut EntityField, count: uint, after_tick: |&EntityField|) -> () {
+    for _ in range(0, count) {
+      self.single_tick(field);
+      after_tick(field);
+    }
+  }
+
+  // Call after teleporting entities so we don't interpolate across the jump
+  pub fn sync(&mut self, field: &EntityField) -> () {
+    self.previous = field.swarm.iter().map(|entity| (entity.id, entity.pos)).collect();
+  }
+
+  // How far we are between the previous and current tick, in [0, 1)
+  pub fn alpha(&self) -> f32 {
+    return self.accumulator / self.step;
+  }
+
+  pub fn interpolated_positions(&self, field: &EntityField) -> Vec<Vector3<f32>> {
+    let alpha = self.alpha();
+    return field.swarm.iter().map(|entity| {
+      match self.previous.get(&entity.id) {
+        Some(previous) => previous.add_v(&entity.pos.sub_v(previous).mul_s(alpha)),
+        // Spawned since the last tick
+        None => entity.pos,
+      }
+    }).collect();
+  }
+
+  fn single_tick(&mut self, field: &mut EntityField) -> () {
+    self.sync(field);
+    field.tick(self.step);
+    self.ticks = self.ticks + 1;
+  }
+}
@@ -0,0 +1,277 @@
+extern crate cgmath;
+
+use anchor_ent::AnchorEnt;
+use world_manifold::WorldManifold;
+use swarm_ent::SwarmEnt;
+use zone_ent::{ZoneEnt, ZoneInfluence, combined_zone_force_at};
+use telemetry::ForceContribution;
+use entity_store::{EntityId, EntityStore};
+use interaction::InteractionMatrix;
+use neighbour_index::NeighbourIndex;
+use boids::Boids;
+
+use cgmath::{Vector, Vector3};
+
+use std::collections::HashMap;
+use std::slice::Items;
+
+static GRAVITY_STR: f32 = -10.0;
+static MANIFOLD_WEIGHT: f32 = 0.2;
+
+// Everything a force may look at while the field is being ticked
+pub struct FieldContext<'a> {
+  pub anchors: &'a [AnchorEnt],
+  pub world: &'a WorldManifold,
+  pub zones: &'a [ZoneEnt],
+  // Zone influence worked out once this tick, with the position it was worked out at
+  pub zone_influences: &'a HashMap<EntityId, (Vector3<f32>, ZoneInfluence)>,
+  pub swarm: &'a EntityStore<SwarmEnt>,
+  pub interactions: &'a InteractionMatrix,
+  pub neighbours: &'a NeighbourIndex,
+}
+
+pub trait Force {
+  // Despite the name this is a force, the registry divides it by the entity's mass
+  fn accel(&self, ent: &SwarmEnt, ctx: &FieldContext) -> Vector3<f32>;
+
+  // Tunable numbers, in a fixed order, so snapshots can save and restore them
+  fn params(&self) -> Vec<f32> {
+    return Vec::new();
+  }
+
+  // Takes what params() gave out, anything short or extra is ignored
+  fn set_params(&mut self, _: &[f32]) -> () {
+  }
+}
+
+// Fresh, default configured, instance of a built in force by its registry name
+pub fn builtin_force(name: &str) -> Option<Box<Force + 'static>> {
+  return match name {
+    "anchor" => Some(box AnchorForce as Box<Force + 'static>),
+    "manifold" => Some(box ManifoldForce as Box<Force + 'static>),
+    "zones" => Some(box ZoneForce as Box<Force + 'static>),
+    "gravity" => Some(box GravityForce::default() as Box<Force + 'static>),
+    "boids" => Some(box Boids::default() as Box<Force + 'static>),
+    "drag" => Some(box DragForce{coefficient: 0.0} as Box<Force + 'static>),
+    "wind" => Some(box WindForce{velocity: Vector3::new(0.0, 0.0, 0.0), coefficient: 0.0} as Box<Force + 'static>),
+    _ => None,
+  };
+}
+
+pub struct WeightedForce {
+  pub name: String,
+  pub weight: f32,
+  pub force: Box<Force + 'static>,
+}
+
+pub struct ForceRegistry {
+  forces: Vec<WeightedForce>,
+}
+
+impl ForceRegistry {
+  pub fn new() -> ForceRegistry {
+    return ForceRegistry{forces: Vec::new()};
+  }
+
+  pub fn default() -> ForceRegistry {
+    let mut registry = ForceRegistry::new();
+    registry.add("anchor", 1.0, box AnchorForce);
+    registry.add("manifold", MANIFOLD_WEIGHT, box ManifoldForce);
+    registry.add("zones", 1.0, box ZoneForce);
+    // Off until we have a floor to stand on
+    registry.add("gravity", 0.0, box GravityForce::default());
+    // Reweight against "manifold" to compare or blend the two styles of coordination
+    registry.add("boids", 0.0, box Boids::default());
+    return registry;
+  }
+
+  // Adding a force under an existing name replaces it
+  pub fn add(&mut self, name: &str, weight: f32, force: Box<Force + 'static>) -> () {
+    self.remove(name);
+    self.forces.push(WeightedForce{name: name.to_string(), weight: weight, force: force});
+  }
+
+  pub fn remove(&mut self, name: &str) -> Option<WeightedForce> {
+    return match self.position(name) {
+      Some(idx) => self.forces.remove(idx),
+      None => None,
+    };
+  }
+
+  pub fn set_weight(&mut self, name: &str, weight: f32) -> bool {
+    return match self.position(name) {
+      Some(idx) => {
+        self.forces[idx].weight = weight;
+        true
+      },
+      None => false,
+    };
+  }
+
+  pub fn weight(&self, name: &str) -> Option<f32> {
+    return self.position(name).map(|idx| self.forces[idx].weight);
+  }
+
+  pub fn iter(&self) -> Items<WeightedForce> {
+    return self.forces.iter();
+  }
+
+  pub fn accel(&self, ent: &SwarmEnt, ctx: &FieldContext) -> Vector3<f32> {
+    return self.accel_with(ent, ctx, &[]);
+  }
+
+  // Named weights in `overrides` take the place of the registry's own. Heavier entities
+  // get less out of the same forces.
+  pub fn accel_with(&self, ent: &SwarmEnt, ctx: &FieldContext, overrides: &[(String, f32)]) -> Vector3<f32> {
+    let mut total = Vector3::new(0.0, 0.0, 0.0);
+    for entry in self.forces.iter() {
+      let weight = ForceRegistry::effective_weight(entry, overrides);
+      if weight == 0.0 {
+        continue;
+      }
+      total = total.add_v(&entry.force.accel(ent, ctx).mul_s(weight));
+    }
+    return total.div_s(ent.mass);
+  }
+
+  // Same as accel, but keeps each force's weighted contribution separate
+  pub fn breakdown(&self, ent: &SwarmEnt, ctx: &FieldContext) -> Vec<ForceContribution> {
+    return self.breakdown_with(ent, ctx, &[]);
+  }
+
+  pub fn breakdown_with(&self, ent: &SwarmEnt, ctx: &FieldContext, overrides: &[(String, f32)]) -> Vec<ForceContribution> {
+    return self.forces.iter()
+      .map(|entry| (entry, ForceRegistry::effective_weight(entry, overrides)))
+      .filter(|&(_, weight)| weight != 0.0)
+      .map(|(entry, weight)| {
+        ForceContribution{name: entry.name.clone(), accel: entry.force.accel(ent, ctx).mul_s(weight / ent.mass)}
+      })
+      .collect();
+  }
+
+  fn effective_weight(entry: &WeightedForce, overrides: &[(String, f32)]) -> f32 {
+    return overrides.iter()
+      .find(|&&(ref name, _)| name.as_slice() == entry.name.as_slice())
+      .map(|&(_, weight)| weight)
+      .unwrap_or(entry.weight);
+  }
+
+  pub fn set_params(&mut self, name: &str, params: &[f32]) -> bool {
+    return match self.position(name) {
+      Some(idx) => {
+        self.forces[idx].force.set_params(params);
+        true
+      },
+      None => false,
+    };
+  }
+
+  fn position(&self, name: &str) -> Option<uint> {
+    return self.forces.iter().position(|entry| entry.name.as_slice() == name);
+  }
+}
+
+// --------- Built in forces -----------
+
+pub struct AnchorForce;
+
+impl Force for AnchorForce {
+  fn accel(&self, ent: &SwarmEnt, ctx: &FieldContext) -> Vector3<f32> {
+    return ctx.anchors.iter().fold(Vector3::new(0.0, 0.0, 0.0), |total, anchor| {
+      total.add_v(&anchor.damped_force_at(ent.pos, ent.vel))
+    });
+  }
+}
+
+pub struct ManifoldForce;
+
+impl Force for ManifoldForce {
+  fn accel(&self, ent: &SwarmEnt, ctx: &FieldContext) -> Vector3<f32> {
+    if ctx.interactions.is_uniform() {
+      return ctx.world.gradient_at(ent.pos);
+    }
+    return ctx.world.group_gradient_at(ent.pos, ctx.interactions.weights_for(ent.group).as_slice());
+  }
+}
+
+pub struct ZoneForce;
+
+impl Force for ZoneForce {
+  fn accel(&self, ent: &SwarmEnt, ctx: &FieldContext) -> Vector3<f32> {
+    // Integrators probe trial positions too, those still need working out
+    return match ctx.zone_influences.get(&ent.id) {
+      Some(&(pos, ref influence)) if pos == ent.pos => influence.accel,
+      _ => combined_zone_force_at(ctx.zones, ent.pos, ctx.world).accel,
+    };
+  }
+}
+
+pub struct GravityForce {
+  pub accel: Vector3<f32>,
+}
+
+impl GravityForce {
+  pub fn default() -> GravityForce {
+    return GravityForce{accel: Vector3::new(0.0, 0.0, GRAVITY_STR)};
+  }
+}
+
+impl Force for GravityForce {
+  // Pulls everything the same however heavy
+  fn accel(&self, ent: &SwarmEnt, _: &FieldContext) -> Vector3<f32> {
+    return self.accel.mul_s(ent.mass);
+  }
+
+  fn params(&self) -> Vec<f32> {
+    return vec![self.accel.x, self.accel.y, self.accel.z];
+  }
+
+  fn set_params(&mut self, params: &[f32]) -> () {
+    if params.len() >= 3 {
+      self.accel = Vector3::new(params[0], params[1], params[2]);
+    }
+  }
+}
+
+pub struct DragForce {
+  pub coefficient: f32,
+}
+
+impl Force for DragForce {
+  fn accel(&self, ent: &SwarmEnt, _: &FieldContext) -> Vector3<f32> {
+    return ent.vel.mul_s(-self.coefficient);
+  }
+
+  fn params(&self) -> Vec<f32> {
+    return vec![self.coefficient];
+  }
+
+  fn set_params(&mut self, params: &[f32]) -> () {
+    if params.len() >= 1 {
+      self.coefficient = params[0];
+    }
+  }
+}
+
+pub struct WindForce {
+  pub velocity: Vector3<f32>,
+  pub coefficient: f32,
+}
+
+impl Force for WindForce {
+  fn accel(&self, ent: &SwarmEnt, _: &FieldContext) -> Vector3<f32> {
+    // Pushes entities towards moving with the wind
+    return self.velocity.sub_v(&ent.vel).mul_s(self.coefficient);
+  }
+
+  fn params(&self) -> Vec<f32> {
+    return vec![self.velocity.x, self.velocity.y, self.velocity.z, self.coefficient];
+  }
+
+  fn set_params(&mut self, params: &[f32]) -> () {
+    if params.len() >= 4 {
+      self.velocity = Vector3::new(params[0], params[1], params[2]);
+      self.coefficient = params[3];
+    }
+  }
+}
@@ -0,0 +1,158 @@
+extern crate cgmath;
+
+use anchor_ent::AnchorEnt;
+use swarm_ent::SwarmEnt;
+use entity_store::EntityId;
+
+use cgmath::{Vector, Vector3};
+
+use std::num::Float;
+
+#[deriving(Clone)]
+pub enum Integrator {
+  ExplicitEuler,
+  SemiImplicitEuler,
+  VelocityVerlet,
+  // Kick-drift-kick, the same update as VelocityVerlet whenever forces only depend on position.
+  // Kept separate because damping at the trial state sees the half kicked velocity here
+  // instead of Verlet's full Euler guess, which behaves differently once drag is strong.
+  Leapfrog,
+  RungeKutta4,
+}
+
+impl Integrator {
+  pub fn default() -> Integrator {
+    return Integrator::SemiImplicitEuler;
+  }
+
+  pub fn all() -> Vec<Integrator> {
+    return vec![Integrator::ExplicitEuler,
+                Integrator::SemiImplicitEuler,
+                Integrator::VelocityVerlet,
+                Integrator::Leapfrog,
+                Integrator::RungeKutta4];
+  }
+
+  pub fn name(&self) -> &'static str {
+    return match *self {
+      Integrator::ExplicitEuler => "explicit-euler",
+      Integrator::SemiImplicitEuler => "semi-implicit-euler",
+      Integrator::VelocityVerlet => "velocity-verlet",
+      Integrator::Leapfrog => "leapfrog",
+      Integrator::RungeKutta4 => "rk4",
+    };
+  }
+
+  pub fn from_name(name: &str) -> Option<Integrator> {
+    return Integrator::all().into_iter().find(|integrator| integrator.name() == name);
+  }
+
+  // `accel` is the acceleration at the entity's current state, `accel_at` re-evaluates
+  // the field for the trial states of the multi-stage methods
+  pub fn step(&self, ent: &SwarmEnt, delta_t: f32, accel: Vector3<f32>, accel_at: |&SwarmEnt| -> Vector3<f32>) -> SwarmEnt {
+    // Every stage goes through the entity's own drag and acceleration limits
+    let accel = ent.limit_accel(accel);
+    let accel_at = |trial: &SwarmEnt| trial.limit_accel(accel_at(trial));
+
+    let mut next = *ent;
+    match *self {
+      Integrator::ExplicitEuler => {
+        next.pos = ent.pos.add_v(&ent.vel.mul_s(delta_t));
+        next.vel = ent.vel.add_v(&accel.mul_s(delta_t));
+      },
+      Integrator::SemiImplicitEuler => {
+        next.vel = ent.vel.add_v(&accel.mul_s(delta_t));
+        next.limit_speed();
+        next.pos = ent.pos.add_v(&next.vel.mul_s(delta_t));
+      },
+      Integrator::VelocityVerlet => {
+        next.pos = ent.pos
+          .add_v(&ent.vel.mul_s(delta_t))
+          .add_v(&accel.mul_s(0.5 * delta_t * delta_t));
+        // Velocity dependent forces (damping) see a first order velocity guess
+        next.vel = ent.vel.add_v(&accel.mul_s(delta_t));
+        let next_accel = accel_at(&next);
+        next.vel = ent.vel.add_v(&accel.add_v(&next_accel).mul_s(0.5 * delta_t));
+      },
+      Integrator::Leapfrog => {
+        // Kick, drift, kick
+        next.vel = ent.vel.add_v(&accel.mul_s(0.5 * delta_t));
+        next.pos = ent.pos.add_v(&next.vel.mul_s(delta_t));
+        let next_accel = accel_at(&next);
+        next.vel = next.vel.add_v(&next_accel.mul_s(0.5 * delta_t));
+      },
+      Integrator::RungeKutta4 => {
+        let half_t = 0.5 * delta_t;
+        let k1_pos = ent.vel;
+        let k1_vel = accel;
+
+        let mut trial = *ent;
+        trial.pos = ent.pos.add_v(&k1_pos.mul_s(half_t));
+        trial.vel = ent.vel.add_v(&k1_vel.mul_s(half_t));
+        let k2_pos = trial.vel;
+        let k2_vel = accel_at(&trial);
+
+        trial.pos = ent.pos.add_v(&k2_pos.mul_s(half_t));
+        trial.vel = ent.vel.add_v(&k2_vel.mul_s(half_t));
+        let k3_pos = trial.vel;
+        let k3_vel = accel_at(&trial);
+
+        trial.pos = ent.pos.add_v(&k3_pos.mul_s(delta_t));
+        trial.vel = ent.vel.add_v(&k3_vel.mul_s(delta_t));
+        let k4_pos = trial.vel;
+        let k4_vel = accel_at(&trial);
+
+        let pos_slope = k1_pos.add_v(&k2_pos.mul_s(2.0)).add_v(&k3_pos.mul_s(2.0)).add_v(&k4_pos);
+        let vel_slope = k1_vel.add_v(&k2_vel.mul_s(2.0)).add_v(&k3_vel.mul_s(2.0)).add_v(&k4_vel);
+        next.pos = ent.pos.add_v(&pos_slope.mul_s(delta_t / 6.0));
+        next.vel = ent.vel.add_v(&vel_slope.mul_s(delta_t / 6.0));
+      },
+    }
+    next.limit_speed();
+    return next;
+  }
+}
+
+// Relative change in total energy of a single entity on an undamped anchor spring.
+// Lets us compare how well each integrator holds up for a given timestep.
+pub fn anchor_spring_energy_drift(integrator: &Integrator, delta_t: f32, steps: uint) -> f32 {
+  let anchor = AnchorEnt::default();
+  let mut ent = SwarmEnt::new(EntityId{index: 0, generation: 0}, Vector3::new(15.0, 0.0, 0.0));
+  ent.vel = Vector3::new(0.0, 3.0, 0.0);
+
+  let energy = |ent: &SwarmEnt| -> f32 {
+    0.5 * ent.vel.dot(&ent.vel) + anchor.potential_at(ent.pos)
+  };
+
+  let start_energy = energy(&ent);
+  for _ in range(0, steps) {
+    let accel = anchor.force_at(ent.pos);
+    ent = integrator.step(&ent, delta_t, accel, |trial| anchor.force_at(trial.pos));
+  }
+
+  return (energy(&ent) - start_energy).abs() / start_energy;
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Integrator, anchor_spring_energy_drift};
+
+  static STEP: f32 = 1.0 / 60.0;
+  static STEPS: uint = 600;
+
+  #[test]
+  fn higher_order_methods_drift_less_than_explicit_euler() {
+    let explicit = anchor_spring_energy_drift(&Integrator::ExplicitEuler, STEP, STEPS);
+    for integrator in [Integrator::SemiImplicitEuler, Integrator::VelocityVerlet, Integrator::RungeKutta4].iter() {
+      let drift = anchor_spring_energy_drift(integrator, STEP, STEPS);
+      assert!(drift < explicit, "{} drifted {} against explicit euler's {}", integrator.name(), drift, explicit);
+    }
+  }
+
+  #[test]
+  fn leapfrog_matches_verlet_without_damping() {
+    let verlet = anchor_spring_energy_drift(&Integrator::VelocityVerlet, STEP, STEPS);
+    let leapfrog = anchor_spring_energy_drift(&Integrator::Leapfrog, STEP, STEPS);
+    assert!((verlet - leapfrog).abs() < 1e-4);
+  }
+}
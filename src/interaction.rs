@@ -0,0 +1,61 @@
+// How an observing group reacts to the manifold deformations left by a source group
+#[deriving(Clone, PartialEq)]
+pub enum Interaction {
+  Attract(f32),
+  Repel(f32),
+  Ignore,
+  // Raw signed kernel weight, positive repels and negative attracts
+  Kernel(f32),
+}
+
+impl Interaction {
+  // Deformations are hills, so following the gradient downhill is repulsion
+  pub fn weight(&self) -> f32 {
+    return match *self {
+      Interaction::Attract(strength) => -strength,
+      Interaction::Repel(strength) => strength,
+      Interaction::Ignore => 0.0,
+      Interaction::Kernel(weight) => weight,
+    };
+  }
+}
+
+// Square matrix indexed by (observer group, source group)
+#[deriving(Clone)]
+pub struct InteractionMatrix {
+  groups: uint,
+  entries: Vec<Interaction>,
+}
+
+impl InteractionMatrix {
+  // Everyone repels everyone, same as a single shared manifold
+  pub fn new(groups: uint) -> InteractionMatrix {
+    return InteractionMatrix{groups: groups, entries: Vec::from_elem(groups * groups, Interaction::Repel(1.0))};
+  }
+
+  pub fn groups(&self) -> uint {
+    return self.groups;
+  }
+
+  pub fn set(&mut self, observer: uint, source: uint, interaction: Interaction) -> () {
+    assert!(observer < self.groups && source < self.groups);
+    self.entries[observer * self.groups + source] = interaction;
+  }
+
+  // Groups outside the matrix get the default behaviour
+  pub fn get(&self, observer: uint, source: uint) -> Interaction {
+    if observer >= self.groups || source >= self.groups {
+      return Interaction::Repel(1.0);
+    }
+    return self.entries[observer * self.groups + source].clone();
+  }
+
+  pub fn weights_for(&self, observer: uint) -> Vec<f32> {
+    return range(0, self.groups).map(|source| self.get(observer, source).weight()).collect();
+  }
+
+  // When nothing is special we can sample the shared manifold directly
+  pub fn is_uniform(&self) -> bool {
+    return self.entries.iter().all(|entry| entry.weight() == 1.0);
+  }
+}
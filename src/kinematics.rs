@@ -0,0 +1,81 @@
+extern crate cgmath;
+
+use swarm_ent::SwarmEnt;
+
+use cgmath::{Vector, Vector3, EuclideanVector};
+
+use std::f32::consts::PI;
+use std::num::Float;
+
+// Below this speed the direction of travel is just noise
+static MIN_STEER_SPEED: f32 = 0.001;
+
+#[deriving(Clone)]
+pub enum Kinematics {
+  // Moves however the forces say, heading just follows the velocity
+  PointMass,
+  // Only drives along its heading, turning at most this many radians per second
+  Unicycle(f32),
+  // Wheel base and top wheel speed, turning and driving share the wheels
+  DifferentialDrive(f32, f32),
+}
+
+impl Kinematics {
+  // Takes the unconstrained result of integrating `prev` and bends it into something
+  // the vehicle could actually have done over delta_t
+  pub fn constrain(&self, prev: &SwarmEnt, next: SwarmEnt, delta_t: f32) -> SwarmEnt {
+    let mut next = next;
+    let desired_speed = next.vel.length();
+    let desired_heading = if desired_speed > MIN_STEER_SPEED {
+      next.vel.y.atan2(next.vel.x)
+    } else {
+      prev.heading
+    };
+    let desired_turn = wrap_angle(desired_heading - prev.heading);
+
+    match *self {
+      Kinematics::PointMass => {
+        next.heading = desired_heading;
+        next.angular_vel = desired_turn / delta_t;
+        return next;
+      },
+      Kinematics::Unicycle(max_turn_rate) => {
+        let max_turn = max_turn_rate * delta_t;
+        let turn = desired_turn.max(-max_turn).min(max_turn);
+        next.heading = wrap_angle(prev.heading + turn);
+        next.angular_vel = turn / delta_t;
+
+        // Whatever part of the desired velocity lies along the new heading, forwards only
+        let forward_speed = next.vel.dot(&heading_dir(next.heading)).max(0.0);
+        next.vel = heading_dir(next.heading).mul_s(forward_speed);
+      },
+      Kinematics::DifferentialDrive(wheel_base, max_wheel_speed) => {
+        let forward_speed = next.vel.dot(&heading_dir(prev.heading));
+        let turn_rate = desired_turn / delta_t;
+
+        let clamp = |speed: f32| speed.max(-max_wheel_speed).min(max_wheel_speed);
+        let left = clamp(forward_speed - 0.5 * turn_rate * wheel_base);
+        let right = clamp(forward_speed + 0.5 * turn_rate * wheel_base);
+
+        next.angular_vel = (right - left) / wheel_base;
+        next.heading = wrap_angle(prev.heading + next.angular_vel * delta_t);
+        next.vel = heading_dir(next.heading).mul_s(0.5 * (left + right));
+      },
+    }
+
+    // The position has to agree with the velocity we actually allowed
+    next.pos = prev.pos.add_v(&next.vel.mul_s(delta_t));
+    return next;
+  }
+}
+
+pub fn heading_dir(heading: f32) -> Vector3<f32> {
+  return Vector3::new(heading.cos(), heading.sin(), 0.0);
+}
+
+// Into [-PI, PI)
+fn wrap_angle(angle: f32) -> f32 {
+  let wrapped = (angle + PI) % (2.0 * PI);
+  let wrapped = if wrapped < 0.0 { wrapped + 2.0 * PI } else { wrapped };
+  return wrapped - PI;
+}
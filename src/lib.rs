@@ -0,0 +1,31 @@
+// Swarm simulation, with no windowing or GPU requirements
+
+extern crate cgmath;
+extern crate serialize;
+extern crate toml;
+
+pub mod anchor_ent;
+pub mod behaviour;
+pub mod boids;
+pub mod entity_field;
+pub mod entity_store;
+pub mod fixed_step;
+pub mod force;
+pub mod integrator;
+pub mod interaction;
+pub mod kinematics;
+pub mod metrics;
+pub mod neighbour_index;
+pub mod optimize;
+pub mod replay;
+pub mod scenario;
+pub mod sim_rng;
+pub mod snapshot;
+pub mod spatial_hash;
+pub mod spawn;
+pub mod sweep;
+pub mod swarm_ent;
+pub mod telemetry;
+pub mod trajectory;
+pub mod world_manifold;
+pub mod zone_ent;
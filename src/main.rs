@@ -1,338 +1,398 @@
 #![feature(phase)]
 #![feature(slicing_syntax)]
-// Graphics
+extern crate swarm;
+extern crate time;
+
+// Graphics, only needed for the viewer
+#[cfg(feature = "viewer")]
 extern crate cgmath;
+#[cfg(feature = "viewer")]
 extern crate gfx;
+#[cfg(feature = "viewer")]
 extern crate device;
+#[cfg(feature = "viewer")]
 extern crate render;
+#[cfg(feature = "viewer")]
 #[phase(plugin)]
 extern crate gfx_macros;
+#[cfg(feature = "viewer")]
 extern crate glfw;
+#[cfg(feature = "viewer")]
 extern crate genmesh;
-extern crate time;
-
-use entity_field::EntityField;
-
-use cgmath::FixedArray;
-use cgmath:: {Matrix4, Point3};
-use cgmath::{Vector2, Vector3};
-use cgmath::{Transform, AffineMatrix3};
-use gfx::{Device, DeviceHelper, ToSlice};
-use device::BufferUsage;
-use render::mesh::Mesh;
-use glfw::Context;
-use std::rand;
-use std::rand::Rng;
 
-use genmesh::{Vertices, Triangulate};
-use genmesh::generators::{Plane, SharedVertex, IndexedPolygon};
+use swarm::entity_field::EntityField;
+use swarm::fixed_step::FixedStepRunner;
+use swarm::scenario::{Scenario, ScenarioError};
+use swarm::replay::{Recorder, Recording};
+use swarm::snapshot::Snapshot;
+use swarm::trajectory::{TrajectoryFile, TrajectoryWriter};
+use swarm::metrics::{MetricsFile, MetricsWriter, DEFAULT_HISTORY};
+use swarm::sweep::{SweepMode, run_sweep, sweep_points, apply_point};
+use swarm::optimize::{OptimizeSettings, optimize};
+use swarm::integrator::{Integrator, anchor_spring_energy_drift};
+use cli::{Command, CliError, Options};
+
+use std::io;
+use std::io::{BufferedWriter, File};
+use std::os;
+
+mod cli;
+#[cfg(feature = "viewer")]
+mod viewer;
 
-use std::io::File;
-
-mod entity_field;
-mod anchor_ent;
-mod swarm_ent;
-mod world_manifold;
-
-// Graphics
-#[vertex_format]
-struct Vertex {
-  #[name = "u_pos"]
-  pos: [f32, ..3],
-
-  #[name = "u_normal"]
-  normal: [f32, ..3],
+fn main() {
+  let args = os::args();
+  let result = match cli::parse(args.as_slice()) {
+    Ok(options) => run_command(&options),
+    Err(err) => Err(err),
+  };
 
-  #[name = "u_uv"]
-  uv: [f32, ..2],
+  match result {
+    Ok(()) => (),
+    Err(CliError::Help) => println!("{}", cli::usage()),
+    Err(err) => {
+      let _ = writeln!(&mut io::stderr(), "swarm-cli: {}", err);
+      os::set_exit_status(1);
+    },
+  }
 }
 
-#[shader_param(Entity)]
-struct Params {
-  #[name= "u_Model"]
-  model: [[f32, ..4], ..4],
-
-  #[name= "u_View"]
-  view: [[f32, ..4], ..4],
-
-  #[name= "u_Proj"]
-  proj: [[f32, ..4], ..4],
+fn run_command(options: &Options) -> Result<(), CliError> {
+  // A recording carries its own scenario
+  match options.command {
+    Command::Replay => return run_replay(options),
+    _ => (),
+  }
 
-  #[name= "t_Color"]
-  color: gfx::shade::TextureParam,
+  let scenario = try!(load_scenario(options));
+  return match options.command {
+    Command::Run => {
+      let (field, runner) = try!(start_field(options, &scenario));
+      let trajectory = try!(open_trajectory(options));
+      let metrics = try!(open_metrics(options));
+      run_windowed(options, field, runner, trajectory, metrics, None, None)
+    },
+    Command::Headless => run_headless(options, &scenario),
+    Command::Record => run_record(options, &scenario),
+    Command::Replay => unreachable!(),
+    Command::Bench => run_bench(options, &scenario),
+    Command::Sweep => run_sweep_command(options, &scenario),
+    Command::Optimize => run_optimize(options, &scenario),
+  };
+}
 
-  #[name = "world_light_pos"]
-  light_pos: [f32, ..3],
+// The scenario's initial state, or wherever --resume left off. A resumed run keeps the
+// snapshot's step unless --tick-rate says otherwise.
+fn start_field(options: &Options, scenario: &Scenario) -> Result<(EntityField, FixedStepRunner), CliError> {
+  let path = match options.resume {
+    Some(ref path) => path,
+    None => return Ok((try!(build_field(options, scenario)), FixedStepRunner::with_rate(scenario.tick_rate))),
+  };
+  let snapshot = match Snapshot::load(path) {
+    Ok(snapshot) => snapshot,
+    Err(err) => return Err(CliError::Snapshot(path.clone(), err)),
+  };
+  let field = match snapshot.restore() {
+    Ok(field) => field,
+    Err(err) => return Err(CliError::Snapshot(path.clone(), err)),
+  };
+  let runner = match options.tick_rate {
+    Some(rate) => FixedStepRunner::with_rate(rate),
+    None => FixedStepRunner::with_step(snapshot.step),
+  };
+  return Ok((field, runner));
 }
 
-// --------- Main -----------
-
-fn generate_model() -> Vec<Vertex> {
-  vec![
-      Vertex { pos: [-1.0, -1.0,  1.0], normal: [0.0, 0.0, 1.0], uv: [0.0, 0.0]},
-      Vertex { pos: [ 1.0, -1.0,  1.0], normal: [0.0, 0.0, 1.0], uv: [0.0, 1.0]},
-      Vertex { pos: [ 1.0,  1.0,  1.0], normal: [0.0, 0.0, 1.0], uv: [1.0, 0.0]},
-      Vertex { pos: [-1.0,  1.0,  1.0], normal: [0.0, 0.0, 1.0], uv: [1.0, 1.0]},
-
-      // bottom (0, 0, -1)
-      Vertex { pos: [-1.0,  1.0, -1.0], normal: [0.0, 0.0, -1.0], uv: [0.0, 0.0]},
-      Vertex { pos: [ 1.0,  1.0, -1.0], normal: [0.0, 0.0, -1.0], uv: [0.0, 1.0]},
-      Vertex { pos: [ 1.0, -1.0, -1.0], normal: [0.0, 0.0, -1.0], uv: [1.0, 0.0]},
-      Vertex { pos: [-1.0, -1.0, -1.0], normal: [0.0, 0.0, -1.0], uv: [1.0, 1.0]},
-      // right (1, 0, 0)
-      Vertex { pos: [ 1.0, -1.0, -1.0], normal: [1.0, 0.0, 0.0], uv: [0.0, 0.0]},
-      Vertex { pos: [ 1.0,  1.0, -1.0], normal: [1.0, 0.0, 0.0], uv: [0.0, 1.0]},
-      Vertex { pos: [ 1.0,  1.0,  1.0], normal: [1.0, 0.0, 0.0], uv: [1.0, 0.0]},
-      Vertex { pos: [ 1.0, -1.0,  1.0], normal: [1.0, 0.0, 0.0], uv: [1.0, 1.0]},
-      // left (-1, 0, 0)
-      Vertex { pos: [-1.0, -1.0,  1.0], normal: [-1.0, 0.0, 0.0], uv: [0.0, 0.0]},
-      Vertex { pos: [-1.0,  1.0,  1.0], normal: [-1.0, 0.0, 0.0], uv: [0.0, 1.0]},
-      Vertex { pos: [-1.0,  1.0, -1.0], normal: [-1.0, 0.0, 0.0], uv: [1.0, 0.0]},
-      Vertex { pos: [-1.0, -1.0, -1.0], normal: [-1.0, 0.0, 0.0], uv: [1.0, 1.0]},
-      // front (0, 1, 0)
-      Vertex { pos: [ 1.0,  1.0, -1.0], normal: [0.0, 1.0, 0.0], uv: [0.0, 0.0]},
-      Vertex { pos: [-1.0,  1.0, -1.0], normal: [0.0, 1.0, 0.0], uv: [0.0, 1.0]},
-      Vertex { pos: [-1.0,  1.0,  1.0], normal: [0.0, 1.0, 0.0], uv: [1.0, 0.0]},
-      Vertex { pos: [ 1.0,  1.0,  1.0], normal: [0.0, 1.0, 0.0], uv: [1.0, 1.0]},
-      // back (0, -1, 0)
-      Vertex { pos: [ 1.0, -1.0,  1.0], normal: [0.0, -1.0, 0.0], uv: [0.0, 0.0]},
-      Vertex { pos: [-1.0, -1.0,  1.0], normal: [0.0, -1.0, 0.0], uv: [0.0, 1.0]},
-      Vertex { pos: [-1.0, -1.0, -1.0], normal: [0.0, -1.0, 0.0], uv: [1.0, 0.0]},
-      Vertex { pos: [ 1.0, -1.0, -1.0], normal: [0.0, -1.0, 0.0], uv: [1.0, 1.0]},
-  ]
+// Metrics are worked out every --sample-every ticks while something wants them. Only the
+// HUD needs a history, --metrics is written out as each sample is taken.
+fn configure_metrics(options: &Options, field: &mut EntityField, shown: bool) -> () {
+  field.metrics.enabled = shown || options.metrics.is_some();
+  field.metrics.every = options.sample_every;
+  field.metrics.capacity = if shown { DEFAULT_HISTORY } else { 1 };
 }
 
-fn main() {
-  println!("Tra-la-la");
-
-  let glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
-
-  glfw.window_hint(glfw::ContextVersion(3, 2));
-  glfw.window_hint(glfw::OpenglForwardCompat(true));
-  glfw.window_hint(glfw::OpenglProfile(glfw::OpenGlProfileHint::Core));
-
-  let (window, events) = glfw
-      .create_window(960, 1080, "Physics example", glfw::Windowed)
-      .expect("Failed to create GLFW window.");
-
-  window.make_current();
-  glfw.set_error_callback(glfw::FAIL_ON_ERRORS);
-  window.set_key_polling(true);
-
-  let (w, h) = window.get_framebuffer_size();
-  let frame = gfx::Frame::new(w as u16, h as u16);
-
-  let mut device = gfx::GlDevice::new(|s| window.get_proc_address(s));
-
-  let ent_data: Vec<Vertex> = generate_model();
-  let anchor_data: Vec<Vertex> = generate_model();
-
-  let ent_mesh = device.create_mesh(ent_data.as_slice());
-  let anchor_mesh = device.create_mesh(anchor_data.as_slice());
-
-  let plane = Plane::subdivide(512,512);
-  let mut plane_vertex_data: Vec<Vertex> = plane.shared_vertex_iter()
-      .map(|(x, y)| {
-        Vertex{
-          pos: [ x*100.0, y*100.0, 1.0],
-          normal: [0.0, 0.0, 1.0],
-          uv: [0.7, 0.7]
-        }
-      })
-      .collect();
-
-  let plane_index_data: Vec<u32> = plane.indexed_polygon_iter()
-      .triangulate()
-      .vertices()
-      .map(|i| i as u32)
-      .collect();
-
-  // Using direct instantiation instead of easy mode helpers, these are static though
-  let mut plane_idx_buffer = device.create_buffer::<u32>(plane_index_data.len(), device::BufferUsage::Static);
-  device.update_buffer(plane_idx_buffer, plane_index_data.as_slice(), 0u);
-  let plane_slice = plane_idx_buffer.to_slice(gfx::TriangleList);
-
-  // Dynamic vertices here
-  let mut plane_vert_buffer = device.create_buffer(plane_vertex_data.len(), device::BufferUsage::Stream);
-  device.update_buffer(plane_vert_buffer, plane_vertex_data.as_slice(), 0u);
-  let plane_mesh = render::mesh::Mesh::from_format(plane_vert_buffer, plane_vertex_data.len() as device::VertexCount);
-
-  let index_data: Vec<u32> = vec![
-       0,  1,  2,  2,  3,  0, // top
-       4,  5,  6,  6,  7,  4, // bottom
-       8,  9, 10, 10, 11,  8, // right
-       12, 13, 14, 14, 15, 12, // left
-       16, 17, 18, 18, 19, 16, // front
-       20, 21, 22, 22, 23, 20, // back
-  ];
-
-  let slice = device
-      .create_buffer_static::<u32>(index_data.as_slice())
-      .to_slice(gfx::TriangleList);
-
-  let texture_info = gfx::tex::TextureInfo {
-    width: 1,
-    height: 1,
-    depth: 1,
-    levels: 1,
-    kind: gfx::tex::Texture2D,
-    format: gfx::tex::RGBA8,
+// --metrics, ready for after_tick
+fn open_metrics(options: &Options) -> Result<Option<MetricsFile>, CliError> {
+  return match options.metrics {
+    Some(ref path) => match MetricsWriter::create(path) {
+      Ok(writer) => Ok(Some(writer)),
+      Err(err) => Err(CliError::Io(path.clone(), err)),
+    },
+    None => Ok(None),
   };
+}
 
-  let image_info = texture_info.to_image_info();
-  let texture = device.create_texture(texture_info).unwrap();
-  device.update_texture(&texture, &image_info, &[0x20u8, 0xA0u8, 0xC0u8, 0x00u8]).unwrap();
-
-  let sampler = device.create_sampler(
-    gfx::tex::SamplerInfo::new(gfx::tex::Bilinear, gfx::tex::Clamp)
-  );
-
-  let vertex_shader_text: Vec<u8> = File::open(&Path::new("vertex-shader.glsl"))
-      .read_to_end()
-      .unwrap();
-
-  let fragment_shader_text: Vec<u8> = File::open(&Path::new("fragment-shader.glsl"))
-      .read_to_end()
-      .unwrap();
+fn finish_metrics(options: &Options, metrics: Option<MetricsFile>) -> Result<(), CliError> {
+  return match (metrics, &options.metrics) {
+    (Some(writer), &Some(ref path)) => {
+      let rows = writer.rows();
+      match writer.finish() {
+        Ok(_) => {
+          let _ = writeln!(&mut io::stderr(), "Wrote {} metrics rows to {}", rows, path.display());
+          Ok(())
+        },
+        Err(err) => Err(CliError::Io(path.clone(), err)),
+      }
+    },
+    _ => Ok(()),
+  };
+}
 
-  let vertex_shader: gfx::ShaderSource = shaders! {
-    GLSL_150: vertex_shader_text.as_slice()
+// --trajectory, ready for after_tick
+fn open_trajectory(options: &Options) -> Result<Option<TrajectoryFile>, CliError> {
+  return match options.trajectory {
+    Some(ref path) => match TrajectoryWriter::create(path, options.sample_every, options.selection.clone()) {
+      Ok(writer) => Ok(Some(writer)),
+      Err(err) => Err(CliError::Io(path.clone(), err)),
+    },
+    None => Ok(None),
   };
+}
 
-  let fragment_shader: gfx::ShaderSource = shaders! {
-    GLSL_150: fragment_shader_text.as_slice()
+fn finish_trajectory(options: &Options, trajectory: Option<TrajectoryFile>) -> Result<(), CliError> {
+  return match (trajectory, &options.trajectory) {
+    (Some(writer), &Some(ref path)) => {
+      let rows = writer.rows();
+      match writer.finish() {
+        Ok(_) => {
+          let _ = writeln!(&mut io::stderr(), "Wrote {} trajectory rows to {}", rows, path.display());
+          Ok(())
+        },
+        Err(err) => Err(CliError::Io(path.clone(), err)),
+      }
+    },
+    _ => Ok(()),
   };
+}
 
-  let program = device.link_program(vertex_shader.clone(), fragment_shader.clone())
-                      .unwrap();
+// --config, or the default field, with any flags laid over the top
+fn load_scenario(options: &Options) -> Result<Scenario, CliError> {
+  let mut scenario = match options.config {
+    Some(ref path) => match Scenario::load(path) {
+      Ok(scenario) => scenario,
+      Err(err) => return Err(CliError::Scenario(path.clone(), err)),
+    },
+    None => Scenario::default(),
+  };
 
-  let state = gfx::DrawState::new().depth(gfx::state::LessEqual, true);
-  let mut graphics = gfx::Graphics::new(device);
+  match options.entities {
+    Some(count) => scenario.set_swarm_size(count),
+    None => (),
+  }
+  scenario.seed = options.seed.unwrap_or(scenario.seed);
+  scenario.duration = options.duration.unwrap_or(scenario.duration);
+  scenario.tick_rate = options.tick_rate.unwrap_or(scenario.tick_rate);
+  return Ok(scenario);
+}
 
-  let ent_batch: Entity = graphics.make_batch(&program, &ent_mesh, slice, &state).unwrap();
-  let anchor_batch: Entity = graphics.make_batch(&program, &anchor_mesh, slice, &state).unwrap();
-  let plane_batch: Entity = graphics.make_batch(&program, &plane_mesh, plane_slice, &state).unwrap();
+fn build_field(options: &Options, scenario: &Scenario) -> Result<EntityField, CliError> {
+  return scenario.build_field().map_err(|err| unbuildable(options, err));
+}
 
-  let aspect = w as f32 / h as f32;
-  let mut data = Params {
-      light_pos: Vector3::new(0.0, 0.0, -3.0).into_fixed(),
-      color: (texture, Some(sampler)),
-      model: Matrix4::identity().into_fixed(),
-      view: Matrix4::identity().into_fixed(),
-      proj: cgmath::perspective(cgmath::deg(60.0f32), aspect, 0.1, 1000.0).into_fixed(),
+// Blamed on --config when there is one, otherwise the flags made the default scenario unbuildable
+fn unbuildable(options: &Options, err: ScenarioError) -> CliError {
+  return match options.config {
+    Some(ref path) => CliError::Scenario(path.clone(), err),
+    None => CliError::Usage(format!("can't set up the default scenario: {}", err)),
   };
+}
 
-  let clear_data = gfx::ClearData {
-      color: [0.1, 0.1, 0.1, 1.0],
-      depth: 1.0,
-      stencil: 0,
-  };
+#[cfg(feature = "viewer")]
+fn run_windowed(options: &Options, field: EntityField, runner: FixedStepRunner, trajectory: Option<TrajectoryFile>,
+                metrics: Option<MetricsFile>, recorder: Option<Recorder>, record_to: Option<&Path>) -> Result<(), CliError> {
+  return viewer::run(options, field, runner, trajectory, metrics, recorder, record_to);
+}
 
-  let mut last_time = time::precise_time_ns();
-  let mut everything = EntityField::default();
-  let mut camera_setting = 0.0;
-  let mut range_setting = 16.0;
+#[cfg(not(feature = "viewer"))]
+fn run_windowed(_: &Options, _: EntityField, _: FixedStepRunner, _: Option<TrajectoryFile>,
+                _: Option<MetricsFile>, _: Option<Recorder>, _: Option<&Path>) -> Result<(), CliError> {
+  return Err(CliError::Unsupported("swarm-cli was built without the \"viewer\" feature, use headless instead".to_string()));
+}
 
-  let mut going_left = false;
-  let mut going_right = false;
-  let mut going_fore = false;
-  let mut going_back = false;
+// Runs for --duration and dumps the final swarm state to --output, or stdout, and to --snapshot if given
+fn run_headless(options: &Options, scenario: &Scenario) -> Result<(), CliError> {
+  let (mut field, mut runner) = try!(start_field(options, scenario));
+  let mut trajectory = try!(open_trajectory(options));
+  let mut metrics = try!(open_metrics(options));
+  configure_metrics(options, &mut field, false);
+  runner.run_ticks_with(&mut field, scenario.ticks(), |field| {
+    match trajectory {
+      Some(ref mut trajectory) => trajectory.after_tick(field),
+      None => (),
+    }
+    match metrics {
+      Some(ref mut metrics) => metrics.after_tick(field),
+      None => (),
+    }
+  });
+  try!(finish_trajectory(options, trajectory));
+  try!(finish_metrics(options, metrics));
+  // stderr, stdout may be carrying the CSV
+  let _ = writeln!(&mut io::stderr(), "Ran {} ticks ({}s simulated), seed {}", runner.ticks, runner.ticks as f32 * runner.step, field.rng.seed());
+
+  match options.snapshot {
+    Some(ref path) => match Snapshot::capture(&field, runner.step).save(path) {
+      Ok(()) => (),
+      Err(err) => return Err(CliError::Io(path.clone(), err)),
+    },
+    None => (),
+  }
 
-  let mut rng = rand::task_rng();
+  return match options.output {
+    Some(ref path) => {
+      let file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => return Err(CliError::Io(path.clone(), err)),
+      };
+      field.write_state_csv(&mut BufferedWriter::new(file)).map_err(|err| CliError::Io(path.clone(), err))
+    },
+    None => field.write_state_csv(&mut io::stdout()).map_err(|err| CliError::Io(Path::new("<stdout>"), err)),
+  };
+}
 
-  while !window.should_close() {
+// Windowed when we have the viewer, so there are inputs worth recording. Otherwise runs
+// headless for --duration, which is still a check that the tick is deterministic.
+fn run_record(options: &Options, scenario: &Scenario) -> Result<(), CliError> {
+  let path = options.output.clone().unwrap_or(Path::new("recording.json"));
+  if options.resume.is_some() {
+    return Err(CliError::Usage("a recording starts from its scenario, record can't --resume a snapshot".to_string()));
+  }
 
-    for vertex in plane_vertex_data.iter_mut() {
-      let height = everything.world.height_at(Vector2::new(vertex.pos[0], vertex.pos[1]));
-      vertex.pos = [vertex.pos[0], vertex.pos[1], height/20.0];
-    }
-    graphics.device.update_buffer(plane_vert_buffer, plane_vertex_data.as_slice(), 0u);
+  let (recorder, field) = try!(Recorder::start(scenario).map_err(|err| unbuildable(options, err)));
+  // The replay runs at the recorded rate, which JSON may have rounded
+  let runner = FixedStepRunner::with_rate(recorder.scenario().tick_rate);
+  let ticks = recorder.scenario().ticks();
+  let trajectory = try!(open_trajectory(options));
+  let metrics = try!(open_metrics(options));
+  if cfg!(feature = "viewer") {
+    return run_windowed(options, field, runner, trajectory, metrics, Some(recorder), Some(&path));
+  }
 
-    let current_time = time::precise_time_ns();
-    let delta_t = ((current_time - last_time) as f32) / 1_000_000_000.0 ;
-    last_time = current_time;
+  let mut recorder = recorder;
+  let mut field = field;
+  let mut runner = runner;
+  let mut trajectory = trajectory;
+  let mut metrics = metrics;
+  configure_metrics(options, &mut field, false);
+  runner.run_ticks_with(&mut field, ticks, |field| {
+    recorder.after_tick(field);
+    match trajectory {
+      Some(ref mut trajectory) => trajectory.after_tick(field),
+      None => (),
+    }
+    match metrics {
+      Some(ref mut metrics) => metrics.after_tick(field),
+      None => (),
+    }
+  });
+  try!(finish_trajectory(options, trajectory));
+  try!(finish_metrics(options, metrics));
+
+  let recording = recorder.finish();
+  match recording.write_file(&path) {
+    Ok(()) => println!("Recorded {} ticks to {}, seed {}", recording.ticks(), path.display(), scenario.seed),
+    Err(err) => return Err(CliError::Io(path, err)),
+  }
+  return Ok(());
+}
 
-    everything.tick(delta_t);
+fn run_replay(options: &Options) -> Result<(), CliError> {
+  let path = match options.input {
+    Some(ref path) => path.clone(),
+    None => return Err(CliError::Usage("replay needs a recording, pass it with --input".to_string())),
+  };
+  let recording = match Recording::load(&path) {
+    Ok(recording) => recording,
+    Err(err) => return Err(CliError::Replay(path, err)),
+  };
 
-    if going_left  {
-      camera_setting = camera_setting + (1.5 * delta_t)
-    }
+  println!("Replaying {} ticks, seed {}", recording.ticks(), recording.scenario.seed);
+  return match recording.replay() {
+    Ok(ticks) => {
+      println!("All {} ticks matched the recording", ticks);
+      Ok(())
+    },
+    Err(err) => Err(CliError::Replay(path, err)),
+  };
+}
 
-    if going_right  {
-      camera_setting = camera_setting - (1.5 * delta_t)
-    }
+// Wall clock cost of a tick, then how well each integrator holds the anchor spring's energy at this rate
+fn run_bench(options: &Options, scenario: &Scenario) -> Result<(), CliError> {
+  let (mut field, mut runner) = try!(start_field(options, scenario));
+  let ticks = scenario.ticks();
 
-    if going_fore  {
-      range_setting = range_setting - (15.0 * delta_t)
-    }
+  let start = time::precise_time_ns();
+  runner.run_ticks(&mut field, ticks);
+  let elapsed_ms = (time::precise_time_ns() - start) as f64 / 1.0e6;
 
-    if going_back  {
-      range_setting = range_setting + (15.0 * delta_t)
-    }
+  println!("{} entities, {} ticks in {:.1}ms, seed {}", field.swarm.len(), ticks, elapsed_ms, field.rng.seed());
+  if ticks > 0 {
+    println!("{:.3}ms per tick, {:.0} ticks per second", elapsed_ms / ticks as f64, ticks as f64 * 1000.0 / elapsed_ms);
+  }
 
+  println!("Energy drift over {} steps of {}s:", ticks, runner.step);
+  for integrator in Integrator::all().iter() {
+    println!("  {:<20} {}", integrator.name(), anchor_spring_energy_drift(integrator, runner.step, ticks));
+  }
+  return Ok(());
+}
 
-    glfw.poll_events();
-    for (_, event) in glfw::flush_messages(&events) {
-      match event {
-        glfw::KeyEvent(glfw::Key::Escape, _, glfw::Press, _) =>
-          window.set_should_close(true),
-        glfw::KeyEvent(glfw::Key::H, _, glfw::Press, _) =>
-          going_left = true,
-        glfw::KeyEvent(glfw::Key::L, _, glfw::Press, _) =>
-          going_right = true,
-        glfw::KeyEvent(glfw::Key::H, _, glfw::Release, _) =>
-          going_left = false,
-        glfw::KeyEvent(glfw::Key::L, _, glfw::Release, _) =>
-          going_right = false,
-        glfw::KeyEvent(glfw::Key::J, _, glfw::Press, _) =>
-          going_fore = true,
-        glfw::KeyEvent(glfw::Key::K, _, glfw::Press, _) =>
-          going_back = true,
-        glfw::KeyEvent(glfw::Key::J, _, glfw::Release, _) =>
-          going_fore = false,
-        glfw::KeyEvent(glfw::Key::K, _, glfw::Release, _) =>
-          going_back = false,
-        glfw::KeyEvent(glfw::Key::R, _, glfw::Press, _) => {
-          for entity in everything.swarm.iter_mut() {
-            let new_x = rng.gen_range(-10.0, 10.0);
-            let new_y = rng.gen_range(-10.0, 10.0);
-            entity.vel = Vector3::new(0.0, 0.0, 0.0);
-            entity.pos = Vector3::new(new_x, new_y, 0.0);
-          }
-        },
-        _ => {},
-      }
-    }
+// Every point headless in parallel, one results row each, skipping points a previous run finished
+fn run_sweep_command(options: &Options, scenario: &Scenario) -> Result<(), CliError> {
+  if options.ranges.is_empty() {
+    return Err(CliError::Usage("sweep needs at least one --param".to_string()));
+  }
+  let path = options.output.clone().unwrap_or(Path::new("sweep.csv"));
+  let mode = match options.samples {
+    Some(samples) => SweepMode::Random(samples, scenario.seed),
+    None => SweepMode::Grid,
+  };
+  let total = sweep_points(options.ranges.as_slice(), &mode).len();
+
+  let result = run_sweep(scenario, options.ranges.as_slice(), &mode, options.threads, &path, |finished, to_run| {
+    let _ = writeln!(&mut io::stderr(), "{}/{} done", finished, to_run);
+  });
+  return match result {
+    Ok(ran) => {
+      println!("Ran {} of {} points, {} were already done, results in {}", ran, total, total - ran, path.display());
+      Ok(())
+    },
+    Err(err) => Err(CliError::Sweep(path, err)),
+  };
+}
 
-    let x = std::num::FloatMath::sin(camera_setting);
-    let y = std::num::FloatMath::cos(camera_setting);
-
-    let view:AffineMatrix3<f32> = Transform::look_at(
-      &Point3::new(2.0*range_setting*x, 2.0*range_setting*y, range_setting),
-      &Point3::new(0.0, 0.0, 0.0),
-      &Vector3::unit_z(),
-    );
-    data.view = view.mat.into_fixed();
-
-    graphics.clear(clear_data, gfx::COLOR | gfx::DEPTH, &frame);
-    
-    // Draw plane
-    data.model = Matrix4::from_translation(&Vector3::new(0.0, 0.0, -10.0)).into_fixed();
-    graphics.draw(&plane_batch, &data, &frame);
-
-    // Draw anchor
-    data.model = Matrix4::from_translation(&Vector3::new(0.0, 0.0, 0.0)).into_fixed();
-    graphics.draw(&anchor_batch, &data, &frame);
-
-    // Draw entities
-    for entity in everything.swarm.iter() {
-      let ent_pos = entity.pos;
-      data.model = Matrix4::from_translation(&Vector3::new(ent_pos.x, ent_pos.y, ent_pos.z)).into_fixed();
-      graphics.draw(&ent_batch, &data, &frame);
-    }
+// Searches the --param ranges and writes the best point found as a scenario file
+fn run_optimize(options: &Options, scenario: &Scenario) -> Result<(), CliError> {
+  if options.ranges.is_empty() {
+    return Err(CliError::Usage("optimize needs at least one --param".to_string()));
+  }
+  if options.objective.needs_collisions() && !scenario.collisions_enabled {
+    return Err(CliError::Usage("that objective scores collisions, enable them in the --config scenario's collisions section".to_string()));
+  }
+  let path = options.output.clone().unwrap_or(Path::new("best.json"));
+  let settings = OptimizeSettings{
+    method: options.method.clone(),
+    objective: options.objective.clone(),
+    generations: options.generations,
+    population: options.population,
+    seed: scenario.seed,
+    threads: options.threads,
+  };
 
-    graphics.end_frame();
+  let best = optimize(scenario, options.ranges.as_slice(), &settings, |generation, best| {
+    let _ = writeln!(&mut io::stderr(), "generation {}: best score {} at {}", generation, best.score, best.values);
+  });
 
-    window.swap_buffers();
+  let names: Vec<String> = options.ranges.iter().map(|range| range.param.name()).collect();
+  for (name, value) in names.iter().zip(best.values.iter()) {
+    println!("{} = {}", name, value);
   }
+  println!("Score {}", best.score);
+  return match apply_point(scenario, options.ranges.as_slice(), best.values.as_slice()).write_file(&path) {
+    Ok(()) => {
+      println!("Wrote the best scenario to {}", path.display());
+      Ok(())
+    },
+    Err(err) => Err(CliError::Io(path, err)),
+  };
 }
@@ -0,0 +1,207 @@
+extern crate cgmath;
+
+use entity_field::EntityField;
+
+use cgmath::{Vector, Vector3, EuclideanVector};
+
+use std::cmp;
+use std::collections::RingBuf;
+use std::io::{BufferedWriter, File, IoError, IoResult};
+use std::num::Float;
+
+// Oldest samples are dropped past this, about 14 minutes at 120Hz. Only the HUD needs
+// this much, files get their rows from a MetricsWriter as they're sampled.
+pub static DEFAULT_HISTORY: uint = 100000;
+static CSV_HEADER: &'static str = "tick,time,polarization,angular_momentum,mean_nearest_distance,spread,kinetic_energy,potential_energy,collisions";
+
+// Collective state of the swarm after one tick
+#[deriving(Clone, Show)]
+pub struct SwarmMetrics {
+  pub tick: u64,
+  pub time: f32,
+  // Length of the mean heading, 1 when everyone moves the same way, near 0 when disordered
+  pub polarization: f32,
+  // z component of the total angular momentum about the first anchor, positive is counter clockwise
+  pub angular_momentum: f32,
+  // Taken from the neighbour index, so the nearest one is picked from start of tick positions
+  pub mean_nearest_distance: f32,
+  // RMS distance from the swarm's centre of mass
+  pub spread: f32,
+  pub kinetic_energy: f32,
+  // Stored in the anchor springs, summed over every anchor
+  pub potential_energy: f32,
+  pub collisions: uint,
+}
+
+impl SwarmMetrics {
+  pub fn measure(field: &EntityField) -> SwarmMetrics {
+    let zero = Vector3::new(0.0, 0.0, 0.0);
+    let count = field.swarm.len();
+    let mut heading_sum = zero;
+    let mut moving = 0u;
+    let mut mass_total = 0.0;
+    let mut weighted_pos = zero;
+    let mut kinetic_energy = 0.0;
+    let mut potential_energy = 0.0;
+    let mut angular_momentum = 0.0;
+    let pivot = match field.anchors.iter().next() {
+      Some(anchor) => anchor.pos,
+      None => zero,
+    };
+
+    for entity in field.swarm.iter() {
+      let speed = entity.vel.length();
+      if speed > 0.0 {
+        heading_sum = heading_sum.add_v(&entity.vel.div_s(speed));
+        moving = moving + 1;
+      }
+      mass_total = mass_total + entity.mass;
+      weighted_pos = weighted_pos.add_v(&entity.pos.mul_s(entity.mass));
+      kinetic_energy = kinetic_energy + 0.5 * entity.mass * speed * speed;
+      for anchor in field.anchors.iter() {
+        potential_energy = potential_energy + anchor.potential_at(entity.pos);
+      }
+      let offset = entity.pos.sub_v(&pivot);
+      angular_momentum = angular_momentum + entity.mass * (offset.x * entity.vel.y - offset.y * entity.vel.x);
+    }
+
+    let centre = if mass_total > 0.0 { weighted_pos.div_s(mass_total) } else { zero };
+    let mut spread_sq = 0.0;
+    let mut nearest_sum = 0.0;
+    let mut nearest_count = 0u;
+    for entity in field.swarm.iter() {
+      let offset = entity.pos.sub_v(&centre);
+      spread_sq = spread_sq + offset.dot(&offset);
+      match field.k_nearest(entity.id, 1).iter().next().and_then(|&id| field.swarm.get(id)) {
+        Some(nearest) => {
+          nearest_sum = nearest_sum + nearest.pos.sub_v(&entity.pos).length();
+          nearest_count = nearest_count + 1;
+        },
+        None => (),
+      }
+    }
+
+    return SwarmMetrics{
+      tick: field.ticks,
+      time: field.time,
+      polarization: if moving > 0 { heading_sum.length() / moving as f32 } else { 0.0 },
+      angular_momentum: angular_momentum,
+      mean_nearest_distance: if nearest_count > 0 { nearest_sum / nearest_count as f32 } else { 0.0 },
+      spread: if count > 0 { (spread_sq / count as f32).sqrt() } else { 0.0 },
+      kinetic_energy: kinetic_energy,
+      potential_energy: potential_energy,
+      collisions: field.collisions,
+    };
+  }
+}
+
+// Metrics over time, filled in by the field each tick while enabled
+pub struct MetricsLog {
+  pub enabled: bool,
+  // Ticks between samples
+  pub every: u64,
+  pub capacity: uint,
+  samples: RingBuf<SwarmMetrics>,
+}
+
+impl MetricsLog {
+  pub fn new() -> MetricsLog {
+    return MetricsLog{enabled: false, every: 1, capacity: DEFAULT_HISTORY, samples: RingBuf::new()};
+  }
+
+  pub fn wants(&self, tick: u64) -> bool {
+    return self.enabled && tick % cmp::max(self.every, 1) == 0;
+  }
+
+  pub fn record(&mut self, sample: SwarmMetrics) -> () {
+    // The capacity may have shrunk since the last sample
+    while !self.samples.is_empty() && self.samples.len() >= self.capacity {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(sample);
+  }
+
+  pub fn latest(&self) -> Option<&SwarmMetrics> {
+    return self.samples.back();
+  }
+
+  pub fn samples(&self) -> Vec<&SwarmMetrics> {
+    return self.samples.iter().collect();
+  }
+
+  pub fn clear(&mut self) -> () {
+    self.samples.clear();
+  }
+
+  pub fn write_csv<W: Writer>(&self, out: &mut W) -> IoResult<()> {
+    try!(writeln!(out, "{}", CSV_HEADER));
+    for sample in self.samples.iter() {
+      try!(write_csv_row(out, sample));
+    }
+    return Ok(());
+  }
+}
+
+fn write_csv_row<W: Writer>(out: &mut W, sample: &SwarmMetrics) -> IoResult<()> {
+  return writeln!(out, "{},{},{},{},{},{},{},{},{}", sample.tick, sample.time, sample.polarization, sample.angular_momentum,
+                  sample.mean_nearest_distance, sample.spread, sample.kinetic_energy, sample.potential_energy,
+                  sample.collisions);
+}
+
+// Appends each sample the field's MetricsLog takes to a CSV file as the run goes, so
+// nothing is lost to the log's cap. Call after_tick after every tick.
+pub struct MetricsWriter<W> {
+  out: W,
+  rows: u64,
+  // The first write that failed, after which nothing more is written
+  error: Option<IoError>,
+}
+
+pub type MetricsFile = MetricsWriter<BufferedWriter<File>>;
+
+impl MetricsWriter<BufferedWriter<File>> {
+  pub fn create(path: &Path) -> IoResult<MetricsFile> {
+    let file = try!(File::create(path));
+    return MetricsWriter::new(BufferedWriter::new(file));
+  }
+}
+
+impl<W: Writer> MetricsWriter<W> {
+  pub fn new(out: W) -> IoResult<MetricsWriter<W>> {
+    let mut writer = MetricsWriter{out: out, rows: 0, error: None};
+    try!(writeln!(&mut writer.out, "{}", CSV_HEADER));
+    return Ok(writer);
+  }
+
+  pub fn rows(&self) -> u64 {
+    return self.rows;
+  }
+
+  // Picks up the sample the field took this tick, if it took one
+  pub fn after_tick(&mut self, field: &EntityField) -> () {
+    if self.error.is_some() || !field.metrics.wants(field.ticks) {
+      return;
+    }
+    let result = match field.metrics.latest() {
+      Some(sample) if sample.tick == field.ticks => write_csv_row(&mut self.out, sample),
+      _ => return,
+    };
+    match result {
+      Ok(()) => self.rows = self.rows + 1,
+      Err(err) => self.error = Some(err),
+    }
+  }
+
+  pub fn finish(mut self) -> IoResult<W> {
+    if self.error.is_none() {
+      match self.out.flush() {
+        Ok(()) => (),
+        Err(err) => self.error = Some(err),
+      }
+    }
+    return match self.error {
+      Some(err) => Err(err),
+      None => Ok(self.out),
+    };
+  }
+}
@@ -0,0 +1,106 @@
+extern crate cgmath;
+
+use entity_store::{EntityId, EntityStore};
+use spatial_hash::SpatialHash;
+use swarm_ent::SwarmEnt;
+
+use cgmath::{Vector, Vector3, EuclideanVector};
+
+use std::cmp::Equal;
+use std::collections::HashMap;
+use std::num::Float;
+
+// "Who's near me" for the whole swarm, as of the last rebuild
+pub struct NeighbourIndex {
+  grid: SpatialHash,
+  ids: Vec<EntityId>,
+  positions: Vec<Vector3<f32>>,
+  slots: HashMap<EntityId, uint>,
+}
+
+impl NeighbourIndex {
+  pub fn new(cell_size: f32) -> NeighbourIndex {
+    return NeighbourIndex{grid: SpatialHash::new(cell_size), ids: Vec::new(), positions: Vec::new(), slots: HashMap::new()};
+  }
+
+  pub fn rebuild(&mut self, swarm: &EntityStore<SwarmEnt>) -> () {
+    self.ids = swarm.ids();
+    self.positions = swarm.iter().map(|entity| entity.pos).collect();
+    self.slots = self.ids.iter().enumerate().map(|(slot, &id)| (id, slot)).collect();
+
+    let cell_size = self.grid.cell_size();
+    self.grid.rebuild(cell_size, self.positions.as_slice());
+  }
+
+  // Drops a despawned entity straight away rather than at the next rebuild
+  pub fn forget(&mut self, id: EntityId) -> () {
+    self.slots.remove(&id);
+  }
+
+  fn live(&self, slot: uint) -> bool {
+    return self.slots.contains_key(&self.ids[slot]);
+  }
+
+  pub fn len(&self) -> uint {
+    return self.ids.len();
+  }
+
+  pub fn position_of(&self, id: EntityId) -> Option<Vector3<f32>> {
+    return self.slots.get(&id).map(|&slot| self.positions[slot]);
+  }
+
+  // Everything within `radius` of a point, nearest first
+  pub fn within(&self, pos: Vector3<f32>, radius: f32) -> Vec<EntityId> {
+    let mut found: Vec<(f32, uint)> = self.grid.query(pos, radius).into_iter()
+      .filter(|&slot| self.live(slot))
+      .map(|slot| (self.positions[slot].sub_v(&pos).length(), slot))
+      .filter(|&(dist, _)| dist <= radius)
+      .collect();
+    // The radius check already dropped NaN distances, this just keeps sort_by from ever panicking
+    found.sort_by(|&(first_dist, _), &(second_dist, _)| first_dist.partial_cmp(&second_dist).unwrap_or(Equal));
+    return found.into_iter().map(|(_, slot)| self.ids[slot]).collect();
+  }
+
+  // Unknown ids (despawned, or spawned since the last rebuild) have no neighbours
+  pub fn neighbors_within(&self, id: EntityId, radius: f32) -> Vec<EntityId> {
+    return match self.position_of(id) {
+      Some(pos) => self.within(pos, radius).into_iter().filter(|&other| other != id).collect(),
+      None => Vec::new(),
+    };
+  }
+
+  pub fn k_nearest(&self, id: EntityId, k: uint) -> Vec<EntityId> {
+    let pos = match self.position_of(id) {
+      Some(pos) => pos,
+      None => return Vec::new(),
+    };
+
+    // Grow the search until it holds k others. Anything outside the search radius is
+    // further away than everything inside, so the first k found are the k nearest.
+    let mut radius = self.grid.cell_size();
+    loop {
+      let cells_across = 2.0 * radius / self.grid.cell_size() + 1.0;
+      if cells_across * cells_across > self.len() as f32 {
+        // Walking the grid would cost more than just checking everyone
+        break;
+      }
+
+      let mut found = self.neighbors_within(id, radius);
+      if found.len() >= k {
+        found.truncate(k);
+        return found;
+      }
+      radius = radius * 2.0;
+    }
+
+    let mut everyone: Vec<(f32, uint)> = self.positions.iter().enumerate()
+      .filter(|&(slot, _)| self.ids[slot] != id && self.live(slot))
+      .map(|(slot, other_pos)| (other_pos.sub_v(&pos).length(), slot))
+      // An entity that blew up to NaN isn't near anyone
+      .filter(|&(dist, _)| !dist.is_nan())
+      .collect();
+    everyone.sort_by(|&(first_dist, _), &(second_dist, _)| first_dist.partial_cmp(&second_dist).unwrap_or(Equal));
+    everyone.truncate(k);
+    return everyone.into_iter().map(|(_, slot)| self.ids[slot]).collect();
+  }
+}
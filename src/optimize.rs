@@ -0,0 +1,369 @@
+use scenario::Scenario;
+use sim_rng::SimRng;
+use sweep::{ParamRange, RunSummary, SUMMARY_COLUMNS, apply_point, run_parallel};
+
+use std::cmp;
+use std::num::Float;
+use std::rand::Rng;
+use std::rand::distributions::{Normal, IndependentSample};
+
+// What a run is scored on, higher is better
+#[deriving(Clone)]
+pub enum Objective {
+  // Quickest to get every entity into a ring about the first anchor
+  RingTime,
+  // Fewest collisions over the whole run
+  FewestCollisions,
+  // Most aligned headings at the end
+  Polarization,
+  // Sum of summary columns times their weights, negative weights to minimise
+  Weighted(Vec<(String, f32)>),
+}
+
+impl Objective {
+  // ring-time, min-collisions, polarization, or COLUMN:WEIGHT,COLUMN:WEIGHT,..
+  pub fn parse(text: &str) -> Option<Objective> {
+    return match text {
+      "ring-time" => Some(Objective::RingTime),
+      "min-collisions" => Some(Objective::FewestCollisions),
+      "polarization" => Some(Objective::Polarization),
+      _ => {
+        let mut terms = Vec::new();
+        for term in text.split(',') {
+          let parts: Vec<&str> = term.split(':').collect();
+          match parts.as_slice() {
+            [column, weight] if SUMMARY_COLUMNS.iter().any(|&known| known == column.trim()) => match from_str(weight.trim()) {
+              Some(weight) => terms.push((column.trim().to_string(), weight)),
+              None => return None,
+            },
+            _ => return None,
+          }
+        }
+        Some(Objective::Weighted(terms))
+      },
+    };
+  }
+
+  // Collisions are only counted when they're resolved, without them every run scores the same
+  pub fn needs_collisions(&self) -> bool {
+    return match *self {
+      Objective::FewestCollisions => true,
+      Objective::Weighted(ref terms) => terms.iter().any(|&(ref column, _)| column.as_slice() == "total_collisions"),
+      _ => false,
+    };
+  }
+
+  // A run that never formed a ring counts as taking twice the run's length, so it ranks
+  // below every run that did
+  pub fn score(&self, summary: &RunSummary, duration: f32) -> f32 {
+    let ring_time = summary.ring_time.unwrap_or(2.0 * duration);
+    return match *self {
+      Objective::RingTime => -ring_time,
+      Objective::FewestCollisions => -(summary.total_collisions as f32),
+      Objective::Polarization => summary.metrics.polarization,
+      Objective::Weighted(ref terms) => terms.iter().fold(0.0, |total, &(ref column, weight)| {
+        let value = match column.as_slice() {
+          "ring_time" => ring_time,
+          name => summary.column(name).unwrap_or(0.0),
+        };
+        total + weight * value
+      }),
+    };
+  }
+}
+
+#[deriving(Clone)]
+pub enum Method {
+  CmaEs,
+  Genetic,
+}
+
+impl Method {
+  pub fn from_name(name: &str) -> Option<Method> {
+    return match name {
+      "cma-es" => Some(Method::CmaEs),
+      "genetic" => Some(Method::Genetic),
+      _ => None,
+    };
+  }
+}
+
+pub struct OptimizeSettings {
+  pub method: Method,
+  pub objective: Objective,
+  pub generations: uint,
+  // Candidates per generation, 0 picks a size to suit the number of parameters
+  pub population: uint,
+  // Drives every random choice the search makes, the simulations keep the scenario's own seed
+  pub seed: u64,
+  pub threads: uint,
+}
+
+#[deriving(Clone)]
+pub struct Candidate {
+  // One per range, in range order
+  pub values: Vec<f32>,
+  pub score: f32,
+}
+
+// Searches the ranges for the best scoring scenario, calling on_generation with each
+// generation's number and the best candidate so far
+pub fn optimize(base: &Scenario, ranges: &[ParamRange], settings: &OptimizeSettings,
+                on_generation: |uint, &Candidate|) -> Candidate {
+  let mut rng = SimRng::new(settings.seed);
+  return match settings.method {
+    Method::CmaEs => cma_es(base, ranges, settings, &mut rng, on_generation),
+    Method::Genetic => genetic(base, ranges, settings, &mut rng, on_generation),
+  };
+}
+
+// The search works in the unit cube, each axis spanning one range
+fn to_values(ranges: &[ParamRange], unit: &[f64]) -> Vec<f32> {
+  return ranges.iter().zip(unit.iter()).map(|(range, &x)| {
+    range.clamp(range.min + x.max(0.0).min(1.0) as f32 * (range.max - range.min))
+  }).collect();
+}
+
+// Scores a whole generation at once, spread across the worker threads
+fn evaluate_all(base: &Scenario, ranges: &[ParamRange], settings: &OptimizeSettings, units: &[Vec<f64>]) -> Vec<Candidate> {
+  let values: Vec<Vec<f32>> = units.iter().map(|unit| to_values(ranges, unit.as_slice())).collect();
+  let jobs = values.iter().enumerate().map(|(idx, values)| (idx, apply_point(base, ranges, values.as_slice()))).collect();
+  let mut scores = Vec::from_elem(units.len(), 0.0f32);
+  run_parallel(jobs, settings.threads, |idx, result| {
+    // A run that couldn't finish loses to everything that did
+    scores[idx] = match result {
+      Ok(summary) => settings.objective.score(&summary, base.duration),
+      Err(_) => Float::neg_infinity(),
+    };
+  });
+  return values.into_iter().zip(scores.into_iter()).map(|(values, score)| Candidate{values: values, score: score}).collect();
+}
+
+fn keep_best(best: &mut Option<Candidate>, candidates: &[Candidate]) -> () {
+  for candidate in candidates.iter() {
+    let better = match *best {
+      Some(ref current) => candidate.score > current.score,
+      None => true,
+    };
+    if better {
+      *best = Some(candidate.clone());
+    }
+  }
+}
+
+// --------- Genetic -----------
+
+static MUTATION_SIGMA: f64 = 0.1;
+
+fn genetic(base: &Scenario, ranges: &[ParamRange], settings: &OptimizeSettings, rng: &mut SimRng,
+           on_generation: |uint, &Candidate|) -> Candidate {
+  let dims = ranges.len();
+  let size = if settings.population > 1 { settings.population } else { cmp::max(8, 4 * dims) };
+  let mutation = Normal::new(0.0, MUTATION_SIGMA);
+  let mut population: Vec<Vec<f64>> = range(0, size).map(|_| range(0, dims).map(|_| rng.gen::<f64>()).collect()).collect();
+  let mut best: Option<Candidate> = None;
+
+  for generation in range(0, settings.generations) {
+    let scored = evaluate_all(base, ranges, settings, population.as_slice());
+    keep_best(&mut best, scored.as_slice());
+    on_generation(generation, best.as_ref().unwrap());
+
+    // The best of this generation carries over untouched
+    let mut fittest = 0u;
+    for idx in range(1, size) {
+      if scored[idx].score > scored[fittest].score {
+        fittest = idx;
+      }
+    }
+    let mut next = vec![population[fittest].clone()];
+    while next.len() < size {
+      let first = tournament(scored.as_slice(), rng);
+      let second = tournament(scored.as_slice(), rng);
+      let child: Vec<f64> = range(0, dims).map(|dim| {
+        // Blend the parents, then now and again nudge a gene
+        let mix = rng.gen::<f64>();
+        let mut gene = population[first][dim] + mix * (population[second][dim] - population[first][dim]);
+        if rng.gen::<f64>() < 1.0 / dims as f64 {
+          gene = gene + mutation.ind_sample(rng);
+        }
+        gene.max(0.0).min(1.0)
+      }).collect();
+      next.push(child);
+    }
+    population = next;
+  }
+  return best.unwrap_or(Candidate{values: to_values(ranges, Vec::from_elem(dims, 0.5).as_slice()), score: Float::neg_infinity()});
+}
+
+// Better of two picked at random
+fn tournament(scored: &[Candidate], rng: &mut SimRng) -> uint {
+  let first = rng.gen_range(0, scored.len());
+  let second = rng.gen_range(0, scored.len());
+  return if scored[first].score >= scored[second].score { first } else { second };
+}
+
+// --------- CMA-ES -----------
+// The standard (mu/mu_w, lambda) strategy with rank one and rank mu covariance updates,
+// starting from the middle of the ranges with a step of 0.3 of each range
+
+static INITIAL_SIGMA: f64 = 0.3;
+
+fn cma_es(base: &Scenario, ranges: &[ParamRange], settings: &OptimizeSettings, rng: &mut SimRng,
+          on_generation: |uint, &Candidate|) -> Candidate {
+  let n = ranges.len();
+  let nf = n as f64;
+  let lambda = if settings.population > 1 { settings.population } else { 4 + (3.0 * nf.ln()).floor() as uint };
+  let mu = lambda / 2;
+
+  let raw_weights: Vec<f64> = range(0, mu).map(|i| (mu as f64 + 0.5).ln() - ((i + 1) as f64).ln()).collect();
+  let weight_sum = raw_weights.iter().fold(0.0, |sum, &w| sum + w);
+  let weights: Vec<f64> = raw_weights.iter().map(|&w| w / weight_sum).collect();
+  let mueff = 1.0 / weights.iter().fold(0.0, |sum, &w| sum + w * w);
+
+  let cc = (4.0 + mueff / nf) / (nf + 4.0 + 2.0 * mueff / nf);
+  let cs = (mueff + 2.0) / (nf + mueff + 5.0);
+  let c1 = 2.0 / ((nf + 1.3) * (nf + 1.3) + mueff);
+  let cmu = (1.0 - c1).min(2.0 * (mueff - 2.0 + 1.0 / mueff) / ((nf + 2.0) * (nf + 2.0) + mueff));
+  let damps = 1.0 + 2.0 * (((mueff - 1.0) / (nf + 1.0)).sqrt() - 1.0).max(0.0) + cs;
+  let chi_n = nf.sqrt() * (1.0 - 1.0 / (4.0 * nf) + 1.0 / (21.0 * nf * nf));
+
+  let standard = Normal::new(0.0, 1.0);
+  let mut mean = Vec::from_elem(n, 0.5f64);
+  let mut sigma = INITIAL_SIGMA;
+  let mut cov = identity(n);
+  let mut pc = Vec::from_elem(n, 0.0f64);
+  let mut ps = Vec::from_elem(n, 0.0f64);
+  let mut best: Option<Candidate> = None;
+
+  for generation in range(0, settings.generations) {
+    let (eigenvalues, basis) = symmetric_eigen(&cov);
+    let scales: Vec<f64> = eigenvalues.iter().map(|&value| value.max(1e-20).sqrt()).collect();
+
+    // x = mean + sigma * B * D * z
+    let steps: Vec<Vec<f64>> = range(0, lambda).map(|_| {
+      let scaled: Vec<f64> = range(0, n).map(|i| scales[i] * standard.ind_sample(rng)).collect();
+      mat_vec(&basis, scaled.as_slice())
+    }).collect();
+    let samples: Vec<Vec<f64>> = steps.iter().map(|step| {
+      range(0, n).map(|i| mean[i] + sigma * step[i]).collect()
+    }).collect();
+
+    let scored = evaluate_all(base, ranges, settings, samples.as_slice());
+    keep_best(&mut best, scored.as_slice());
+    on_generation(generation, best.as_ref().unwrap());
+
+    let mut order: Vec<uint> = range(0, lambda).collect();
+    order.sort_by(|&a, &b| scored[b].score.partial_cmp(&scored[a].score).unwrap_or(cmp::Equal));
+
+    // New mean from the best mu, as a weighted step
+    let mut step_w = Vec::from_elem(n, 0.0f64);
+    for (rank, &idx) in order.iter().take(mu).enumerate() {
+      for i in range(0, n) {
+        step_w[i] = step_w[i] + weights[rank] * steps[idx][i];
+      }
+    }
+    for i in range(0, n) {
+      mean[i] = mean[i] + sigma * step_w[i];
+    }
+
+    // C^-1/2 * step_w = B * D^-1 * B^T * step_w
+    let rotated = mat_t_vec(&basis, step_w.as_slice());
+    let whitened: Vec<f64> = range(0, n).map(|i| rotated[i] / scales[i]).collect();
+    let inv_sqrt_step = mat_vec(&basis, whitened.as_slice());
+
+    let ps_scale = (cs * (2.0 - cs) * mueff).sqrt();
+    for i in range(0, n) {
+      ps[i] = (1.0 - cs) * ps[i] + ps_scale * inv_sqrt_step[i];
+    }
+    let ps_len = norm(ps.as_slice());
+    let stall = (1.0 - (1.0 - cs).powi(2 * (generation as i32 + 1))).sqrt();
+    let hsig = ps_len / stall / chi_n < 1.4 + 2.0 / (nf + 1.0);
+
+    let pc_scale = if hsig { (cc * (2.0 - cc) * mueff).sqrt() } else { 0.0 };
+    for i in range(0, n) {
+      pc[i] = (1.0 - cc) * pc[i] + pc_scale * step_w[i];
+    }
+
+    let correction = if hsig { 0.0 } else { cc * (2.0 - cc) };
+    for i in range(0, n) {
+      for j in range(0, n) {
+        let rank_mu = order.iter().take(mu).enumerate().fold(0.0, |sum, (rank, &idx)| {
+          sum + weights[rank] * steps[idx][i] * steps[idx][j]
+        });
+        cov[i][j] = (1.0 - c1 - cmu) * cov[i][j]
+          + c1 * (pc[i] * pc[j] + correction * cov[i][j])
+          + cmu * rank_mu;
+      }
+    }
+
+    sigma = sigma * ((cs / damps) * (ps_len / chi_n - 1.0)).exp();
+  }
+  return best.unwrap_or(Candidate{values: to_values(ranges, mean.as_slice()), score: Float::neg_infinity()});
+}
+
+fn identity(n: uint) -> Vec<Vec<f64>> {
+  return range(0, n).map(|i| range(0, n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+}
+
+fn mat_vec(matrix: &Vec<Vec<f64>>, vector: &[f64]) -> Vec<f64> {
+  return matrix.iter().map(|row| row.iter().zip(vector.iter()).fold(0.0, |sum, (&a, &b)| sum + a * b)).collect();
+}
+
+// Transpose times vector
+fn mat_t_vec(matrix: &Vec<Vec<f64>>, vector: &[f64]) -> Vec<f64> {
+  let n = vector.len();
+  return range(0, n).map(|j| range(0, n).fold(0.0, |sum, i| sum + matrix[i][j] * vector[i])).collect();
+}
+
+fn norm(vector: &[f64]) -> f64 {
+  return vector.iter().fold(0.0, |sum, &x| sum + x * x).sqrt();
+}
+
+// Cyclic Jacobi. Eigenvectors come back as the columns of the matrix. Plenty for the
+// handful of parameters we search over.
+fn symmetric_eigen(matrix: &Vec<Vec<f64>>) -> (Vec<f64>, Vec<Vec<f64>>) {
+  let n = matrix.len();
+  let mut a = matrix.clone();
+  let mut vectors = identity(n);
+
+  for _ in range(0u, 50) {
+    let off_diagonal = range(0, n).fold(0.0, |sum, i| {
+      range(0, n).fold(sum, |sum, j| if i == j { sum } else { sum + a[i][j] * a[i][j] })
+    });
+    if off_diagonal < 1e-22 {
+      break;
+    }
+
+    for p in range(0, n) {
+      for q in range(p + 1, n) {
+        if a[p][q].abs() < 1e-300 {
+          continue;
+        }
+        let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+        let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+        let c = 1.0 / (t * t + 1.0).sqrt();
+        let s = t * c;
+
+        for k in range(0, n) {
+          let akp = a[k][p];
+          let akq = a[k][q];
+          a[k][p] = c * akp - s * akq;
+          a[k][q] = s * akp + c * akq;
+        }
+        for k in range(0, n) {
+          let apk = a[p][k];
+          let aqk = a[q][k];
+          a[p][k] = c * apk - s * aqk;
+          a[q][k] = s * apk + c * aqk;
+        }
+        for k in range(0, n) {
+          let vkp = vectors[k][p];
+          let vkq = vectors[k][q];
+          vectors[k][p] = c * vkp - s * vkq;
+          vectors[k][q] = s * vkp + c * vkq;
+        }
+      }
+    }
+  }
+  return (range(0, n).map(|i| a[i][i]).collect(), vectors);
+}
@@ -0,0 +1,427 @@
+extern crate cgmath;
+extern crate serialize;
+
+use entity_field::EntityField;
+use entity_store::EntityId;
+use fixed_step::FixedStepRunner;
+use behaviour::default_machines;
+use scenario::{Scenario, ScenarioError, SpawnGroup, Section, read_spawn_group, read_u64, read_uint, read_str,
+               object_json, f32_json, vec3_json, field_error};
+
+use cgmath::Vector3;
+use serialize::json;
+use serialize::json::Json;
+
+use std::fmt;
+use std::io::{File, IoError, IoResult};
+use std::mem;
+use std::u64;
+
+// Something from outside the simulation that changed it between two ticks
+#[deriving(Clone)]
+pub enum Input {
+  // Half width of the square the swarm gets thrown into
+  Scatter(f32),
+  Spawn(SpawnGroup),
+  Despawn(EntityId),
+  SetForceWeight(String, f32),
+  // Anchor index and its new origin
+  MoveAnchor(uint, Vector3<f32>),
+  SetCollisions(bool),
+}
+
+impl Input {
+  pub fn apply(&self, field: &mut EntityField) -> () {
+    match *self {
+      Input::Scatter(half_width) => field.scatter(half_width),
+      // Recorded groups went through the same checks as scenario files when they were read
+      Input::Spawn(ref group) => { let _ = group.spawn_into(field); },
+      Input::Despawn(id) => { field.despawn(id); },
+      Input::SetForceWeight(ref name, weight) => { field.forces.set_weight(name.as_slice(), weight); },
+      Input::MoveAnchor(idx, origin) => {
+        if idx < field.anchors.len() {
+          field.anchors[idx].origin = origin;
+          let time = field.time;
+          field.anchors[idx].move_to_time(time);
+        }
+      },
+      Input::SetCollisions(enabled) => field.collisions_enabled = enabled,
+    }
+  }
+
+  pub fn to_json(&self) -> Json {
+    return match *self {
+      Input::Scatter(half_width) => {
+        object_json(vec![("type", Json::String("scatter".to_string())), ("half_width", f32_json(half_width))])
+      },
+      Input::Spawn(ref group) => {
+        object_json(vec![("type", Json::String("spawn".to_string())), ("group", group.to_json())])
+      },
+      Input::Despawn(id) => {
+        object_json(vec![("type", Json::String("despawn".to_string())),
+                         ("index", Json::U64(id.index as u64)),
+                         ("generation", Json::U64(id.generation as u64))])
+      },
+      Input::SetForceWeight(ref name, weight) => {
+        object_json(vec![("type", Json::String("set-force-weight".to_string())),
+                         ("force", Json::String(name.clone())),
+                         ("weight", f32_json(weight))])
+      },
+      Input::MoveAnchor(idx, origin) => {
+        object_json(vec![("type", Json::String("move-anchor".to_string())),
+                         ("anchor", Json::U64(idx as u64)),
+                         ("pos", vec3_json(origin))])
+      },
+      Input::SetCollisions(enabled) => {
+        object_json(vec![("type", Json::String("set-collisions".to_string())), ("enabled", Json::Boolean(enabled))])
+      },
+    };
+  }
+
+  pub fn from_json(input: &Section) -> Result<Input, ScenarioError> {
+    return match try!(input.kind()) {
+      "scatter" => {
+        try!(input.only(&["tick", "type", "half_width"]));
+        Ok(Input::Scatter(try!(input.f32("half_width"))))
+      },
+      "spawn" => {
+        try!(input.only(&["tick", "type", "group"]));
+        match try!(input.section("group")) {
+          Some(group) => Ok(Input::Spawn(try!(read_spawn_group(&group, default_machines().len())))),
+          None => Err(input.error("group", "missing")),
+        }
+      },
+      "despawn" => {
+        try!(input.only(&["tick", "type", "index", "generation"]));
+        Ok(Input::Despawn(EntityId{index: try!(input.uint_or("index", 0)), generation: try!(input.uint_or("generation", 0))}))
+      },
+      "set-force-weight" => {
+        try!(input.only(&["tick", "type", "force", "weight"]));
+        let name = match input.get("force") {
+          Some(value) => try!(read_str(value, input.path_to("force").as_slice())),
+          None => return Err(input.error("force", "missing")),
+        };
+        Ok(Input::SetForceWeight(name.to_string(), try!(input.f32("weight"))))
+      },
+      "move-anchor" => {
+        try!(input.only(&["tick", "type", "anchor", "pos"]));
+        let idx = match input.get("anchor") {
+          Some(value) => try!(read_uint(value, input.path_to("anchor").as_slice())),
+          None => return Err(input.error("anchor", "missing")),
+        };
+        Ok(Input::MoveAnchor(idx, try!(input.vec3("pos"))))
+      },
+      "set-collisions" => {
+        try!(input.only(&["tick", "type", "enabled"]));
+        Ok(Input::SetCollisions(try!(input.bool_or("enabled", true))))
+      },
+      other => Err(input.error("type", format!("unknown input '{}'", other).as_slice())),
+    };
+  }
+
+  // What this input will be once it has been written out and read back. Floats only
+  // survive JSON to about six digits, so that's the version a recording has to run on.
+  fn settled(&self) -> Result<Input, ScenarioError> {
+    let text = self.to_json().to_string();
+    let value = match json::from_str(text.as_slice()) {
+      Ok(value) => value,
+      Err(err) => return Err(ScenarioError::Syntax(err.to_string())),
+    };
+    return Input::from_json(&try!(Section::new(&value, "input".to_string())));
+  }
+}
+
+#[deriving(Clone)]
+pub struct RecordedInput {
+  // How many ticks had run when it happened
+  pub tick: u64,
+  pub input: Input,
+}
+
+// A run boiled down to what's needed to reproduce it exactly: the scenario (seed included)
+// the field was built from, every input and when it came, and a hash of the state after
+// each tick to check the rerun against
+pub struct Recording {
+  pub scenario: Scenario,
+  pub inputs: Vec<RecordedInput>,
+  // hashes[n] is the state after n ticks, hashes[0] the freshly built field
+  pub hashes: Vec<u64>,
+}
+
+pub enum ReplayError {
+  Io(IoError),
+  Format(ScenarioError),
+  // Tick, the hash the recording has for it, the hash the replay got
+  Diverged(u64, u64, u64),
+}
+
+impl fmt::Show for ReplayError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ReplayError::Io(ref err) => write!(f, "{}", err),
+      ReplayError::Format(ref err) => write!(f, "{}", err),
+      ReplayError::Diverged(0, expected, actual) => {
+        write!(f, "diverged before the first tick, the initial field hashes to {:016x} instead of {:016x}", actual, expected)
+      },
+      ReplayError::Diverged(tick, expected, actual) => {
+        write!(f, "diverged at tick {}, state hash {:016x} instead of {:016x}", tick, actual, expected)
+      },
+    }
+  }
+}
+
+impl Recording {
+  pub fn ticks(&self) -> u64 {
+    return if self.hashes.is_empty() { 0 } else { self.hashes.len() as u64 - 1 };
+  }
+
+  pub fn to_json(&self) -> Json {
+    let inputs = self.inputs.iter().map(|recorded| {
+      match recorded.input.to_json() {
+        Json::Object(mut fields) => {
+          fields.insert("tick".to_string(), Json::U64(recorded.tick));
+          Json::Object(fields)
+        },
+        other => other,
+      }
+    }).collect();
+    return object_json(vec![("scenario", self.scenario.to_json()),
+                            ("inputs", Json::Array(inputs)),
+                            ("hashes", Json::Array(self.hashes.iter().map(|&hash| Json::U64(hash)).collect()))]);
+  }
+
+  pub fn from_json(value: &Json) -> Result<Recording, ScenarioError> {
+    let root = try!(Section::new(value, "".to_string()));
+    try!(root.only(&["scenario", "inputs", "hashes"]));
+
+    let scenario = match root.get("scenario") {
+      Some(scenario) => try!(Scenario::from_json(scenario).map_err(|err| nest_error("scenario", err))),
+      None => return Err(root.error("scenario", "missing")),
+    };
+
+    let mut inputs = Vec::new();
+    for input in try!(root.list("inputs")).iter() {
+      let tick = match input.get("tick") {
+        Some(value) => try!(read_u64(value, input.path_to("tick").as_slice())),
+        None => return Err(input.error("tick", "missing")),
+      };
+      inputs.push(RecordedInput{tick: tick, input: try!(Input::from_json(input))});
+    }
+
+    let hashes = match root.get("hashes") {
+      Some(&Json::Array(ref items)) => {
+        try!(items.iter().enumerate().map(|(idx, item)| read_u64(item, format!("hashes[{}]", idx).as_slice())).collect())
+      },
+      Some(_) => return Err(root.error("hashes", "expected a list of whole numbers")),
+      None => return Err(root.error("hashes", "missing")),
+    };
+
+    return Ok(Recording{scenario: scenario, inputs: inputs, hashes: hashes});
+  }
+
+  pub fn write_file(&self, path: &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+    return file.write_str(self.to_json().to_string().as_slice());
+  }
+
+  pub fn load(path: &Path) -> Result<Recording, ReplayError> {
+    let text = match File::open(path).read_to_string() {
+      Ok(text) => text,
+      Err(err) => return Err(ReplayError::Io(err)),
+    };
+    let value = match json::from_str(text.as_slice()) {
+      Ok(value) => value,
+      Err(err) => return Err(ReplayError::Format(ScenarioError::Syntax(err.to_string()))),
+    };
+    return Recording::from_json(&value).map_err(|err| ReplayError::Format(err));
+  }
+
+  // Reruns the whole recording, checking every tick. Ok is the number of ticks replayed.
+  pub fn replay(&self) -> Result<u64, ReplayError> {
+    let mut field = try!(self.scenario.build_field().map_err(|err| ReplayError::Format(err)));
+    let mut runner = FixedStepRunner::with_rate(self.scenario.tick_rate);
+    let mut inputs = self.inputs.iter().peekable();
+
+    for (tick, &expected) in self.hashes.iter().enumerate() {
+      let tick = tick as u64;
+      let actual = state_hash(&field);
+      if actual != expected {
+        return Err(ReplayError::Diverged(tick, expected, actual));
+      }
+      if tick == self.ticks() {
+        break;
+      }
+
+      while inputs.peek().map_or(false, |recorded| recorded.tick == tick) {
+        inputs.next().unwrap().input.apply(&mut field);
+      }
+      runner.run_ticks(&mut field, 1);
+    }
+    return Ok(self.ticks());
+  }
+}
+
+fn nest_error(parent: &str, err: ScenarioError) -> ScenarioError {
+  return match err {
+    ScenarioError::Field(path, message) => field_error(format!("{}.{}", parent, path), message.as_slice()),
+    other => other,
+  };
+}
+
+// Builds a recording alongside a live run. Inputs have to go through here rather than
+// straight at the field, or the replay won't know about them.
+pub struct Recorder {
+  recording: Recording,
+}
+
+impl Recorder {
+  // The field to run comes back with the recorder, built from the scenario as the
+  // recording file will describe it. Run it at scenario().tick_rate, not the original's.
+  pub fn start(scenario: &Scenario) -> Result<(Recorder, EntityField), ScenarioError> {
+    let text = scenario.to_json().to_string();
+    let scenario = try!(Scenario::from_json_str(text.as_slice()));
+    let field = try!(scenario.build_field());
+    let hashes = vec![state_hash(&field)];
+    return Ok((Recorder{recording: Recording{scenario: scenario, inputs: Vec::new(), hashes: hashes}}, field));
+  }
+
+  pub fn ticks(&self) -> u64 {
+    return self.recording.ticks();
+  }
+
+  // The scenario as the recording has it, after the round trip through JSON
+  pub fn scenario(&self) -> &Scenario {
+    return &self.recording.scenario;
+  }
+
+  // An input that can't be written out is neither applied nor recorded
+  pub fn apply(&mut self, field: &mut EntityField, input: Input) -> Result<(), ScenarioError> {
+    let input = try!(input.settled());
+    input.apply(field);
+    let tick = self.ticks();
+    self.recording.inputs.push(RecordedInput{tick: tick, input: input});
+    return Ok(());
+  }
+
+  // Call after every tick, FixedStepRunner::advance_with and run_ticks_with take this
+  pub fn after_tick(&mut self, field: &EntityField) -> () {
+    self.recording.hashes.push(state_hash(field));
+  }
+
+  pub fn finish(self) -> Recording {
+    return self.recording;
+  }
+}
+
+// FNV-1a over the exact bits of everything that carries from one tick to the next
+pub fn state_hash(field: &EntityField) -> u64 {
+  let mut hash = StateHasher::new();
+  hash.write_f32(field.time);
+  let rng_state = field.rng.state();
+  for word in rng_state.iter() {
+    hash.write_u32(*word);
+  }
+  for anchor in field.anchors.iter() {
+    hash.write_vec3(anchor.pos);
+  }
+  for entity in field.swarm.iter() {
+    hash.write_u64(entity.id.index as u64);
+    hash.write_u64(entity.id.generation as u64);
+    hash.write_vec3(entity.pos);
+    hash.write_vec3(entity.vel);
+    hash.write_f32(entity.heading);
+    hash.write_f32(entity.angular_vel);
+    match entity.behaviour {
+      Some(status) => {
+        hash.write_u64(status.state.index() as u64);
+        hash.write_f32(status.time_in_state);
+      },
+      None => hash.write_u64(u64::MAX),
+    }
+  }
+  return hash.finish();
+}
+
+struct StateHasher {
+  hash: u64,
+}
+
+impl StateHasher {
+  fn new() -> StateHasher {
+    return StateHasher{hash: 0xcbf29ce484222325};
+  }
+
+  fn write_byte(&mut self, byte: u8) -> () {
+    self.hash = (self.hash ^ byte as u64) * 0x100000001b3;
+  }
+
+  fn write_u32(&mut self, value: u32) -> () {
+    for shift in range(0u, 4) {
+      self.write_byte((value >> (shift * 8)) as u8);
+    }
+  }
+
+  fn write_u64(&mut self, value: u64) -> () {
+    self.write_u32(value as u32);
+    self.write_u32((value >> 32) as u32);
+  }
+
+  fn write_f32(&mut self, value: f32) -> () {
+    let bits: u32 = unsafe { mem::transmute(value) };
+    self.write_u32(bits);
+  }
+
+  fn write_vec3(&mut self, value: Vector3<f32>) -> () {
+    self.write_f32(value.x);
+    self.write_f32(value.y);
+    self.write_f32(value.z);
+  }
+
+  fn finish(&self) -> u64 {
+    return self.hash;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Input, Recorder, Recording, ReplayError};
+  use fixed_step::FixedStepRunner;
+  use scenario::Scenario;
+
+  static TICKS: u64 = 6;
+
+  // A few ticks of a small swarm, with inputs partway through
+  fn record() -> Recording {
+    let mut scenario = Scenario::with_swarm_size(5);
+    scenario.duration = 1.0;
+    let (mut recorder, mut field) = Recorder::start(&scenario).ok().unwrap();
+    let mut runner = FixedStepRunner::with_rate(recorder.scenario().tick_rate);
+    for tick in range(0, TICKS) {
+      match tick {
+        2 => recorder.apply(&mut field, Input::SetForceWeight("boids".to_string(), 0.5)).ok().unwrap(),
+        4 => recorder.apply(&mut field, Input::Scatter(5.0)).ok().unwrap(),
+        _ => (),
+      }
+      runner.run_ticks_with(&mut field, 1, |field| recorder.after_tick(field));
+    }
+    return recorder.finish();
+  }
+
+  #[test]
+  fn replay_reproduces_a_recording() {
+    let recording = record();
+    assert_eq!(recording.inputs.len(), 2);
+    assert_eq!(recording.replay().ok(), Some(TICKS));
+  }
+
+  #[test]
+  fn replay_reports_the_tick_that_diverged() {
+    let mut recording = record();
+    recording.hashes[3] = recording.hashes[3] ^ 1;
+    let diverged_at = match recording.replay() {
+      Err(ReplayError::Diverged(tick, _, _)) => Some(tick),
+      _ => None,
+    };
+    assert_eq!(diverged_at, Some(3));
+  }
+}
@@ -0,0 +1,922 @@
+extern crate cgmath;
+extern crate serialize;
+extern crate toml;
+
+use anchor_ent::{AnchorEnt, AnchorMotion, ANCHOR_FIELD_LEN, ANCHOR_FIELD_STR};
+use behaviour::{BehaviourStatus, default_machines};
+use entity_store::EntityId;
+use entity_field::{EntityField, SWARM_FIELD_STR, DEFAULT_RESTITUTION, DEFAULT_FRICTION};
+use force::{ForceRegistry, GravityForce, DragForce, WindForce};
+use integrator::Integrator;
+use interaction::{Interaction, InteractionMatrix};
+use kinematics::Kinematics;
+use spawn::{Distribution, EntitySpec, SpecError};
+use world_manifold::{KERNEL_SIGMA, WorldManifold};
+use sim_rng::{SimRng, DEFAULT_SEED};
+
+use cgmath::{Vector, Vector3};
+use serialize::json;
+use serialize::json::Json;
+
+use std::fmt;
+use std::io::{File, IoError, IoResult};
+use std::num::Float;
+
+pub static DEFAULT_TICK_RATE: f32 = 120.0;
+pub static DEFAULT_DURATION: f32 = 10.0;
+
+// A bulk spawn. Without a distribution the whole group starts at the spec's position.
+#[deriving(Clone)]
+pub struct SpawnGroup {
+  pub count: uint,
+  pub distribution: Option<Distribution>,
+  pub spec: EntitySpec,
+  // Index into the field's behaviours, if the group runs a state machine
+  pub behaviour: Option<uint>,
+}
+
+impl SpawnGroup {
+  pub fn spawn_into(&self, field: &mut EntityField) -> Result<Vec<EntityId>, SpecError> {
+    let mut spec = self.spec.clone();
+    spec.behaviour = self.behaviour.map(|idx| BehaviourStatus::start(idx, &field.behaviours[idx]));
+    return match self.distribution {
+      Some(ref distribution) => field.spawn_many(distribution, self.count, &spec),
+      None => range(0, self.count).map(|_| field.spawn(&spec)).collect(),
+    };
+  }
+
+  pub fn to_json(&self) -> Json {
+    let spec = &self.spec;
+    let mut fields = vec![("count", Json::U64(self.count as u64)),
+                          ("pos", vec3_json(spec.pos)),
+                          ("vel", vec3_json(spec.vel)),
+                          ("mass", f32_json(spec.mass)),
+                          ("radius", f32_json(spec.radius)),
+                          ("drag", f32_json(spec.drag)),
+                          ("group", Json::U64(spec.group as u64)),
+                          ("heading", f32_json(spec.heading)),
+                          ("kinematics", kinematics_json(spec.kinematics))];
+    // Infinite limits are the default, and JSON has no way to say infinity anyway
+    if spec.max_speed.is_finite() {
+      fields.push(("max_speed", f32_json(spec.max_speed)));
+    }
+    if spec.max_accel.is_finite() {
+      fields.push(("max_accel", f32_json(spec.max_accel)));
+    }
+    match self.behaviour {
+      Some(idx) => fields.push(("behaviour", Json::U64(idx as u64))),
+      None => (),
+    }
+    match self.distribution {
+      Some(ref distribution) => fields.push(("distribution", distribution_json(distribution))),
+      None => (),
+    }
+    return object_json(fields);
+  }
+}
+
+#[deriving(Clone)]
+pub enum ForceSetting {
+  // Reweights one of the forces every field starts with
+  Weight(String, f32),
+  // Weight, then the force's own parameters
+  Gravity(f32, Vector3<f32>),
+  Drag(f32, f32),
+  Wind(f32, Vector3<f32>, f32),
+}
+
+// Everything needed to set up and run one experiment
+#[deriving(Clone)]
+pub struct Scenario {
+  pub seed: u64,
+  pub deform_strength: int,
+  pub kernel_sigma: f32,
+  pub anchors: Vec<AnchorEnt>,
+  pub spawns: Vec<SpawnGroup>,
+  // Applied in order on top of the default registry
+  pub forces: Vec<ForceSetting>,
+  pub integrator: Integrator,
+  pub tick_rate: f32,
+  // Simulated seconds
+  pub duration: f32,
+  pub collisions_enabled: bool,
+  pub restitution: f32,
+  pub friction: f32,
+  // How each spawn group sees the others' deformations, see SwarmEnt::group
+  pub interactions: InteractionMatrix,
+}
+
+pub enum ScenarioError {
+  Io(IoError),
+  Syntax(String),
+  // Where in the file, e.g. spawn[2].distribution.radius, and what was wrong there
+  Field(String, String),
+}
+
+impl fmt::Show for ScenarioError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ScenarioError::Io(ref err) => write!(f, "{}", err),
+      ScenarioError::Syntax(ref message) => write!(f, "{}", message),
+      ScenarioError::Field(ref path, ref message) => write!(f, "{}: {}", path, message),
+    }
+  }
+}
+
+impl Scenario {
+  // Same field as EntityField::default
+  pub fn default() -> Scenario {
+    let mut scenario = Scenario::with_swarm_size(14);
+    scenario.spawns.insert(0, SpawnGroup{
+      count: 1,
+      distribution: None,
+      spec: EntitySpec::at(Vector3::new(0.0, 5.0, 0.0)),
+      behaviour: None,
+    });
+    return scenario;
+  }
+
+  pub fn with_swarm_size(count: uint) -> Scenario {
+    let mut scenario = Scenario{
+      seed: DEFAULT_SEED,
+      deform_strength: SWARM_FIELD_STR,
+      kernel_sigma: KERNEL_SIGMA,
+      anchors: vec![AnchorEnt::default()],
+      spawns: Vec::new(),
+      forces: Vec::new(),
+      integrator: Integrator::default(),
+      tick_rate: DEFAULT_TICK_RATE,
+      duration: DEFAULT_DURATION,
+      collisions_enabled: false,
+      restitution: DEFAULT_RESTITUTION,
+      friction: DEFAULT_FRICTION,
+      interactions: InteractionMatrix::new(1),
+    };
+    scenario.set_swarm_size(count);
+    return scenario;
+  }
+
+  // Swaps every spawn group for one uniform box, grown so the density stays about the same
+  pub fn set_swarm_size(&mut self, count: uint) -> () {
+    let half_width = (count as f32).sqrt().max(5.0);
+    self.spawns = vec![SpawnGroup{
+      count: count,
+      distribution: Some(Distribution::UniformBox(Vector3::new(-half_width, -half_width, 0.0),
+                                                  Vector3::new(half_width, half_width, 0.0))),
+      spec: EntitySpec::default(),
+      behaviour: None,
+    }];
+  }
+
+  pub fn ticks(&self) -> uint {
+    return (self.duration * self.tick_rate).round() as uint;
+  }
+
+  // A spawn that can't be placed, say a gaussian that scattered off the manifold, fails the build
+  pub fn build_field(&self) -> Result<EntityField, ScenarioError> {
+    let mut field = EntityField::empty();
+    // Before anything spawns, so the spawn positions follow from the seed too
+    field.rng = SimRng::new(self.seed);
+    field.anchors = self.anchors.clone();
+    field.deform_strength = self.deform_strength;
+    field.world.set_kernel_sigma(self.kernel_sigma);
+    field.integrator = self.integrator;
+    field.collisions_enabled = self.collisions_enabled;
+    field.restitution = self.restitution;
+    field.friction = self.friction;
+    field.interactions = self.interactions.clone();
+
+    for setting in self.forces.iter() {
+      match *setting {
+        ForceSetting::Weight(ref name, weight) => { field.forces.set_weight(name.as_slice(), weight); },
+        ForceSetting::Gravity(weight, accel) => field.forces.add("gravity", weight, box GravityForce{accel: accel}),
+        ForceSetting::Drag(weight, coefficient) => field.forces.add("drag", weight, box DragForce{coefficient: coefficient}),
+        ForceSetting::Wind(weight, velocity, coefficient) => {
+          field.forces.add("wind", weight, box WindForce{velocity: velocity, coefficient: coefficient})
+        },
+      }
+    }
+
+    for (idx, group) in self.spawns.iter().enumerate() {
+      match group.spawn_into(&mut field) {
+        Ok(_) => (),
+        Err(err) => return Err(field_error(format!("spawn[{}]", idx), format!("{}", err).as_slice())),
+      }
+    }
+    return Ok(field);
+  }
+
+  // Everything, defaults included, so the file still means the same thing if the defaults move
+  pub fn to_json(&self) -> Json {
+    // A list rather than a table, the same force can be set more than once and the last one wins
+    let forces = self.forces.iter().map(|setting| {
+      match *setting {
+        ForceSetting::Weight(ref name, weight) => {
+          object_json(vec![("name", Json::String(name.clone())), ("weight", f32_json(weight))])
+        },
+        ForceSetting::Gravity(weight, accel) => {
+          object_json(vec![("name", Json::String("gravity".to_string())), ("weight", f32_json(weight)), ("accel", vec3_json(accel))])
+        },
+        ForceSetting::Drag(weight, coefficient) => {
+          object_json(vec![("name", Json::String("drag".to_string())), ("weight", f32_json(weight)),
+                           ("coefficient", f32_json(coefficient))])
+        },
+        ForceSetting::Wind(weight, velocity, coefficient) => {
+          object_json(vec![("name", Json::String("wind".to_string())),
+                           ("weight", f32_json(weight)),
+                           ("velocity", vec3_json(velocity)),
+                           ("coefficient", f32_json(coefficient))])
+        },
+      }
+    }).collect();
+
+    return object_json(vec![
+      ("seed", Json::U64(self.seed)),
+      ("tick_rate", f32_json(self.tick_rate)),
+      ("duration", f32_json(self.duration)),
+      ("integrator", Json::String(self.integrator.name().to_string())),
+      ("manifold", object_json(vec![("deform_strength", Json::I64(self.deform_strength as i64)),
+                                    ("kernel_sigma", f32_json(self.kernel_sigma))])),
+      ("collisions", object_json(vec![("enabled", Json::Boolean(self.collisions_enabled)),
+                                      ("restitution", f32_json(self.restitution)),
+                                      ("friction", f32_json(self.friction))])),
+      ("anchors", Json::Array(self.anchors.iter().map(|anchor| anchor_json(anchor)).collect())),
+      ("spawn", Json::Array(self.spawns.iter().map(|group| group.to_json()).collect())),
+      ("forces", Json::Array(forces)),
+      ("interactions", interactions_json(&self.interactions)),
+    ]);
+  }
+
+  pub fn write_file(&self, path: &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+    return file.write_str(self.to_json().to_pretty_str().as_slice());
+  }
+
+  // .toml files are read as TOML, anything else as JSON
+  pub fn load(path: &Path) -> Result<Scenario, ScenarioError> {
+    let text = match File::open(path).read_to_string() {
+      Ok(text) => text,
+      Err(err) => return Err(ScenarioError::Io(err)),
+    };
+    return match path.extension_str() {
+      Some("toml") => Scenario::from_toml_str(text.as_slice()),
+      _ => Scenario::from_json_str(text.as_slice()),
+    };
+  }
+
+  pub fn from_json_str(text: &str) -> Result<Scenario, ScenarioError> {
+    return match json::from_str(text) {
+      Ok(value) => Scenario::from_json(&value),
+      Err(err) => Err(ScenarioError::Syntax(err.to_string())),
+    };
+  }
+
+  pub fn from_toml_str(text: &str) -> Result<Scenario, ScenarioError> {
+    let mut parser = toml::Parser::new(text);
+    return match parser.parse() {
+      Some(table) => Scenario::from_json(&toml_to_json(&toml::Value::Table(table))),
+      None => {
+        let err = &parser.errors[0];
+        let (line, column) = parser.to_linecol(err.lo);
+        Err(ScenarioError::Syntax(format!("line {}, column {}: {}", line + 1, column + 1, err.desc)))
+      },
+    };
+  }
+
+  // Anything left out keeps its value from Scenario::default
+  pub fn from_json(value: &Json) -> Result<Scenario, ScenarioError> {
+    let root = try!(Section::new(value, "".to_string()));
+    try!(root.only(&["seed", "manifold", "anchors", "spawn", "forces", "integrator", "tick_rate", "duration", "collisions",
+                     "interactions"]));
+
+    let mut scenario = Scenario::default();
+    match root.get("seed") {
+      Some(value) => scenario.seed = try!(read_u64(value, root.path_to("seed").as_slice())),
+      None => (),
+    }
+    scenario.tick_rate = try!(root.f32_or("tick_rate", scenario.tick_rate));
+    if !(scenario.tick_rate > 0.0) || !scenario.tick_rate.is_finite() {
+      return Err(root.error("tick_rate", "must be a finite number more than zero"));
+    }
+    scenario.duration = try!(root.f32_or("duration", scenario.duration));
+    if scenario.duration < 0.0 {
+      return Err(root.error("duration", "can't be negative"));
+    }
+
+    match root.get("integrator") {
+      Some(value) => {
+        let name = try!(read_str(value, root.path_to("integrator").as_slice()));
+        scenario.integrator = match Integrator::from_name(name) {
+          Some(integrator) => integrator,
+          None => {
+            let names: Vec<&str> = Integrator::all().iter().map(|integrator| integrator.name()).collect();
+            return Err(root.error("integrator", format!("unknown integrator '{}', expected one of {}", name, names.as_slice().connect(", ")).as_slice()));
+          },
+        };
+      },
+      None => (),
+    }
+
+    match try!(root.section("manifold")) {
+      Some(manifold) => {
+        try!(manifold.only(&["deform_strength", "kernel_sigma"]));
+        scenario.deform_strength = try!(manifold.int_or("deform_strength", scenario.deform_strength));
+        scenario.kernel_sigma = try!(manifold.f32_or("kernel_sigma", scenario.kernel_sigma));
+        if scenario.kernel_sigma <= 0.0 {
+          return Err(manifold.error("kernel_sigma", "must be more than zero"));
+        }
+      },
+      None => (),
+    }
+
+    match try!(root.section("collisions")) {
+      Some(collisions) => {
+        try!(collisions.only(&["enabled", "restitution", "friction"]));
+        scenario.collisions_enabled = try!(collisions.bool_or("enabled", scenario.collisions_enabled));
+        scenario.restitution = try!(collisions.f32_or("restitution", scenario.restitution));
+        if !(scenario.restitution >= 0.0 && scenario.restitution <= 1.0) {
+          return Err(collisions.error("restitution", "must be between 0 and 1"));
+        }
+        scenario.friction = try!(collisions.f32_or("friction", scenario.friction));
+        if !(scenario.friction >= 0.0 && scenario.friction <= 1.0) {
+          return Err(collisions.error("friction", "must be between 0 and 1"));
+        }
+      },
+      None => (),
+    }
+
+    if root.get("anchors").is_some() {
+      scenario.anchors = try!(try!(root.list("anchors")).iter().map(|anchor| read_anchor(anchor)).collect());
+    }
+    if root.get("spawn").is_some() {
+      let behaviour_count = default_machines().len();
+      scenario.spawns = try!(try!(root.list("spawn")).iter().map(|group| read_spawn_group(group, behaviour_count)).collect());
+    }
+    match root.get("forces") {
+      Some(&Json::Array(_)) => scenario.forces = try!(read_force_list(try!(root.list("forces")).as_slice())),
+      Some(_) => scenario.forces = try!(read_forces(&try!(root.section("forces")).unwrap())),
+      None => (),
+    }
+    match try!(root.section("interactions")) {
+      Some(interactions) => scenario.interactions = try!(read_interactions(&interactions)),
+      None => (),
+    }
+    return Ok(scenario);
+  }
+}
+
+pub fn read_anchor(anchor: &Section) -> Result<AnchorEnt, ScenarioError> {
+  try!(anchor.only(&["pos", "strength", "distance", "motion"]));
+  let pos = try!(anchor.vec3_or("pos", Vector3::new(0.0, 0.0, 0.0)));
+  let mut result = AnchorEnt::new(pos,
+                                  try!(anchor.f32_or("strength", ANCHOR_FIELD_STR)),
+                                  try!(anchor.f32_or("distance", ANCHOR_FIELD_LEN)));
+
+  let motion = match try!(anchor.section("motion")) {
+    Some(motion) => motion,
+    None => return Ok(result),
+  };
+  result.motion = match try!(motion.kind()) {
+    "fixed" => {
+      try!(motion.only(&["type"]));
+      AnchorMotion::Fixed
+    },
+    "orbit" => {
+      try!(motion.only(&["type", "center", "radius", "period"]));
+      AnchorMotion::Orbit(try!(motion.vec3_or("center", pos)), try!(motion.f32("radius")), try!(motion.period()))
+    },
+    "linear" => {
+      try!(motion.only(&["type", "velocity"]));
+      AnchorMotion::Linear(try!(motion.vec3("velocity")))
+    },
+    "oscillate" => {
+      try!(motion.only(&["type", "amplitude", "period"]));
+      AnchorMotion::Oscillate(try!(motion.vec3("amplitude")), try!(motion.period()))
+    },
+    other => return Err(motion.error("type", format!("unknown motion '{}', expected fixed, orbit, linear or oscillate", other).as_slice())),
+  };
+  // Start where the motion says, not wherever pos happened to be
+  result.move_to_time(0.0);
+  return Ok(result);
+}
+
+pub fn read_spawn_group(group: &Section, behaviour_count: uint) -> Result<SpawnGroup, ScenarioError> {
+  try!(group.only(&["count", "distribution", "pos", "vel", "mass", "radius", "drag", "max_speed", "max_accel",
+                    "group", "heading", "kinematics", "behaviour"]));
+  let defaults = EntitySpec::default();
+  let mut spec = EntitySpec::at(try!(group.vec3_or("pos", defaults.pos)));
+  spec.vel = try!(group.vec3_or("vel", defaults.vel));
+  spec.mass = try!(group.f32_or("mass", defaults.mass));
+  spec.radius = try!(group.f32_or("radius", defaults.radius));
+  match spec.validate() {
+    Err(SpecError::Mass(_)) => return Err(group.error("mass", "must be more than zero")),
+    Err(SpecError::Radius(_)) => return Err(group.error("radius", "must be more than zero")),
+    _ => (),
+  }
+  spec.drag = try!(non_negative(group, "drag", try!(group.f32_or("drag", defaults.drag))));
+  spec.max_speed = try!(non_negative(group, "max_speed", try!(group.f32_or("max_speed", defaults.max_speed))));
+  spec.max_accel = try!(non_negative(group, "max_accel", try!(group.f32_or("max_accel", defaults.max_accel))));
+  spec.group = try!(group.uint_or("group", defaults.group));
+  spec.heading = try!(group.f32_or("heading", defaults.heading));
+
+  match try!(group.section("kinematics")) {
+    Some(kinematics) => spec.kinematics = try!(read_kinematics(&kinematics)),
+    None => (),
+  }
+
+  let behaviour = match group.get("behaviour") {
+    Some(value) => {
+      let idx = try!(read_uint(value, group.path_to("behaviour").as_slice()));
+      if idx >= behaviour_count {
+        return Err(group.error("behaviour", format!("no behaviour machine {}, there are {}", idx, behaviour_count).as_slice()));
+      }
+      Some(idx)
+    },
+    None => None,
+  };
+
+  let count = try!(group.uint_or("count", 1));
+  let distribution = match try!(group.section("distribution")) {
+    Some(distribution) => Some(try!(read_distribution(&distribution, count))),
+    None => {
+      try!(check_on_manifold(group, "pos", spec.pos));
+      None
+    },
+  };
+
+  return Ok(SpawnGroup{count: count, distribution: distribution, spec: spec, behaviour: behaviour});
+}
+
+// Checks the whole area the group could land in is on the manifold, except a gaussian's
+// tails, which are only caught if a draw actually lands off it when the field is built.
+fn read_distribution(distribution: &Section, count: uint) -> Result<Distribution, ScenarioError> {
+  return match try!(distribution.kind()) {
+    "uniform-box" => {
+      try!(distribution.only(&["type", "min", "max"]));
+      let min = try!(distribution.vec3("min"));
+      let max = try!(distribution.vec3("max"));
+      try!(check_on_manifold(distribution, "min", min));
+      try!(check_on_manifold(distribution, "max", max));
+      Ok(Distribution::UniformBox(min, max))
+    },
+    "disk" => {
+      try!(distribution.only(&["type", "center", "radius"]));
+      let center = try!(distribution.vec3("center"));
+      let radius = try!(non_negative(distribution, "radius", try!(distribution.f32("radius"))));
+      try!(check_on_manifold(distribution, "center", center));
+      for &(dx, dy) in [(-radius, -radius), (radius, radius)].iter() {
+        try!(check_on_manifold(distribution, "radius", center.add_v(&Vector3::new(dx, dy, 0.0))));
+      }
+      Ok(Distribution::Disk(center, radius))
+    },
+    "gaussian" => {
+      try!(distribution.only(&["type", "center", "sigma"]));
+      let center = try!(distribution.vec3("center"));
+      let sigma = try!(distribution.f32("sigma"));
+      if !(sigma >= 0.0) || !sigma.is_finite() {
+        return Err(distribution.error("sigma", "must be a finite number, zero or more"));
+      }
+      try!(check_on_manifold(distribution, "center", center));
+      Ok(Distribution::GaussianCluster(center, sigma))
+    },
+    "grid" => {
+      try!(distribution.only(&["type", "corner", "spacing", "columns"]));
+      let corner = try!(distribution.vec3("corner"));
+      let spacing = try!(non_negative(distribution, "spacing", try!(distribution.f32("spacing"))));
+      let columns = try!(distribution.uint_or("columns", 1));
+      try!(check_on_manifold(distribution, "corner", corner));
+      if count > 0 {
+        // The far corner, the grid fills row by row so the last row may be short
+        let width = if columns == 0 { 1 } else { columns };
+        let used_columns = if count < width { count } else { width };
+        let rows = (count + width - 1) / width;
+        let far = corner.add_v(&Vector3::new((used_columns - 1) as f32 * spacing, (rows - 1) as f32 * spacing, 0.0));
+        try!(check_on_manifold(distribution, "spacing", far));
+      }
+      Ok(Distribution::Grid(corner, spacing, columns))
+    },
+    other => Err(distribution.error("type", format!("unknown distribution '{}', expected uniform-box, disk, gaussian or grid", other).as_slice())),
+  };
+}
+
+// Infinity is fine, it's how the limits say there isn't one
+fn non_negative(section: &Section, key: &str, value: f32) -> Result<f32, ScenarioError> {
+  if !(value >= 0.0) {
+    return Err(section.error(key, "can't be negative"));
+  }
+  return Ok(value);
+}
+
+fn check_on_manifold(section: &Section, key: &str, pos: Vector3<f32>) -> Result<(), ScenarioError> {
+  if !WorldManifold::in_bounds(pos) {
+    return Err(section.error(key, format!("puts entities at ({}, {}), off the manifold", pos.x, pos.y).as_slice()));
+  }
+  return Ok(());
+}
+
+pub fn read_kinematics(kinematics: &Section) -> Result<Kinematics, ScenarioError> {
+  return match try!(kinematics.kind()) {
+    "point-mass" => {
+      try!(kinematics.only(&["type"]));
+      Ok(Kinematics::PointMass)
+    },
+    "unicycle" => {
+      try!(kinematics.only(&["type", "max_turn_rate"]));
+      Ok(Kinematics::Unicycle(try!(kinematics.f32("max_turn_rate"))))
+    },
+    "differential-drive" => {
+      try!(kinematics.only(&["type", "wheel_base", "max_wheel_speed"]));
+      let wheel_base = try!(kinematics.f32("wheel_base"));
+      if wheel_base <= 0.0 {
+        return Err(kinematics.error("wheel_base", "must be more than zero"));
+      }
+      Ok(Kinematics::DifferentialDrive(wheel_base, try!(kinematics.f32("max_wheel_speed"))))
+    },
+    other => Err(kinematics.error("type", format!("unknown kinematics '{}', expected point-mass, unicycle or differential-drive", other).as_slice())),
+  };
+}
+
+// A groups x groups matrix, entries row by row with the observing group first
+pub fn read_interactions(interactions: &Section) -> Result<InteractionMatrix, ScenarioError> {
+  try!(interactions.only(&["groups", "entries"]));
+  let groups = try!(interactions.uint_or("groups", 1));
+  if groups == 0 {
+    return Err(interactions.error("groups", "must be at least one"));
+  }
+  let entries = try!(interactions.list("entries"));
+  if entries.len() != groups * groups {
+    return Err(interactions.error("entries", format!("expected {} entries for {} groups", groups * groups, groups).as_slice()));
+  }
+  let mut matrix = InteractionMatrix::new(groups);
+  for (idx, entry) in entries.iter().enumerate() {
+    matrix.set(idx / groups, idx % groups, try!(read_interaction(entry)));
+  }
+  return Ok(matrix);
+}
+
+fn read_interaction(entry: &Section) -> Result<Interaction, ScenarioError> {
+  try!(entry.only(&["type", "strength"]));
+  return match try!(entry.kind()) {
+    "attract" => Ok(Interaction::Attract(try!(entry.f32("strength")))),
+    "repel" => Ok(Interaction::Repel(try!(entry.f32("strength")))),
+    "ignore" => Ok(Interaction::Ignore),
+    "kernel" => Ok(Interaction::Kernel(try!(entry.f32("strength")))),
+    other => Err(entry.error("type", format!("unknown interaction '{}'", other).as_slice())),
+  };
+}
+
+// A table of force name to weight, or to a table for the forces that take parameters.
+// Keys are unordered, so each force can only be set once this way.
+fn read_forces(forces: &Section) -> Result<Vec<ForceSetting>, ScenarioError> {
+  let mut settings = Vec::new();
+  for (name, value) in forces.fields.iter() {
+    let path = forces.path_to(name.as_slice());
+    let setting = match *value {
+      Json::Object(_) => try!(read_force_params(name.as_slice(), &try!(Section::new(value, path)), false)),
+      _ => {
+        try!(check_force_name(name.as_slice(), path.as_slice()));
+        ForceSetting::Weight(name.clone(), try!(read_f32(value, path.as_slice())))
+      },
+    };
+    settings.push(setting);
+  }
+  return Ok(settings);
+}
+
+// The same settings as a list of tables, each naming its force. Applied in order, so a
+// later entry for a force wins over an earlier one.
+fn read_force_list(forces: &[Section]) -> Result<Vec<ForceSetting>, ScenarioError> {
+  let mut settings = Vec::new();
+  for force in forces.iter() {
+    let name = match force.get("name") {
+      Some(value) => try!(read_str(value, force.path_to("name").as_slice())),
+      None => return Err(force.error("name", "missing")),
+    };
+    let setting = match name {
+      "gravity" | "drag" | "wind" => try!(read_force_params(name, force, true)),
+      _ => {
+        try!(force.only(&["name", "weight"]));
+        try!(check_force_name(name, force.path_to("name").as_slice()));
+        ForceSetting::Weight(name.to_string(), try!(force.f32("weight")))
+      },
+    };
+    settings.push(setting);
+  }
+  return Ok(settings);
+}
+
+fn check_force_name(name: &str, path: &str) -> Result<(), ScenarioError> {
+  let registry = ForceRegistry::default();
+  if registry.weight(name).is_none() {
+    let names: Vec<&str> = registry.iter().map(|entry| entry.name.as_slice()).collect();
+    return Err(field_error(path.to_string(), format!("no such force, expected one of {}", names.as_slice().connect(", ")).as_slice()));
+  }
+  return Ok(());
+}
+
+// `named` is for list entries, which carry their force's name alongside the parameters
+fn read_force_params(name: &str, force: &Section, named: bool) -> Result<ForceSetting, ScenarioError> {
+  let mut known = vec!["weight"];
+  if named {
+    known.push("name");
+  }
+  let weight = try!(force.f32_or("weight", 1.0));
+  return match name {
+    "gravity" => {
+      known.push("accel");
+      try!(force.only(known.as_slice()));
+      Ok(ForceSetting::Gravity(weight, try!(force.vec3_or("accel", GravityForce::default().accel))))
+    },
+    "drag" => {
+      known.push("coefficient");
+      try!(force.only(known.as_slice()));
+      Ok(ForceSetting::Drag(weight, try!(force.f32("coefficient"))))
+    },
+    "wind" => {
+      known.push("velocity");
+      known.push("coefficient");
+      try!(force.only(known.as_slice()));
+      Ok(ForceSetting::Wind(weight, try!(force.vec3("velocity")), try!(force.f32("coefficient"))))
+    },
+    _ => Err(field_error(force.path.clone(), "only gravity, drag and wind take parameters, give other forces just a weight")),
+  };
+}
+
+// --------- Reading values -----------
+
+// A table within the file, remembering where it sits for error messages. Other files
+// stored as JSON (recordings, snapshots) read themselves through this too.
+pub struct Section<'a> {
+  pub path: String,
+  pub fields: &'a json::Object,
+}
+
+impl<'a> Section<'a> {
+  pub fn new(value: &'a Json, path: String) -> Result<Section<'a>, ScenarioError> {
+    return match *value {
+      Json::Object(ref fields) => Ok(Section{path: path, fields: fields}),
+      _ => Err(field_error(if path.is_empty() { "(top level)".to_string() } else { path }, "expected a table")),
+    };
+  }
+
+  pub fn path_to(&self, key: &str) -> String {
+    if self.path.is_empty() {
+      return key.to_string();
+    }
+    return format!("{}.{}", self.path, key);
+  }
+
+  pub fn error(&self, key: &str, message: &str) -> ScenarioError {
+    return field_error(self.path_to(key), message);
+  }
+
+  pub fn get(&self, key: &str) -> Option<&'a Json> {
+    return self.fields.get(&key.to_string());
+  }
+
+  // Typos would otherwise quietly fall back to defaults
+  pub fn only(&self, known: &[&str]) -> Result<(), ScenarioError> {
+    for key in self.fields.keys() {
+      if !known.contains(&key.as_slice()) {
+        return Err(self.error(key.as_slice(), format!("unknown field, expected one of {}", known.connect(", ")).as_slice()));
+      }
+    }
+    return Ok(());
+  }
+
+  pub fn section(&self, key: &str) -> Result<Option<Section<'a>>, ScenarioError> {
+    return match self.get(key) {
+      Some(value) => Section::new(value, self.path_to(key)).map(|section| Some(section)),
+      None => Ok(None),
+    };
+  }
+
+  pub fn list(&self, key: &str) -> Result<Vec<Section<'a>>, ScenarioError> {
+    return match self.get(key) {
+      Some(&Json::Array(ref items)) => {
+        items.iter().enumerate().map(|(idx, item)| Section::new(item, format!("{}[{}]", self.path_to(key), idx))).collect()
+      },
+      Some(_) => Err(self.error(key, "expected a list of tables")),
+      None => Ok(Vec::new()),
+    };
+  }
+
+  // The "type" tag picking which variant a table describes
+  pub fn kind(&self) -> Result<&'a str, ScenarioError> {
+    return match self.get("type") {
+      Some(value) => read_str(value, self.path_to("type").as_slice()),
+      None => Err(self.error("type", "missing")),
+    };
+  }
+
+  pub fn f32(&self, key: &str) -> Result<f32, ScenarioError> {
+    return match self.get(key) {
+      Some(value) => read_f32(value, self.path_to(key).as_slice()),
+      None => Err(self.error(key, "missing")),
+    };
+  }
+
+  pub fn f32_or(&self, key: &str, default: f32) -> Result<f32, ScenarioError> {
+    return match self.get(key) {
+      Some(value) => read_f32(value, self.path_to(key).as_slice()),
+      None => Ok(default),
+    };
+  }
+
+  pub fn period(&self) -> Result<f32, ScenarioError> {
+    let period = try!(self.f32("period"));
+    if period <= 0.0 {
+      return Err(self.error("period", "must be more than zero"));
+    }
+    return Ok(period);
+  }
+
+  pub fn int_or(&self, key: &str, default: int) -> Result<int, ScenarioError> {
+    return match self.get(key) {
+      Some(value) => read_int(value, self.path_to(key).as_slice()),
+      None => Ok(default),
+    };
+  }
+
+  pub fn uint_or(&self, key: &str, default: uint) -> Result<uint, ScenarioError> {
+    return match self.get(key) {
+      Some(value) => read_uint(value, self.path_to(key).as_slice()),
+      None => Ok(default),
+    };
+  }
+
+  pub fn bool_or(&self, key: &str, default: bool) -> Result<bool, ScenarioError> {
+    return match self.get(key) {
+      Some(&Json::Boolean(value)) => Ok(value),
+      Some(_) => Err(self.error(key, "expected true or false")),
+      None => Ok(default),
+    };
+  }
+
+  pub fn vec3(&self, key: &str) -> Result<Vector3<f32>, ScenarioError> {
+    return match self.get(key) {
+      Some(value) => read_vec3(value, self.path_to(key).as_slice()),
+      None => Err(self.error(key, "missing")),
+    };
+  }
+
+  pub fn vec3_or(&self, key: &str, default: Vector3<f32>) -> Result<Vector3<f32>, ScenarioError> {
+    return match self.get(key) {
+      Some(value) => read_vec3(value, self.path_to(key).as_slice()),
+      None => Ok(default),
+    };
+  }
+}
+
+pub fn field_error(path: String, message: &str) -> ScenarioError {
+  return ScenarioError::Field(path, message.to_string());
+}
+
+pub fn read_f32(value: &Json, path: &str) -> Result<f32, ScenarioError> {
+  return match *value {
+    Json::F64(number) => Ok(number as f32),
+    Json::I64(number) => Ok(number as f32),
+    Json::U64(number) => Ok(number as f32),
+    _ => Err(field_error(path.to_string(), "expected a number")),
+  };
+}
+
+pub fn read_int(value: &Json, path: &str) -> Result<int, ScenarioError> {
+  return match *value {
+    Json::I64(number) => Ok(number as int),
+    Json::U64(number) => Ok(number as int),
+    _ => Err(field_error(path.to_string(), "expected a whole number")),
+  };
+}
+
+pub fn read_uint(value: &Json, path: &str) -> Result<uint, ScenarioError> {
+  return match *value {
+    Json::I64(number) if number >= 0 => Ok(number as uint),
+    Json::U64(number) => Ok(number as uint),
+    _ => Err(field_error(path.to_string(), "expected a whole number, zero or more")),
+  };
+}
+
+pub fn read_u64(value: &Json, path: &str) -> Result<u64, ScenarioError> {
+  return match *value {
+    Json::I64(number) if number >= 0 => Ok(number as u64),
+    Json::U64(number) => Ok(number),
+    _ => Err(field_error(path.to_string(), "expected a whole number, zero or more")),
+  };
+}
+
+pub fn read_str<'a>(value: &'a Json, path: &str) -> Result<&'a str, ScenarioError> {
+  return match *value {
+    Json::String(ref text) => Ok(text.as_slice()),
+    _ => Err(field_error(path.to_string(), "expected a string")),
+  };
+}
+
+// [x, y, z]
+pub fn read_vec3(value: &Json, path: &str) -> Result<Vector3<f32>, ScenarioError> {
+  return match *value {
+    Json::Array(ref items) if items.len() == 3 => {
+      Ok(Vector3::new(try!(read_f32(&items[0], format!("{}[0]", path).as_slice())),
+                      try!(read_f32(&items[1], format!("{}[1]", path).as_slice())),
+                      try!(read_f32(&items[2], format!("{}[2]", path).as_slice()))))
+    },
+    _ => Err(field_error(path.to_string(), "expected [x, y, z]")),
+  };
+}
+
+// --------- Writing values -----------
+
+pub fn object_json(fields: Vec<(&str, Json)>) -> Json {
+  return Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect());
+}
+
+pub fn f32_json(value: f32) -> Json {
+  return Json::F64(value as f64);
+}
+
+pub fn vec3_json(value: Vector3<f32>) -> Json {
+  return Json::Array(vec![f32_json(value.x), f32_json(value.y), f32_json(value.z)]);
+}
+
+pub fn anchor_json(anchor: &AnchorEnt) -> Json {
+  let motion = match anchor.motion {
+    AnchorMotion::Fixed => vec![("type", Json::String("fixed".to_string()))],
+    AnchorMotion::Orbit(center, radius, period) => {
+      vec![("type", Json::String("orbit".to_string())),
+           ("center", vec3_json(center)),
+           ("radius", f32_json(radius)),
+           ("period", f32_json(period))]
+    },
+    AnchorMotion::Linear(velocity) => {
+      vec![("type", Json::String("linear".to_string())), ("velocity", vec3_json(velocity))]
+    },
+    AnchorMotion::Oscillate(amplitude, period) => {
+      vec![("type", Json::String("oscillate".to_string())), ("amplitude", vec3_json(amplitude)), ("period", f32_json(period))]
+    },
+  };
+  // The origin, where the file's pos came from
+  return object_json(vec![("pos", vec3_json(anchor.origin)),
+                          ("strength", f32_json(anchor.strength)),
+                          ("distance", f32_json(anchor.distance)),
+                          ("motion", object_json(motion))]);
+}
+
+fn distribution_json(distribution: &Distribution) -> Json {
+  return match *distribution {
+    Distribution::UniformBox(min, max) => {
+      object_json(vec![("type", Json::String("uniform-box".to_string())), ("min", vec3_json(min)), ("max", vec3_json(max))])
+    },
+    Distribution::Disk(center, radius) => {
+      object_json(vec![("type", Json::String("disk".to_string())), ("center", vec3_json(center)), ("radius", f32_json(radius))])
+    },
+    Distribution::GaussianCluster(center, sigma) => {
+      object_json(vec![("type", Json::String("gaussian".to_string())), ("center", vec3_json(center)), ("sigma", f32_json(sigma))])
+    },
+    Distribution::Grid(corner, spacing, columns) => {
+      object_json(vec![("type", Json::String("grid".to_string())),
+                       ("corner", vec3_json(corner)),
+                       ("spacing", f32_json(spacing)),
+                       ("columns", Json::U64(columns as u64))])
+    },
+  };
+}
+
+pub fn kinematics_json(kinematics: Kinematics) -> Json {
+  return match kinematics {
+    Kinematics::PointMass => object_json(vec![("type", Json::String("point-mass".to_string()))]),
+    Kinematics::Unicycle(max_turn_rate) => {
+      object_json(vec![("type", Json::String("unicycle".to_string())), ("max_turn_rate", f32_json(max_turn_rate))])
+    },
+    Kinematics::DifferentialDrive(wheel_base, max_wheel_speed) => {
+      object_json(vec![("type", Json::String("differential-drive".to_string())),
+                       ("wheel_base", f32_json(wheel_base)),
+                       ("max_wheel_speed", f32_json(max_wheel_speed))])
+    },
+  };
+}
+
+pub fn interactions_json(interactions: &InteractionMatrix) -> Json {
+  let groups = interactions.groups();
+  let entries: Vec<Json> = range(0, groups * groups).map(|idx| {
+    match interactions.get(idx / groups, idx % groups) {
+      Interaction::Attract(strength) => object_json(vec![("type", Json::String("attract".to_string())), ("strength", f32_json(strength))]),
+      Interaction::Repel(strength) => object_json(vec![("type", Json::String("repel".to_string())), ("strength", f32_json(strength))]),
+      Interaction::Ignore => object_json(vec![("type", Json::String("ignore".to_string()))]),
+      Interaction::Kernel(weight) => object_json(vec![("type", Json::String("kernel".to_string())), ("strength", f32_json(weight))]),
+    }
+  }).collect();
+  return object_json(vec![("groups", Json::U64(groups as u64)), ("entries", Json::Array(entries))]);
+}
+
+// Both formats are read through the same JSON tree
+fn toml_to_json(value: &toml::Value) -> Json {
+  return match *value {
+    toml::Value::String(ref text) => Json::String(text.clone()),
+    toml::Value::Integer(number) => Json::I64(number),
+    toml::Value::Float(number) => Json::F64(number),
+    toml::Value::Boolean(value) => Json::Boolean(value),
+    toml::Value::Datetime(ref text) => Json::String(text.clone()),
+    toml::Value::Array(ref items) => Json::Array(items.iter().map(|item| toml_to_json(item)).collect()),
+    toml::Value::Table(ref table) => Json::Object(table.iter().map(|(key, value)| (key.clone(), toml_to_json(value))).collect()),
+  };
+}
@@ -0,0 +1,54 @@
+use std::rand::Rng;
+
+pub static DEFAULT_SEED: u64 = 0x5EED;
+
+// xorshift128, owned by the field so a seed reproduces a whole run. Unlike the std
+// generators the state is out in the open, so it can be saved and restored mid run.
+#[deriving(Clone, PartialEq, Show)]
+pub struct SimRng {
+  seed: u64,
+  state: [u32, ..4],
+}
+
+impl SimRng {
+  pub fn new(seed: u64) -> SimRng {
+    // splitmix64 spreads similar seeds apart and never hands xorshift the all zero state
+    let mut mix = seed;
+    let mut next_word = || {
+      mix = mix + 0x9E3779B97F4A7C15;
+      let mut z = mix;
+      z = (z ^ (z >> 30)) * 0xBF58476D1CE4E5B9;
+      z = (z ^ (z >> 27)) * 0x94D049BB133111EB;
+      z ^ (z >> 31)
+    };
+    let low = next_word();
+    let high = next_word();
+    let state = [low as u32, (low >> 32) as u32, high as u32, (high >> 32) as u32 | 1];
+    return SimRng{seed: seed, state: state};
+  }
+
+  // Picks up exactly where a saved generator left off
+  pub fn from_state(seed: u64, state: [u32, ..4]) -> SimRng {
+    return SimRng{seed: seed, state: state};
+  }
+
+  // What this generator was originally started from, for logs and snapshots
+  pub fn seed(&self) -> u64 {
+    return self.seed;
+  }
+
+  pub fn state(&self) -> [u32, ..4] {
+    return self.state;
+  }
+}
+
+impl Rng for SimRng {
+  fn next_u32(&mut self) -> u32 {
+    let t = self.state[0] ^ (self.state[0] << 11);
+    self.state[0] = self.state[1];
+    self.state[1] = self.state[2];
+    self.state[2] = self.state[3];
+    self.state[3] = self.state[3] ^ (self.state[3] >> 19) ^ (t ^ (t >> 8));
+    return self.state[3];
+  }
+}
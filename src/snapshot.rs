@@ -0,0 +1,1004 @@
+extern crate cgmath;
+extern crate serialize;
+
+use anchor_ent::{AnchorEnt, AnchorMotion};
+use behaviour::{BehaviourState, BehaviourStatus};
+use entity_field::EntityField;
+use entity_store::{EntityId, EntityStore};
+use force::{ForceRegistry, builtin_force};
+use integrator::Integrator;
+use interaction::{Interaction, InteractionMatrix};
+use kinematics::Kinematics;
+use scenario::{ScenarioError, Section, anchor_json, kinematics_json, interactions_json, read_anchor, read_kinematics,
+               read_interactions, object_json, f32_json, vec3_json, read_f32, read_uint, read_u64, read_str, field_error};
+use sim_rng::SimRng;
+use swarm_ent::SwarmEnt;
+use world_manifold::{Deformation, WorldManifold};
+use zone_ent::{ZoneEnt, ZoneShape, ZoneKind};
+
+use cgmath::{Vector2, Vector3};
+use serialize::json;
+use serialize::json::Json;
+
+use std::fmt;
+use std::io::{BufferedReader, BufferedWriter, File, IoError, IoResult};
+use std::num::Float;
+
+static MAGIC: &'static [u8] = b"SWRMSNAP";
+static VERSION: u32 = 1;
+
+pub struct ForceState {
+  pub name: String,
+  pub weight: f32,
+  pub params: Vec<f32>,
+}
+
+// Everything that carries the field from one tick to the next. Derived state (neighbour
+// index, collision grid, telemetry) is rebuilt by the next tick and isn't kept.
+pub struct Snapshot {
+  // The fixed step the run was using, resuming on a different one won't match
+  pub step: f32,
+  pub seed: u64,
+  pub rng_state: [u32, ..4],
+  pub ticks: u64,
+  pub time: f32,
+  pub integrator: Integrator,
+  pub deform_strength: int,
+  pub kernel_sigma: f32,
+  // The manifold is exactly these, applied in order to a flat field
+  pub deformations: Vec<Deformation>,
+  pub collisions_enabled: bool,
+  pub restitution: f32,
+  pub friction: f32,
+  pub anchors: Vec<AnchorEnt>,
+  pub zones: Vec<ZoneEnt>,
+  pub interactions: InteractionMatrix,
+  // Only built in forces can be restored, by name
+  pub forces: Vec<ForceState>,
+  // Every store slot in order, its generation and whoever lives there, then the free list.
+  // Keeping these makes the restored store hand out the same ids the original would have.
+  pub slots: Vec<(uint, Option<SwarmEnt>)>,
+  pub free: Vec<uint>,
+}
+
+pub enum SnapshotError {
+  Io(IoError),
+  // Binary file that isn't a snapshot, or is from a newer version, or is cut short
+  Corrupt(String),
+  Json(ScenarioError),
+  // Readable, but can't be turned back into a field
+  Restore(String),
+}
+
+impl fmt::Show for SnapshotError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      SnapshotError::Io(ref err) => write!(f, "{}", err),
+      SnapshotError::Corrupt(ref message) => write!(f, "not a usable snapshot: {}", message),
+      SnapshotError::Json(ref err) => write!(f, "{}", err),
+      SnapshotError::Restore(ref message) => write!(f, "can't restore: {}", message),
+    }
+  }
+}
+
+impl Snapshot {
+  pub fn capture(field: &EntityField, step: f32) -> Snapshot {
+    return Snapshot{
+      step: step,
+      seed: field.rng.seed(),
+      rng_state: field.rng.state(),
+      ticks: field.ticks,
+      time: field.time,
+      integrator: field.integrator,
+      deform_strength: field.deform_strength,
+      kernel_sigma: field.world.kernel_sigma(),
+      deformations: field.world.deformations().to_vec(),
+      collisions_enabled: field.collisions_enabled,
+      restitution: field.restitution,
+      friction: field.friction,
+      anchors: field.anchors.clone(),
+      zones: field.zones.clone(),
+      interactions: field.interactions.clone(),
+      forces: field.forces.iter().map(|entry| {
+        ForceState{name: entry.name.clone(), weight: entry.weight, params: entry.force.params()}
+      }).collect(),
+      slots: field.swarm.slots().into_iter().map(|(generation, entity)| (generation, entity.map(|entity| *entity))).collect(),
+      free: field.swarm.free_list().to_vec(),
+    };
+  }
+
+  pub fn restore(&self) -> Result<EntityField, SnapshotError> {
+    let mut field = EntityField::empty();
+    field.rng = SimRng::from_state(self.seed, self.rng_state);
+    field.ticks = self.ticks;
+    field.time = self.time;
+    field.integrator = self.integrator;
+    field.deform_strength = self.deform_strength;
+    field.world.set_kernel_sigma(self.kernel_sigma);
+    field.collisions_enabled = self.collisions_enabled;
+    field.restitution = self.restitution;
+    field.friction = self.friction;
+    field.anchors = self.anchors.clone();
+    field.zones = self.zones.clone();
+    field.interactions = self.interactions.clone();
+
+    field.forces = ForceRegistry::new();
+    for state in self.forces.iter() {
+      let mut force = match builtin_force(state.name.as_slice()) {
+        Some(force) => force,
+        None => return Err(SnapshotError::Restore(format!("'{}' isn't a built in force", state.name))),
+      };
+      force.set_params(state.params.as_slice());
+      field.forces.add(state.name.as_slice(), state.weight, force);
+    }
+
+    for (index, &(generation, ref entity)) in self.slots.iter().enumerate() {
+      match *entity {
+        Some(entity) => {
+          if entity.id.index != index {
+            return Err(SnapshotError::Restore(format!("entity {} is in slot {}", entity.id, index)));
+          }
+          // Ticking either would index the manifold off its edge
+          if !finite(entity.pos) || !finite(entity.vel) {
+            return Err(SnapshotError::Restore(format!("entity {} has a position or velocity that isn't finite", entity.id)));
+          }
+          if !WorldManifold::in_bounds(entity.pos) {
+            return Err(SnapshotError::Restore(format!("entity {} is off the manifold at ({}, {})", entity.id, entity.pos.x, entity.pos.y)));
+          }
+          match entity.behaviour {
+            Some(status) if status.machine >= field.behaviours.len() => {
+              return Err(SnapshotError::Restore(format!("entity {} runs behaviour machine {}, there are only {}",
+                                                        entity.id, status.machine, field.behaviours.len())));
+            },
+            _ => (),
+          }
+          if entity.id.generation != generation {
+            return Err(SnapshotError::Restore(format!("entity {} is in a slot at generation {}", entity.id, generation)));
+          }
+        },
+        None => (),
+      }
+    }
+    let slots = self.slots.iter().map(|&(generation, entity)| (generation, entity)).collect();
+    field.swarm = match EntityStore::from_slots(slots, self.free.clone()) {
+      Ok(swarm) => swarm,
+      Err(message) => return Err(SnapshotError::Restore(message)),
+    };
+
+    field.world.split_groups = !field.interactions.is_uniform();
+    for deformation in self.deformations.iter() {
+      if !WorldManifold::in_bounds(Vector3::new(deformation.x, deformation.y, 0.0)) {
+        return Err(SnapshotError::Restore(format!("deformation at ({}, {}) is off the manifold", deformation.x, deformation.y)));
+      }
+    }
+    for deformation in self.deformations.iter() {
+      field.world.deform(Vector3::new(deformation.x, deformation.y, 0.0), deformation.magnitude, deformation.group);
+    }
+    return Ok(field);
+  }
+
+  // .json files get the readable format, anything else the binary one
+  pub fn save(&self, path: &Path) -> IoResult<()> {
+    let mut out = BufferedWriter::new(try!(File::create(path)));
+    return match path.extension_str() {
+      Some("json") => out.write_str(self.to_json().to_pretty_str().as_slice()),
+      _ => self.write_binary(&mut out),
+    };
+  }
+
+  pub fn load(path: &Path) -> Result<Snapshot, SnapshotError> {
+    let file = match File::open(path) {
+      Ok(file) => file,
+      Err(err) => return Err(SnapshotError::Io(err)),
+    };
+    let mut input = BufferedReader::new(file);
+    return match path.extension_str() {
+      Some("json") => {
+        let text = match input.read_to_string() {
+          Ok(text) => text,
+          Err(err) => return Err(SnapshotError::Io(err)),
+        };
+        match json::from_str(text.as_slice()) {
+          Ok(value) => Snapshot::from_json(&value).map_err(|err| SnapshotError::Json(err)),
+          Err(err) => Err(SnapshotError::Json(ScenarioError::Syntax(err.to_string()))),
+        }
+      },
+      _ => Snapshot::read_binary(&mut input),
+    };
+  }
+
+  // --------- Binary -----------
+  // Little endian throughout, floats as their bits, so a restore is exact
+
+  pub fn write_binary<W: Writer>(&self, out: &mut W) -> IoResult<()> {
+    try!(out.write(MAGIC));
+    try!(out.write_le_u32(VERSION));
+    try!(out.write_le_f32(self.step));
+    try!(out.write_le_u64(self.seed));
+    for &word in self.rng_state.iter() {
+      try!(out.write_le_u32(word));
+    }
+    try!(out.write_le_u64(self.ticks));
+    try!(out.write_le_f32(self.time));
+    try!(write_string(out, self.integrator.name()));
+    try!(out.write_le_i64(self.deform_strength as i64));
+    try!(out.write_le_f32(self.kernel_sigma));
+    try!(out.write_u8(self.collisions_enabled as u8));
+    try!(out.write_le_f32(self.restitution));
+    try!(out.write_le_f32(self.friction));
+
+    try!(out.write_le_u32(self.anchors.len() as u32));
+    for anchor in self.anchors.iter() {
+      try!(write_vec3(out, anchor.pos));
+      try!(write_vec3(out, anchor.origin));
+      try!(out.write_le_f32(anchor.strength));
+      try!(out.write_le_f32(anchor.distance));
+      match anchor.motion {
+        AnchorMotion::Fixed => try!(out.write_u8(0)),
+        AnchorMotion::Orbit(center, radius, period) => {
+          try!(out.write_u8(1));
+          try!(write_vec3(out, center));
+          try!(out.write_le_f32(radius));
+          try!(out.write_le_f32(period));
+        },
+        AnchorMotion::Linear(velocity) => {
+          try!(out.write_u8(2));
+          try!(write_vec3(out, velocity));
+        },
+        AnchorMotion::Oscillate(amplitude, period) => {
+          try!(out.write_u8(3));
+          try!(write_vec3(out, amplitude));
+          try!(out.write_le_f32(period));
+        },
+      }
+    }
+
+    try!(out.write_le_u32(self.zones.len() as u32));
+    for zone in self.zones.iter() {
+      match zone.shape {
+        ZoneShape::Circle(center, radius) => {
+          try!(out.write_u8(0));
+          try!(write_vec2(out, center));
+          try!(out.write_le_f32(radius));
+        },
+        ZoneShape::Polygon(ref points) => {
+          try!(out.write_u8(1));
+          try!(out.write_le_u32(points.len() as u32));
+          for &point in points.iter() {
+            try!(write_vec2(out, point));
+          }
+        },
+        ZoneShape::ManifoldMask(threshold) => {
+          try!(out.write_u8(2));
+          try!(out.write_le_f32(threshold));
+        },
+      }
+      try!(out.write_u8(match zone.kind { ZoneKind::Attract => 0, ZoneKind::Repel => 1 }));
+      try!(out.write_le_f32(zone.strength));
+      try!(out.write_le_i64(zone.priority as i64));
+    }
+
+    let groups = self.interactions.groups();
+    try!(out.write_le_u32(groups as u32));
+    for observer in range(0, groups) {
+      for source in range(0, groups) {
+        let (tag, strength) = interaction_parts(self.interactions.get(observer, source));
+        try!(out.write_u8(tag));
+        try!(out.write_le_f32(strength));
+      }
+    }
+
+    try!(out.write_le_u32(self.forces.len() as u32));
+    for force in self.forces.iter() {
+      try!(write_string(out, force.name.as_slice()));
+      try!(out.write_le_f32(force.weight));
+      try!(out.write_le_u32(force.params.len() as u32));
+      for &param in force.params.iter() {
+        try!(out.write_le_f32(param));
+      }
+    }
+
+    try!(out.write_le_u32(self.deformations.len() as u32));
+    for deformation in self.deformations.iter() {
+      try!(out.write_le_i64(deformation.magnitude as i64));
+      try!(out.write_le_u32(deformation.group as u32));
+      try!(out.write_le_f32(deformation.x));
+      try!(out.write_le_f32(deformation.y));
+    }
+
+    try!(out.write_le_u32(self.slots.len() as u32));
+    for &(generation, ref entity) in self.slots.iter() {
+      try!(out.write_le_u64(generation as u64));
+      match *entity {
+        Some(ref entity) => {
+          try!(out.write_u8(1));
+          try!(write_entity(out, entity));
+        },
+        None => try!(out.write_u8(0)),
+      }
+    }
+    try!(out.write_le_u32(self.free.len() as u32));
+    for &index in self.free.iter() {
+      try!(out.write_le_u32(index as u32));
+    }
+    return out.flush();
+  }
+
+  pub fn read_binary<R: Reader>(input: &mut R) -> Result<Snapshot, SnapshotError> {
+    let magic = try!(io(input.read_exact(MAGIC.len())));
+    if magic.as_slice() != MAGIC {
+      return Err(SnapshotError::Corrupt("wrong magic number".to_string()));
+    }
+    let version = try!(io(input.read_le_u32()));
+    if version != VERSION {
+      return Err(SnapshotError::Corrupt(format!("version {}, this build reads version {}", version, VERSION)));
+    }
+
+    let step = try!(io(input.read_le_f32()));
+    let seed = try!(io(input.read_le_u64()));
+    let mut rng_state = [0u32, ..4];
+    for word in rng_state.iter_mut() {
+      *word = try!(io(input.read_le_u32()));
+    }
+    let ticks = try!(io(input.read_le_u64()));
+    let time = try!(io(input.read_le_f32()));
+    let integrator_name = try!(read_string(input));
+    let integrator = match Integrator::from_name(integrator_name.as_slice()) {
+      Some(integrator) => integrator,
+      None => return Err(SnapshotError::Corrupt(format!("unknown integrator '{}'", integrator_name))),
+    };
+    let deform_strength = try!(io(input.read_le_i64())) as int;
+    let kernel_sigma = try!(io(input.read_le_f32()));
+    let collisions_enabled = try!(io(input.read_u8())) != 0;
+    let restitution = try!(io(input.read_le_f32()));
+    let friction = try!(io(input.read_le_f32()));
+
+    let mut anchors = Vec::new();
+    for _ in range(0, try!(io(input.read_le_u32()))) {
+      let pos = try!(read_vec3(input));
+      let origin = try!(read_vec3(input));
+      let strength = try!(io(input.read_le_f32()));
+      let distance = try!(io(input.read_le_f32()));
+      let motion = match try!(io(input.read_u8())) {
+        0 => AnchorMotion::Fixed,
+        1 => AnchorMotion::Orbit(try!(read_vec3(input)), try!(io(input.read_le_f32())), try!(io(input.read_le_f32()))),
+        2 => AnchorMotion::Linear(try!(read_vec3(input))),
+        3 => AnchorMotion::Oscillate(try!(read_vec3(input)), try!(io(input.read_le_f32()))),
+        tag => return Err(SnapshotError::Corrupt(format!("unknown anchor motion {}", tag))),
+      };
+      anchors.push(AnchorEnt{pos: pos, origin: origin, strength: strength, distance: distance, motion: motion});
+    }
+
+    let mut zones = Vec::new();
+    for _ in range(0, try!(io(input.read_le_u32()))) {
+      let shape = match try!(io(input.read_u8())) {
+        0 => ZoneShape::Circle(try!(read_vec2(input)), try!(io(input.read_le_f32()))),
+        1 => {
+          let count = try!(io(input.read_le_u32()));
+          let mut points = Vec::new();
+          for _ in range(0, count) {
+            points.push(try!(read_vec2(input)));
+          }
+          ZoneShape::Polygon(points)
+        },
+        2 => ZoneShape::ManifoldMask(try!(io(input.read_le_f32()))),
+        tag => return Err(SnapshotError::Corrupt(format!("unknown zone shape {}", tag))),
+      };
+      let kind = match try!(io(input.read_u8())) {
+        0 => ZoneKind::Attract,
+        1 => ZoneKind::Repel,
+        tag => return Err(SnapshotError::Corrupt(format!("unknown zone kind {}", tag))),
+      };
+      let strength = try!(io(input.read_le_f32()));
+      let priority = try!(io(input.read_le_i64())) as int;
+      zones.push(ZoneEnt{shape: shape, kind: kind, strength: strength, priority: priority});
+    }
+
+    let groups = try!(io(input.read_le_u32())) as uint;
+    let mut interactions = InteractionMatrix::new(groups);
+    for observer in range(0, groups) {
+      for source in range(0, groups) {
+        let tag = try!(io(input.read_u8()));
+        let strength = try!(io(input.read_le_f32()));
+        match interaction_from_parts(tag, strength) {
+          Some(interaction) => interactions.set(observer, source, interaction),
+          None => return Err(SnapshotError::Corrupt(format!("unknown interaction {}", tag))),
+        }
+      }
+    }
+
+    let mut forces = Vec::new();
+    for _ in range(0, try!(io(input.read_le_u32()))) {
+      let name = try!(read_string(input));
+      let weight = try!(io(input.read_le_f32()));
+      let mut params = Vec::new();
+      for _ in range(0, try!(io(input.read_le_u32()))) {
+        params.push(try!(io(input.read_le_f32())));
+      }
+      forces.push(ForceState{name: name, weight: weight, params: params});
+    }
+
+    let mut deformations = Vec::new();
+    for _ in range(0, try!(io(input.read_le_u32()))) {
+      let magnitude = try!(io(input.read_le_i64())) as int;
+      let group = try!(io(input.read_le_u32())) as uint;
+      let x = try!(io(input.read_le_f32()));
+      let y = try!(io(input.read_le_f32()));
+      deformations.push(Deformation{magnitude: magnitude, group: group, x: x, y: y});
+    }
+
+    let mut slots = Vec::new();
+    for index in range(0, try!(io(input.read_le_u32())) as uint) {
+      let generation = try!(io(input.read_le_u64())) as uint;
+      let entity = match try!(io(input.read_u8())) {
+        0 => None,
+        _ => Some(try!(read_entity(input, EntityId{index: index, generation: generation}))),
+      };
+      slots.push((generation, entity));
+    }
+    let mut free = Vec::new();
+    for _ in range(0, try!(io(input.read_le_u32()))) {
+      free.push(try!(io(input.read_le_u32())) as uint);
+    }
+
+    return Ok(Snapshot{
+      step: step,
+      seed: seed,
+      rng_state: rng_state,
+      ticks: ticks,
+      time: time,
+      integrator: integrator,
+      deform_strength: deform_strength,
+      kernel_sigma: kernel_sigma,
+      deformations: deformations,
+      collisions_enabled: collisions_enabled,
+      restitution: restitution,
+      friction: friction,
+      anchors: anchors,
+      zones: zones,
+      interactions: interactions,
+      forces: forces,
+      slots: slots,
+      free: free,
+    });
+  }
+
+  // --------- JSON -----------
+  // For reading and sharing. JSON keeps floats to about six digits, so a run resumed
+  // from it is close but not bit for bit, use the binary format for that.
+
+  pub fn to_json(&self) -> Json {
+    return object_json(vec![
+      ("version", Json::U64(VERSION as u64)),
+      ("step", f32_json(self.step)),
+      ("seed", Json::U64(self.seed)),
+      ("rng_state", Json::Array(self.rng_state.iter().map(|&word| Json::U64(word as u64)).collect())),
+      ("ticks", Json::U64(self.ticks)),
+      ("time", f32_json(self.time)),
+      ("integrator", Json::String(self.integrator.name().to_string())),
+      ("manifold", object_json(vec![
+        ("deform_strength", Json::I64(self.deform_strength as i64)),
+        ("kernel_sigma", f32_json(self.kernel_sigma)),
+        ("deformations", Json::Array(self.deformations.iter().map(|deformation| {
+          object_json(vec![("magnitude", Json::I64(deformation.magnitude as i64)),
+                           ("group", Json::U64(deformation.group as u64)),
+                           ("x", f32_json(deformation.x)),
+                           ("y", f32_json(deformation.y))])
+        }).collect())),
+      ])),
+      ("collisions", object_json(vec![("enabled", Json::Boolean(self.collisions_enabled)),
+                                      ("restitution", f32_json(self.restitution)),
+                                      ("friction", f32_json(self.friction))])),
+      ("anchors", Json::Array(self.anchors.iter().map(|anchor| {
+        // anchor_json only keeps the origin, the current position follows from it and the time
+        anchor_json(anchor)
+      }).collect())),
+      ("zones", Json::Array(self.zones.iter().map(|zone| zone_json(zone)).collect())),
+      ("interactions", interactions_json(&self.interactions)),
+      ("forces", Json::Array(self.forces.iter().map(|force| {
+        object_json(vec![("name", Json::String(force.name.clone())),
+                         ("weight", f32_json(force.weight)),
+                         ("params", Json::Array(force.params.iter().map(|&param| f32_json(param)).collect()))])
+      }).collect())),
+      ("swarm", object_json(vec![
+        ("slots", Json::Array(self.slots.iter().map(|&(generation, ref entity)| {
+          let mut fields = vec![("generation", Json::U64(generation as u64))];
+          match *entity {
+            Some(ref entity) => fields.push(("entity", entity_json(entity))),
+            None => (),
+          }
+          object_json(fields)
+        }).collect())),
+        ("free", Json::Array(self.free.iter().map(|&index| Json::U64(index as u64)).collect())),
+      ])),
+    ]);
+  }
+
+  pub fn from_json(value: &Json) -> Result<Snapshot, ScenarioError> {
+    let root = try!(Section::new(value, "".to_string()));
+    try!(root.only(&["version", "step", "seed", "rng_state", "ticks", "time", "integrator", "manifold", "collisions",
+                     "anchors", "zones", "interactions", "forces", "swarm"]));
+    let version = try!(required_u64(&root, "version"));
+    if version != VERSION as u64 {
+      return Err(root.error("version", format!("version {}, this build reads version {}", version, VERSION).as_slice()));
+    }
+
+    let mut rng_state = [0u32, ..4];
+    match root.get("rng_state") {
+      Some(&Json::Array(ref words)) if words.len() == 4 => {
+        for (idx, word) in words.iter().enumerate() {
+          rng_state[idx] = try!(read_u64(word, format!("rng_state[{}]", idx).as_slice())) as u32;
+        }
+      },
+      _ => return Err(root.error("rng_state", "expected four whole numbers")),
+    }
+
+    let integrator_name = match root.get("integrator") {
+      Some(value) => try!(read_str(value, "integrator")),
+      None => return Err(root.error("integrator", "missing")),
+    };
+    let integrator = match Integrator::from_name(integrator_name) {
+      Some(integrator) => integrator,
+      None => return Err(root.error("integrator", format!("unknown integrator '{}'", integrator_name).as_slice())),
+    };
+
+    let manifold = try!(required_section(&root, "manifold"));
+    try!(manifold.only(&["deform_strength", "kernel_sigma", "deformations"]));
+    let mut deformations = Vec::new();
+    for deformation in try!(manifold.list("deformations")).iter() {
+      try!(deformation.only(&["magnitude", "group", "x", "y"]));
+      deformations.push(Deformation{
+        magnitude: try!(deformation.int_or("magnitude", 0)),
+        group: try!(deformation.uint_or("group", 0)),
+        x: try!(deformation.f32("x")),
+        y: try!(deformation.f32("y")),
+      });
+    }
+
+    let collisions = try!(required_section(&root, "collisions"));
+    try!(collisions.only(&["enabled", "restitution", "friction"]));
+
+    let anchors: Vec<AnchorEnt> = try!(try!(root.list("anchors")).iter().map(|anchor| read_anchor(anchor)).collect());
+    let zones: Vec<ZoneEnt> = try!(try!(root.list("zones")).iter().map(|zone| read_zone(zone)).collect());
+
+    let interactions = try!(read_interactions(&try!(required_section(&root, "interactions"))));
+
+    let mut forces = Vec::new();
+    for force in try!(root.list("forces")).iter() {
+      try!(force.only(&["name", "weight", "params"]));
+      let name = match force.get("name") {
+        Some(value) => try!(read_str(value, force.path_to("name").as_slice())).to_string(),
+        None => return Err(force.error("name", "missing")),
+      };
+      let params: Vec<f32> = match force.get("params") {
+        Some(&Json::Array(ref items)) => {
+          try!(items.iter().enumerate().map(|(idx, item)| read_f32(item, format!("{}[{}]", force.path_to("params"), idx).as_slice())).collect())
+        },
+        Some(_) => return Err(force.error("params", "expected a list of numbers")),
+        None => Vec::new(),
+      };
+      forces.push(ForceState{name: name, weight: try!(force.f32("weight")), params: params});
+    }
+
+    let swarm = try!(required_section(&root, "swarm"));
+    try!(swarm.only(&["slots", "free"]));
+    let mut slots = Vec::new();
+    for (index, slot) in try!(swarm.list("slots")).iter().enumerate() {
+      try!(slot.only(&["generation", "entity"]));
+      let generation = try!(required_u64(slot, "generation")) as uint;
+      let entity = match try!(slot.section("entity")) {
+        Some(entity) => Some(try!(read_entity_json(&entity, EntityId{index: index, generation: generation}))),
+        None => None,
+      };
+      slots.push((generation, entity));
+    }
+    let free: Vec<uint> = match swarm.get("free") {
+      Some(&Json::Array(ref items)) => {
+        try!(items.iter().enumerate().map(|(idx, item)| read_uint(item, format!("swarm.free[{}]", idx).as_slice())).collect())
+      },
+      _ => return Err(swarm.error("free", "expected a list of slot indices")),
+    };
+
+    return Ok(Snapshot{
+      step: try!(root.f32("step")),
+      seed: try!(required_u64(&root, "seed")),
+      rng_state: rng_state,
+      ticks: try!(required_u64(&root, "ticks")),
+      time: try!(root.f32("time")),
+      integrator: integrator,
+      deform_strength: try!(manifold.int_or("deform_strength", 0)),
+      kernel_sigma: try!(manifold.f32("kernel_sigma")),
+      deformations: deformations,
+      collisions_enabled: try!(collisions.bool_or("enabled", false)),
+      restitution: try!(collisions.f32("restitution")),
+      friction: try!(collisions.f32("friction")),
+      anchors: anchors,
+      zones: zones,
+      interactions: interactions,
+      forces: forces,
+      slots: slots,
+      free: free,
+    });
+  }
+}
+
+fn io<T>(result: IoResult<T>) -> Result<T, SnapshotError> {
+  return result.map_err(|err| SnapshotError::Io(err));
+}
+
+fn write_string<W: Writer>(out: &mut W, text: &str) -> IoResult<()> {
+  try!(out.write_le_u32(text.len() as u32));
+  return out.write_str(text);
+}
+
+fn read_string<R: Reader>(input: &mut R) -> Result<String, SnapshotError> {
+  let len = try!(io(input.read_le_u32())) as uint;
+  let bytes = try!(io(input.read_exact(len)));
+  return match String::from_utf8(bytes) {
+    Ok(text) => Ok(text),
+    Err(_) => Err(SnapshotError::Corrupt("string isn't UTF-8".to_string())),
+  };
+}
+
+fn write_vec2<W: Writer>(out: &mut W, value: Vector2<f32>) -> IoResult<()> {
+  try!(out.write_le_f32(value.x));
+  return out.write_le_f32(value.y);
+}
+
+fn read_vec2<R: Reader>(input: &mut R) -> Result<Vector2<f32>, SnapshotError> {
+  let x = try!(io(input.read_le_f32()));
+  let y = try!(io(input.read_le_f32()));
+  return Ok(Vector2::new(x, y));
+}
+
+fn write_vec3<W: Writer>(out: &mut W, value: Vector3<f32>) -> IoResult<()> {
+  try!(out.write_le_f32(value.x));
+  try!(out.write_le_f32(value.y));
+  return out.write_le_f32(value.z);
+}
+
+fn read_vec3<R: Reader>(input: &mut R) -> Result<Vector3<f32>, SnapshotError> {
+  let x = try!(io(input.read_le_f32()));
+  let y = try!(io(input.read_le_f32()));
+  let z = try!(io(input.read_le_f32()));
+  return Ok(Vector3::new(x, y, z));
+}
+
+fn write_entity<W: Writer>(out: &mut W, entity: &SwarmEnt) -> IoResult<()> {
+  try!(write_vec3(out, entity.pos));
+  try!(write_vec3(out, entity.vel));
+  try!(out.write_le_f32(entity.mass));
+  try!(out.write_le_f32(entity.radius));
+  try!(out.write_le_f32(entity.drag));
+  try!(out.write_le_f32(entity.max_speed));
+  try!(out.write_le_f32(entity.max_accel));
+  try!(out.write_le_u32(entity.group as u32));
+  try!(out.write_le_f32(entity.heading));
+  try!(out.write_le_f32(entity.angular_vel));
+  match entity.kinematics {
+    Kinematics::PointMass => try!(out.write_u8(0)),
+    Kinematics::Unicycle(max_turn_rate) => {
+      try!(out.write_u8(1));
+      try!(out.write_le_f32(max_turn_rate));
+    },
+    Kinematics::DifferentialDrive(wheel_base, max_wheel_speed) => {
+      try!(out.write_u8(2));
+      try!(out.write_le_f32(wheel_base));
+      try!(out.write_le_f32(max_wheel_speed));
+    },
+  }
+  return match entity.behaviour {
+    Some(status) => {
+      try!(out.write_u8(1));
+      try!(out.write_le_u32(status.machine as u32));
+      try!(out.write_u8(status.state.index() as u8));
+      out.write_le_f32(status.time_in_state)
+    },
+    None => out.write_u8(0),
+  };
+}
+
+fn read_entity<R: Reader>(input: &mut R, id: EntityId) -> Result<SwarmEnt, SnapshotError> {
+  let pos = try!(read_vec3(input));
+  let vel = try!(read_vec3(input));
+  let mass = try!(io(input.read_le_f32()));
+  let radius = try!(io(input.read_le_f32()));
+  let drag = try!(io(input.read_le_f32()));
+  let max_speed = try!(io(input.read_le_f32()));
+  let max_accel = try!(io(input.read_le_f32()));
+  let group = try!(io(input.read_le_u32())) as uint;
+  let heading = try!(io(input.read_le_f32()));
+  let angular_vel = try!(io(input.read_le_f32()));
+  let kinematics = match try!(io(input.read_u8())) {
+    0 => Kinematics::PointMass,
+    1 => Kinematics::Unicycle(try!(io(input.read_le_f32()))),
+    2 => Kinematics::DifferentialDrive(try!(io(input.read_le_f32())), try!(io(input.read_le_f32()))),
+    tag => return Err(SnapshotError::Corrupt(format!("unknown kinematics {}", tag))),
+  };
+  let behaviour = match try!(io(input.read_u8())) {
+    0 => None,
+    _ => {
+      let machine = try!(io(input.read_le_u32())) as uint;
+      let state_idx = try!(io(input.read_u8())) as uint;
+      let state = match BehaviourState::all().into_iter().find(|state| state.index() == state_idx) {
+        Some(state) => state,
+        None => return Err(SnapshotError::Corrupt(format!("unknown behaviour state {}", state_idx))),
+      };
+      let time_in_state = try!(io(input.read_le_f32()));
+      Some(BehaviourStatus{machine: machine, state: state, time_in_state: time_in_state})
+    },
+  };
+
+  return Ok(SwarmEnt{
+    id: id,
+    pos: pos,
+    vel: vel,
+    mass: mass,
+    radius: radius,
+    drag: drag,
+    max_speed: max_speed,
+    max_accel: max_accel,
+    group: group,
+    heading: heading,
+    angular_vel: angular_vel,
+    kinematics: kinematics,
+    behaviour: behaviour,
+  });
+}
+
+fn finite(value: Vector3<f32>) -> bool {
+  return value.x.is_finite() && value.y.is_finite() && value.z.is_finite();
+}
+
+fn interaction_parts(interaction: Interaction) -> (u8, f32) {
+  return match interaction {
+    Interaction::Attract(strength) => (0, strength),
+    Interaction::Repel(strength) => (1, strength),
+    Interaction::Ignore => (2, 0.0),
+    Interaction::Kernel(weight) => (3, weight),
+  };
+}
+
+fn interaction_from_parts(tag: u8, strength: f32) -> Option<Interaction> {
+  return match tag {
+    0 => Some(Interaction::Attract(strength)),
+    1 => Some(Interaction::Repel(strength)),
+    2 => Some(Interaction::Ignore),
+    3 => Some(Interaction::Kernel(strength)),
+    _ => None,
+  };
+}
+
+fn required_u64(section: &Section, key: &str) -> Result<u64, ScenarioError> {
+  return match section.get(key) {
+    Some(value) => read_u64(value, section.path_to(key).as_slice()),
+    None => Err(section.error(key, "missing")),
+  };
+}
+
+fn required_section<'a>(section: &Section<'a>, key: &str) -> Result<Section<'a>, ScenarioError> {
+  return match try!(section.section(key)) {
+    Some(found) => Ok(found),
+    None => Err(section.error(key, "missing")),
+  };
+}
+
+fn vec2_json(value: Vector2<f32>) -> Json {
+  return Json::Array(vec![f32_json(value.x), f32_json(value.y)]);
+}
+
+fn read_vec2_json(value: &Json, path: &str) -> Result<Vector2<f32>, ScenarioError> {
+  return match *value {
+    Json::Array(ref items) if items.len() == 2 => {
+      Ok(Vector2::new(try!(read_f32(&items[0], format!("{}[0]", path).as_slice())),
+                      try!(read_f32(&items[1], format!("{}[1]", path).as_slice()))))
+    },
+    _ => Err(field_error(path.to_string(), "expected [x, y]")),
+  };
+}
+
+fn zone_json(zone: &ZoneEnt) -> Json {
+  let shape = match zone.shape {
+    ZoneShape::Circle(center, radius) => {
+      object_json(vec![("type", Json::String("circle".to_string())), ("center", vec2_json(center)), ("radius", f32_json(radius))])
+    },
+    ZoneShape::Polygon(ref points) => {
+      object_json(vec![("type", Json::String("polygon".to_string())),
+                       ("points", Json::Array(points.iter().map(|&point| vec2_json(point)).collect()))])
+    },
+    ZoneShape::ManifoldMask(threshold) => {
+      object_json(vec![("type", Json::String("manifold-mask".to_string())), ("threshold", f32_json(threshold))])
+    },
+  };
+  let kind = match zone.kind {
+    ZoneKind::Attract => "attract",
+    ZoneKind::Repel => "repel",
+  };
+  return object_json(vec![("shape", shape),
+                          ("kind", Json::String(kind.to_string())),
+                          ("strength", f32_json(zone.strength)),
+                          ("priority", Json::I64(zone.priority as i64))]);
+}
+
+fn read_zone(zone: &Section) -> Result<ZoneEnt, ScenarioError> {
+  try!(zone.only(&["shape", "kind", "strength", "priority"]));
+  let shape_section = try!(required_section(zone, "shape"));
+  let shape = match try!(shape_section.kind()) {
+    "circle" => {
+      try!(shape_section.only(&["type", "center", "radius"]));
+      let center = match shape_section.get("center") {
+        Some(value) => try!(read_vec2_json(value, shape_section.path_to("center").as_slice())),
+        None => return Err(shape_section.error("center", "missing")),
+      };
+      ZoneShape::Circle(center, try!(shape_section.f32("radius")))
+    },
+    "polygon" => {
+      try!(shape_section.only(&["type", "points"]));
+      match shape_section.get("points") {
+        Some(&Json::Array(ref items)) => {
+          let path = shape_section.path_to("points");
+          ZoneShape::Polygon(try!(items.iter().enumerate().map(|(idx, item)| {
+            read_vec2_json(item, format!("{}[{}]", path, idx).as_slice())
+          }).collect()))
+        },
+        _ => return Err(shape_section.error("points", "expected a list of [x, y]")),
+      }
+    },
+    "manifold-mask" => {
+      try!(shape_section.only(&["type", "threshold"]));
+      ZoneShape::ManifoldMask(try!(shape_section.f32("threshold")))
+    },
+    other => return Err(shape_section.error("type", format!("unknown zone shape '{}'", other).as_slice())),
+  };
+  let kind = match zone.get("kind") {
+    Some(value) => match try!(read_str(value, zone.path_to("kind").as_slice())) {
+      "attract" => ZoneKind::Attract,
+      "repel" => ZoneKind::Repel,
+      other => return Err(zone.error("kind", format!("unknown zone kind '{}', expected attract or repel", other).as_slice())),
+    },
+    None => return Err(zone.error("kind", "missing")),
+  };
+  return Ok(ZoneEnt{shape: shape, kind: kind, strength: try!(zone.f32("strength")), priority: try!(zone.int_or("priority", 0))});
+}
+
+fn entity_json(entity: &SwarmEnt) -> Json {
+  let mut fields = vec![("pos", vec3_json(entity.pos)),
+                        ("vel", vec3_json(entity.vel)),
+                        ("mass", f32_json(entity.mass)),
+                        ("radius", f32_json(entity.radius)),
+                        ("drag", f32_json(entity.drag)),
+                        ("group", Json::U64(entity.group as u64)),
+                        ("heading", f32_json(entity.heading)),
+                        ("angular_vel", f32_json(entity.angular_vel)),
+                        ("kinematics", kinematics_json(entity.kinematics))];
+  // Left out when unlimited, JSON can't say infinity
+  if entity.max_speed.is_finite() {
+    fields.push(("max_speed", f32_json(entity.max_speed)));
+  }
+  if entity.max_accel.is_finite() {
+    fields.push(("max_accel", f32_json(entity.max_accel)));
+  }
+  match entity.behaviour {
+    Some(status) => {
+      fields.push(("behaviour", object_json(vec![("machine", Json::U64(status.machine as u64)),
+                                                 ("state", Json::String(status.state.name().to_string())),
+                                                 ("time_in_state", f32_json(status.time_in_state))])));
+    },
+    None => (),
+  }
+  return object_json(fields);
+}
+
+fn read_entity_json(entity: &Section, id: EntityId) -> Result<SwarmEnt, ScenarioError> {
+  try!(entity.only(&["pos", "vel", "mass", "radius", "drag", "max_speed", "max_accel", "group", "heading", "angular_vel",
+                     "kinematics", "behaviour"]));
+  let kinematics = match try!(entity.section("kinematics")) {
+    Some(kinematics) => try!(read_kinematics(&kinematics)),
+    None => Kinematics::PointMass,
+  };
+  let behaviour = match try!(entity.section("behaviour")) {
+    Some(behaviour) => {
+      try!(behaviour.only(&["machine", "state", "time_in_state"]));
+      let state_name = match behaviour.get("state") {
+        Some(value) => try!(read_str(value, behaviour.path_to("state").as_slice())),
+        None => return Err(behaviour.error("state", "missing")),
+      };
+      let state = match BehaviourState::from_name(state_name) {
+        Some(state) => state,
+        None => return Err(behaviour.error("state", format!("unknown behaviour state '{}'", state_name).as_slice())),
+      };
+      Some(BehaviourStatus{machine: try!(behaviour.uint_or("machine", 0)),
+                           state: state,
+                           time_in_state: try!(behaviour.f32_or("time_in_state", 0.0))})
+    },
+    None => None,
+  };
+
+  return Ok(SwarmEnt{
+    id: id,
+    pos: try!(entity.vec3("pos")),
+    vel: try!(entity.vec3("vel")),
+    mass: try!(entity.f32("mass")),
+    radius: try!(entity.f32("radius")),
+    drag: try!(entity.f32_or("drag", 0.0)),
+    max_speed: try!(entity.f32_or("max_speed", Float::infinity())),
+    max_accel: try!(entity.f32_or("max_accel", Float::infinity())),
+    group: try!(entity.uint_or("group", 0)),
+    heading: try!(entity.f32_or("heading", 0.0)),
+    angular_vel: try!(entity.f32_or("angular_vel", 0.0)),
+    kinematics: kinematics,
+    behaviour: behaviour,
+  });
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Snapshot, SnapshotError};
+  use entity_field::EntityField;
+  use fixed_step::FixedStepRunner;
+  use replay::state_hash;
+  use scenario::Scenario;
+
+  use std::io::{BufReader, MemWriter};
+
+  static TICKS: uint = 5;
+
+  // A few ticks into a small run, so there's a manifold, a moved swarm and a free slot
+  fn running_field() -> (EntityField, FixedStepRunner) {
+    let mut scenario = Scenario::with_swarm_size(8);
+    scenario.collisions_enabled = true;
+    let mut field = scenario.build_field().ok().unwrap();
+    let mut runner = FixedStepRunner::with_rate(scenario.tick_rate);
+    runner.run_ticks(&mut field, TICKS);
+    let id = field.swarm.ids()[3];
+    field.despawn(id);
+    runner.run_ticks(&mut field, TICKS);
+    return (field, runner);
+  }
+
+  fn binary(snapshot: &Snapshot) -> Vec<u8> {
+    let mut out = MemWriter::new();
+    snapshot.write_binary(&mut out).ok().unwrap();
+    return out.unwrap();
+  }
+
+  #[test]
+  fn binary_round_trip_resumes_bit_for_bit() {
+    let (mut field, mut runner) = running_field();
+    let bytes = binary(&Snapshot::capture(&field, runner.step));
+    let snapshot = Snapshot::read_binary(&mut BufReader::new(bytes.as_slice())).ok().unwrap();
+    let mut restored = snapshot.restore().ok().unwrap();
+    let mut restored_runner = FixedStepRunner::with_step(snapshot.step);
+    assert_eq!(state_hash(&restored), state_hash(&field));
+
+    runner.run_ticks(&mut field, TICKS);
+    restored_runner.run_ticks(&mut restored, TICKS);
+    assert_eq!(state_hash(&restored), state_hash(&field));
+  }
+
+  #[test]
+  fn restore_rejects_a_corrupt_free_list() {
+    let (field, runner) = running_field();
+    let mut snapshot = Snapshot::capture(&field, runner.step);
+    assert_eq!(snapshot.free.len(), 1);
+
+    let slot = snapshot.free[0];
+    snapshot.free.push(slot);
+    assert!(match snapshot.restore() { Err(SnapshotError::Restore(_)) => true, _ => false });
+
+    snapshot.free.clear();
+    assert!(match snapshot.restore() { Err(SnapshotError::Restore(_)) => true, _ => false });
+
+    snapshot.free.push(0);
+    assert!(match snapshot.restore() { Err(SnapshotError::Restore(_)) => true, _ => false });
+  }
+
+  #[test]
+  fn read_binary_rejects_a_wrong_magic_number() {
+    let (field, runner) = running_field();
+    let mut bytes = binary(&Snapshot::capture(&field, runner.step));
+    bytes[0] = b'X';
+    let result = Snapshot::read_binary(&mut BufReader::new(bytes.as_slice()));
+    assert!(match result { Err(SnapshotError::Corrupt(_)) => true, _ => false });
+  }
+}
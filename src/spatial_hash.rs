@@ -0,0 +1,101 @@
+extern crate cgmath;
+
+use cgmath::Vector3;
+
+use std::collections::HashMap;
+use std::num::Float;
+
+// Neighbouring cells that haven't been paired with a cell yet when walking every cell.
+// Together with the cell itself this covers all 9 cells without double counting.
+static FORWARD_NEIGHBOURS: [(int, int), ..4] = [(1, 0), (1, 1), (0, 1), (-1, 1)];
+
+// Uniform grid over the xy plane, buckets item indices by cell
+pub struct SpatialHash {
+  cell_size: f32,
+  cells: HashMap<(int, int), Vec<uint>>,
+}
+
+impl SpatialHash {
+  pub fn new(cell_size: f32) -> SpatialHash {
+    return SpatialHash{cell_size: cell_size, cells: HashMap::new()};
+  }
+
+  pub fn cell_size(&self) -> f32 {
+    return self.cell_size;
+  }
+
+  // Also drops any buckets we were holding on to
+  pub fn rebuild(&mut self, cell_size: f32, positions: &[Vector3<f32>]) -> () {
+    self.cell_size = cell_size;
+    self.cells.clear();
+    for (item, pos) in positions.iter().enumerate() {
+      self.insert(item, *pos);
+    }
+  }
+
+  pub fn insert(&mut self, item: uint, pos: Vector3<f32>) -> () {
+    let cell = self.cell_of(pos);
+    match self.cells.get_mut(&cell) {
+      Some(bucket) => {
+        bucket.push(item);
+        return;
+      },
+      None => (),
+    }
+    self.cells.insert(cell, vec![item]);
+  }
+
+  pub fn cell_of(&self, pos: Vector3<f32>) -> (int, int) {
+    return ((pos.x / self.cell_size).floor() as int, (pos.y / self.cell_size).floor() as int);
+  }
+
+  // Every pair of items sharing a cell or in touching cells, sorted so callers see a
+  // stable order regardless of hash map iteration
+  pub fn candidate_pairs(&self) -> Vec<(uint, uint)> {
+    let mut pairs: Vec<(uint, uint)> = Vec::new();
+    for (&(cell_x, cell_y), bucket) in self.cells.iter() {
+      for (i, &first) in bucket.iter().enumerate() {
+        for &second in bucket.slice_from(i + 1).iter() {
+          pairs.push(SpatialHash::ordered(first, second));
+        }
+      }
+
+      for &(dx, dy) in FORWARD_NEIGHBOURS.iter() {
+        match self.cells.get(&(cell_x + dx, cell_y + dy)) {
+          Some(other_bucket) => {
+            for &first in bucket.iter() {
+              for &second in other_bucket.iter() {
+                pairs.push(SpatialHash::ordered(first, second));
+              }
+            }
+          },
+          None => (),
+        }
+      }
+    }
+    pairs.sort();
+    return pairs;
+  }
+
+  // Items in every cell overlapped by the square around pos, callers still need a distance check
+  pub fn query(&self, pos: Vector3<f32>, radius: f32) -> Vec<uint> {
+    let (min_x, min_y) = self.cell_of(Vector3::new(pos.x - radius, pos.y - radius, 0.0));
+    let (max_x, max_y) = self.cell_of(Vector3::new(pos.x + radius, pos.y + radius, 0.0));
+
+    let mut items: Vec<uint> = Vec::new();
+    for cell_x in range(min_x, max_x + 1) {
+      for cell_y in range(min_y, max_y + 1) {
+        match self.cells.get(&(cell_x, cell_y)) {
+          Some(bucket) => items.push_all(bucket.as_slice()),
+          None => (),
+        }
+      }
+    }
+    items.sort();
+    return items;
+  }
+
+  fn ordered(first: uint, second: uint) -> (uint, uint) {
+    return if first < second { (first, second) } else { (second, first) };
+  }
+}
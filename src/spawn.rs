@@ -0,0 +1,134 @@
+extern crate cgmath;
+
+use swarm_ent::DEFAULT_RADIUS;
+use kinematics::Kinematics;
+use behaviour::BehaviourStatus;
+
+use cgmath::{Vector, Vector3};
+
+use std::fmt;
+use std::f32::consts::PI;
+use std::num::Float;
+use std::rand::Rng;
+use std::rand::distributions::{IndependentSample, Normal};
+
+// Why a spec can't be spawned
+pub enum SpecError {
+  Mass(f32),
+  Radius(f32),
+  // Off the manifold, where the field can't be deformed
+  OutOfBounds(Vector3<f32>),
+}
+
+impl fmt::Show for SpecError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      SpecError::Mass(mass) => write!(f, "mass must be more than zero, got {}", mass),
+      SpecError::Radius(radius) => write!(f, "radius must be more than zero, got {}", radius),
+      SpecError::OutOfBounds(pos) => write!(f, "({}, {}, {}) is off the manifold", pos.x, pos.y, pos.z),
+    }
+  }
+}
+
+// Everything needed to bring a swarm entity into the field
+#[deriving(Clone)]
+pub struct EntitySpec {
+  pub pos: Vector3<f32>,
+  pub vel: Vector3<f32>,
+  pub mass: f32,
+  pub radius: f32,
+  pub drag: f32,
+  pub max_speed: f32,
+  pub max_accel: f32,
+  pub group: uint,
+  pub heading: f32,
+  pub kinematics: Kinematics,
+  pub behaviour: Option<BehaviourStatus>,
+}
+
+impl EntitySpec {
+  pub fn default() -> EntitySpec {
+    return EntitySpec::at(Vector3::new(0.0, 0.0, 0.0));
+  }
+
+  pub fn at(pos: Vector3<f32>) -> EntitySpec {
+    // Unlimited until told otherwise
+    return EntitySpec{
+      pos: pos,
+      vel: Vector3::new(0.0, 0.0, 0.0),
+      mass: 1.0,
+      radius: DEFAULT_RADIUS,
+      drag: 0.0,
+      max_speed: Float::infinity(),
+      max_accel: Float::infinity(),
+      group: 0,
+      heading: 0.0,
+      kinematics: Kinematics::PointMass,
+      behaviour: None,
+    };
+  }
+
+  // Collisions divide by mass and the broadphase sizes cells by radius, so neither may be zero
+  // or negative. The negated checks also turn away NaN.
+  pub fn validate(&self) -> Result<(), SpecError> {
+    if !(self.mass > 0.0) {
+      return Err(SpecError::Mass(self.mass));
+    }
+    if !(self.radius > 0.0) {
+      return Err(SpecError::Radius(self.radius));
+    }
+    return Ok(());
+  }
+}
+
+// Where bulk spawns get placed. Everything lies on the xy plane at the given z.
+#[deriving(Clone)]
+pub enum Distribution {
+  // Min and max corners
+  UniformBox(Vector3<f32>, Vector3<f32>),
+  // Centre and radius
+  Disk(Vector3<f32>, f32),
+  // Centre and standard deviation along each axis
+  GaussianCluster(Vector3<f32>, f32),
+  // First corner, spacing and number of columns, filled row by row
+  Grid(Vector3<f32>, f32, uint),
+}
+
+impl Distribution {
+  // `index` is the position of this sample within the bulk spawn
+  pub fn sample<R: Rng>(&self, rng: &mut R, index: uint) -> Vector3<f32> {
+    return match *self {
+      Distribution::UniformBox(min, max) => {
+        Vector3::new(Distribution::between(rng, min.x, max.x),
+                     Distribution::between(rng, min.y, max.y),
+                     Distribution::between(rng, min.z, max.z))
+      },
+      Distribution::Disk(center, radius) => {
+        // sqrt keeps the density even instead of bunching up in the middle
+        let dist = radius * rng.gen::<f32>().sqrt();
+        let angle = 2.0 * PI * rng.gen::<f32>();
+        center.add_v(&Vector3::new(dist * angle.cos(), dist * angle.sin(), 0.0))
+      },
+      Distribution::GaussianCluster(center, sigma) => {
+        let normal = Normal::new(0.0, sigma as f64);
+        let x = normal.ind_sample(rng) as f32;
+        let y = normal.ind_sample(rng) as f32;
+        center.add_v(&Vector3::new(x, y, 0.0))
+      },
+      Distribution::Grid(corner, spacing, columns) => {
+        let columns = if columns == 0 { 1 } else { columns };
+        let column = (index % columns) as f32;
+        let row = (index / columns) as f32;
+        corner.add_v(&Vector3::new(column * spacing, row * spacing, 0.0))
+      },
+    };
+  }
+
+  fn between<R: Rng>(rng: &mut R, low: f32, high: f32) -> f32 {
+    // gen_range panics on an empty range, which a flat box legitimately is
+    if low >= high {
+      return low;
+    }
+    return rng.gen_range(low, high);
+  }
+}
@@ -1,23 +1,78 @@
 extern crate cgmath;
 
+use entity_store::EntityId;
+use spawn::EntitySpec;
+use kinematics::Kinematics;
+use behaviour::BehaviourStatus;
+
 use std::fmt;
-use cgmath::{Vector, Vector3};
+use cgmath::{Vector, Vector3, EuclideanVector};
+
+pub static DEFAULT_RADIUS: f32 = 1.0;
 
 pub struct SwarmEnt {
-  pub id: int,
+  pub id: EntityId,
   pub pos: Vector3<f32>,
   pub vel: Vector3<f32>,
+  pub mass: f32,
+  pub radius: f32,
+  // Fraction of velocity bled off per second
+  pub drag: f32,
+  pub max_speed: f32,
+  pub max_accel: f32,
+  pub group: uint,
+  // Radians about +z, measured from +x
+  pub heading: f32,
+  pub angular_vel: f32,
+  pub kinematics: Kinematics,
+  // Entities without a state machine just use the registry weights
+  pub behaviour: Option<BehaviourStatus>,
 }
 
 impl SwarmEnt {
-  pub fn integrate(&mut self, delta_t: f32, accel: Vector3<f32>) -> () {
-    self.vel = self.vel.add_v(&accel.mul_s(delta_t));
-    self.pos = self.pos.add_v(&self.vel.mul_s(delta_t));
+  pub fn new(id: EntityId, pos: Vector3<f32>) -> SwarmEnt {
+    return SwarmEnt::from_spec(id, &EntitySpec::at(pos));
+  }
+
+  pub fn from_spec(id: EntityId, spec: &EntitySpec) -> SwarmEnt {
+    return SwarmEnt{
+      id: id,
+      pos: spec.pos,
+      vel: spec.vel,
+      mass: spec.mass,
+      radius: spec.radius,
+      drag: spec.drag,
+      max_speed: spec.max_speed,
+      max_accel: spec.max_accel,
+      group: spec.group,
+      heading: spec.heading,
+      angular_vel: 0.0,
+      kinematics: spec.kinematics,
+      behaviour: spec.behaviour,
+    };
+  }
+
+  // What this entity actually manages with the field's requested acceleration
+  pub fn limit_accel(&self, accel: Vector3<f32>) -> Vector3<f32> {
+    let accel = accel.sub_v(&self.vel.mul_s(self.drag));
+    return SwarmEnt::clamp_length(accel, self.max_accel);
+  }
+
+  pub fn limit_speed(&mut self) -> () {
+    self.vel = SwarmEnt::clamp_length(self.vel, self.max_speed);
+  }
+
+  fn clamp_length(vec: Vector3<f32>, max: f32) -> Vector3<f32> {
+    let length = vec.length();
+    if length <= max || length == 0.0 {
+      return vec;
+    }
+    return vec.mul_s(max / length);
   }
 }
 
 impl fmt::Show for SwarmEnt {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "SwarmEnt: {}, pos: {}, vel {}", self.id, self.pos, self.vel)
+    write!(f, "SwarmEnt: {}, pos: {}, vel {}, mass {}, radius {}", self.id, self.pos, self.vel, self.mass, self.radius)
   }
 }
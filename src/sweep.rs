@@ -0,0 +1,416 @@
+use entity_field::EntityField;
+use fixed_step::FixedStepRunner;
+use force::ForceRegistry;
+use metrics::SwarmMetrics;
+use scenario::{Scenario, ForceSetting};
+use sim_rng::SimRng;
+use world_manifold::WorldManifold;
+
+use std::cmp;
+use std::collections::HashSet;
+use std::fmt;
+use std::from_str::FromStr;
+use std::io::{File, IoError, IoErrorKind, IoResult, Append, Write};
+use std::num::Float;
+use std::rand::Rng;
+use std::sync::{Arc, Mutex};
+use std::task;
+
+// A knob on the scenario that sweeps and the optimiser can turn
+#[deriving(Clone, PartialEq)]
+pub enum Param {
+  // Every anchor's spring strength, ANCHOR_FIELD_STR by default
+  AnchorStrength,
+  // How hard entities press into the manifold, SWARM_FIELD_STR by default, rounded to whole
+  DeformStrength,
+  // The manifold gradient force's weight, 0.2 by default
+  ManifoldWeight,
+  KernelSigma,
+  // Any other force's weight, by registry name
+  ForceWeight(String),
+}
+
+impl Param {
+  pub fn name(&self) -> String {
+    return match *self {
+      Param::AnchorStrength => "anchor-strength".to_string(),
+      Param::DeformStrength => "deform-strength".to_string(),
+      Param::ManifoldWeight => "manifold-weight".to_string(),
+      Param::KernelSigma => "kernel-sigma".to_string(),
+      Param::ForceWeight(ref force) => format!("weight:{}", force),
+    };
+  }
+
+  pub fn from_name(name: &str) -> Option<Param> {
+    return match name {
+      "anchor-strength" => Some(Param::AnchorStrength),
+      "deform-strength" => Some(Param::DeformStrength),
+      "manifold-weight" => Some(Param::ManifoldWeight),
+      "kernel-sigma" => Some(Param::KernelSigma),
+      _ if name.starts_with("weight:") => {
+        let force = name.slice_from("weight:".len());
+        if ForceRegistry::default().weight(force).is_none() {
+          return None;
+        }
+        Some(Param::ForceWeight(force.to_string()))
+      },
+      _ => None,
+    };
+  }
+
+  // What a weight:FORCE parameter would be missing, so the caller can say which force is wrong
+  pub fn unknown_force(name: &str) -> Option<String> {
+    if !name.starts_with("weight:") || Param::from_name(name).is_some() {
+      return None;
+    }
+    let names: Vec<&str> = ForceRegistry::default().iter().map(|entry| entry.name.as_slice()).collect();
+    return Some(format!("{} names no force, expected weight: and one of {}", name, names.as_slice().connect(", ")));
+  }
+
+  pub fn apply(&self, scenario: &mut Scenario, value: f32) -> () {
+    match *self {
+      Param::AnchorStrength => {
+        for anchor in scenario.anchors.iter_mut() {
+          anchor.strength = value;
+        }
+      },
+      Param::DeformStrength => scenario.deform_strength = value.round() as int,
+      // Later settings win, so these go over whatever the scenario already had
+      Param::ManifoldWeight => scenario.forces.push(ForceSetting::Weight("manifold".to_string(), value)),
+      Param::KernelSigma => scenario.kernel_sigma = value,
+      Param::ForceWeight(ref force) => scenario.forces.push(ForceSetting::Weight(force.clone(), value)),
+    }
+  }
+}
+
+#[deriving(Clone)]
+pub struct ParamRange {
+  pub param: Param,
+  pub min: f32,
+  pub max: f32,
+  // Grid points across min..max inclusive, ignored when sampling at random
+  pub steps: uint,
+}
+
+impl ParamRange {
+  // NAME=MIN:MAX or NAME=MIN:MAX:STEPS
+  pub fn parse(text: &str) -> Option<ParamRange> {
+    let (name, bounds) = match text.find('=') {
+      Some(split) => (text.slice_to(split), text.slice_from(split + 1)),
+      None => return None,
+    };
+    let param = match Param::from_name(name.trim()) {
+      Some(param) => param,
+      None => return None,
+    };
+    let numbers: Vec<&str> = bounds.split(':').collect();
+    let (min, max, steps) = match numbers.as_slice() {
+      [min, max] => (from_str(min.trim()), from_str(max.trim()), Some(2u)),
+      [min, max, steps] => (from_str(min.trim()), from_str(max.trim()), from_str(steps.trim())),
+      _ => return None,
+    };
+    return match (min, max, steps) {
+      (Some(min), Some(max), Some(steps)) if min <= max && steps > 0 => {
+        Some(ParamRange{param: param, min: min, max: max, steps: steps})
+      },
+      _ => None,
+    };
+  }
+
+  pub fn grid_value(&self, step: uint) -> f32 {
+    if self.steps < 2 {
+      return self.min;
+    }
+    return self.min + (self.max - self.min) * step as f32 / (self.steps - 1) as f32;
+  }
+
+  pub fn clamp(&self, value: f32) -> f32 {
+    return value.max(self.min).min(self.max);
+  }
+}
+
+pub enum SweepMode {
+  // Every combination of every range's steps
+  Grid,
+  // This many points drawn uniformly from the ranges, from the seed
+  Random(uint, u64),
+}
+
+// Every point a sweep will run, in order. The index into this is the point's id in the results.
+pub fn sweep_points(ranges: &[ParamRange], mode: &SweepMode) -> Vec<Vec<f32>> {
+  return match *mode {
+    SweepMode::Grid => {
+      let total = ranges.iter().fold(1u, |total, range| total * range.steps);
+      range(0, total).map(|point| {
+        // First range varies slowest
+        let mut rest = point;
+        let mut values = Vec::from_elem(ranges.len(), 0.0f32);
+        for (idx, range) in ranges.iter().enumerate().rev() {
+          values[idx] = range.grid_value(rest % range.steps);
+          rest = rest / range.steps;
+        }
+        values
+      }).collect()
+    },
+    SweepMode::Random(samples, seed) => {
+      let mut rng = SimRng::new(seed);
+      range(0, samples).map(|_| {
+        ranges.iter().map(|range| {
+          if range.max > range.min { rng.gen_range(range.min, range.max) } else { range.min }
+        }).collect()
+      }).collect()
+    },
+  };
+}
+
+pub fn apply_point(base: &Scenario, ranges: &[ParamRange], values: &[f32]) -> Scenario {
+  let mut scenario = base.clone();
+  for (range, &value) in ranges.iter().zip(values.iter()) {
+    range.param.apply(&mut scenario, value);
+  }
+  return scenario;
+}
+
+// How one headless run came out
+#[deriving(Clone)]
+pub struct RunSummary {
+  // After the final tick
+  pub metrics: SwarmMetrics,
+  pub total_collisions: u64,
+  // Simulated seconds until the swarm first held a ring about the first anchor, if it ever did
+  pub ring_time: Option<f32>,
+}
+
+// A swarm is in a ring once every entity sits within this fraction of the anchor's rest distance
+static RING_TOLERANCE: f32 = 0.2;
+
+pub static SUMMARY_COLUMNS: [&'static str, ..9] = [
+  "polarization", "angular_momentum", "mean_nearest_distance", "spread", "kinetic_energy", "potential_energy",
+  "collisions", "total_collisions", "ring_time",
+];
+
+impl RunSummary {
+  // One of SUMMARY_COLUMNS by name, None for an unknown name or a ring that never formed
+  pub fn column(&self, name: &str) -> Option<f32> {
+    let metrics = &self.metrics;
+    return match name {
+      "polarization" => Some(metrics.polarization),
+      "angular_momentum" => Some(metrics.angular_momentum),
+      "mean_nearest_distance" => Some(metrics.mean_nearest_distance),
+      "spread" => Some(metrics.spread),
+      "kinetic_energy" => Some(metrics.kinetic_energy),
+      "potential_energy" => Some(metrics.potential_energy),
+      "collisions" => Some(metrics.collisions as f32),
+      "total_collisions" => Some(self.total_collisions as f32),
+      "ring_time" => self.ring_time,
+      _ => None,
+    };
+  }
+
+  // In SUMMARY_COLUMNS order, a run that never formed a ring gets an empty ring_time
+  pub fn csv_fields(&self) -> Vec<String> {
+    let metrics = &self.metrics;
+    return vec![metrics.polarization.to_string(), metrics.angular_momentum.to_string(),
+                metrics.mean_nearest_distance.to_string(), metrics.spread.to_string(),
+                metrics.kinetic_energy.to_string(), metrics.potential_energy.to_string(),
+                metrics.collisions.to_string(), self.total_collisions.to_string(),
+                self.ring_time.map(|time| time.to_string()).unwrap_or(String::new())];
+  }
+}
+
+fn in_ring(field: &EntityField) -> bool {
+  let anchor = match field.anchors.iter().next() {
+    Some(anchor) => anchor,
+    None => return false,
+  };
+  if field.swarm.is_empty() {
+    return false;
+  }
+  return field.swarm.iter().all(|entity| {
+    (anchor.distance_to(entity.pos) - anchor.distance).abs() <= RING_TOLERANCE * anchor.distance
+  });
+}
+
+// A finished run, or why it couldn't finish. Messages never hold commas, they go in a CSV column.
+pub type RunResult = Result<RunSummary, String>;
+
+// Runs the scenario headless for its duration. A swarm that flies off the manifold stops
+// the run rather than tripping the manifold's bounds check.
+pub fn evaluate(scenario: &Scenario) -> RunResult {
+  let mut field = match scenario.build_field() {
+    Ok(field) => field,
+    Err(err) => return Err(format!("couldn't spawn the swarm: {}", err).replace(",", ";")),
+  };
+  let mut runner = FixedStepRunner::with_rate(scenario.tick_rate);
+  let mut total_collisions = 0u64;
+  let mut ring_time = None;
+  for _ in range(0, scenario.ticks()) {
+    if field.swarm.iter().any(|entity| !WorldManifold::in_bounds(entity.pos)) {
+      return Err(format!("an entity left the world after tick {}", field.ticks));
+    }
+    runner.run_ticks_with(&mut field, 1, |field| {
+      total_collisions = total_collisions + field.collisions as u64;
+      if ring_time.is_none() && in_ring(field) {
+        ring_time = Some(field.time);
+      }
+    });
+  }
+  return Ok(RunSummary{metrics: SwarmMetrics::measure(&field), total_collisions: total_collisions, ring_time: ring_time});
+}
+
+// Runs every job on up to threads workers, handing each result back as it lands. Results
+// come back in whatever order they finish, tagged with the job's id. Every worker holds
+// a whole field, so threads costs world_manifold::FIELD_BYTES each.
+pub fn run_parallel(jobs: Vec<(uint, Scenario)>, threads: uint, on_result: |uint, RunResult|) -> () {
+  let total = jobs.len();
+  // Workers pop from the back, so reversed to start with the first job
+  let mut jobs = jobs;
+  jobs.reverse();
+  let queue = Arc::new(Mutex::new(jobs));
+  let (results_tx, results_rx) = channel();
+
+  for _ in range(0, cmp::min(cmp::max(threads, 1), total)) {
+    let queue = queue.clone();
+    let results_tx = results_tx.clone();
+    spawn(proc() {
+      loop {
+        let job = queue.lock().pop();
+        match job {
+          Some((id, scenario)) => {
+            // Each run gets a task of its own, so a panic loses that one point and never
+            // happens while the queue is locked
+            let result = match task::try(proc() evaluate(&scenario)) {
+              Ok(result) => result,
+              Err(_) => Err("the simulation panicked".to_string()),
+            };
+            results_tx.send((id, result));
+          },
+          None => break,
+        }
+      }
+    });
+  }
+  // Only the workers hold senders now, so the results run out once they've all finished
+  drop(results_tx);
+
+  for (id, result) in results_rx.iter() {
+    on_result(id, result);
+  }
+}
+
+pub enum SweepError {
+  Io(IoError),
+  // The results file is from a sweep over different parameters
+  Mismatch(String),
+}
+
+impl fmt::Show for SweepError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      SweepError::Io(ref err) => write!(f, "{}", err),
+      SweepError::Mismatch(ref message) => write!(f, "{}", message),
+    }
+  }
+}
+
+fn results_header(ranges: &[ParamRange]) -> String {
+  let mut columns = vec!["point".to_string()];
+  columns.extend(ranges.iter().map(|range| range.param.name()));
+  columns.extend(SUMMARY_COLUMNS.iter().map(|column| column.to_string()));
+  // Empty unless the run failed, in which case the summary columns are
+  columns.push("error".to_string());
+  return columns.as_slice().connect(",");
+}
+
+// Points already in a results file from an interrupted run. Anything after the last complete
+// row is cut off so appending carries on cleanly. Every row's values have to be the ones this
+// sweep would give its point, otherwise the file came from different ranges, steps or seed.
+fn resume_results(path: &Path, header: &str, points: &[Vec<f32>]) -> Result<HashSet<uint>, SweepError> {
+  let text = match File::open(path).and_then(|mut file| file.read_to_string()) {
+    Ok(text) => text,
+    Err(ref err) if err.kind == IoErrorKind::FileNotFound => String::new(),
+    Err(err) => return Err(SweepError::Io(err)),
+  };
+
+  let mut rows: Vec<&str> = text.as_slice().split('\n').collect();
+  // Whatever follows the last newline is a row cut off mid write, or nothing
+  rows.pop();
+  match rows.as_slice().head() {
+    Some(&found) if found == header => (),
+    None => rows.push(header),
+    Some(_) => {
+      return Err(SweepError::Mismatch(format!("{} holds results for a different sweep, expected columns {}", path.display(), header)));
+    },
+  }
+
+  let columns = header.split(',').count();
+  let mut kept = vec![header];
+  let mut done = HashSet::new();
+  for &row in rows.iter().skip(1) {
+    let fields: Vec<&str> = row.split(',').collect();
+    let point: Option<uint> = fields.as_slice().head().and_then(|&field| FromStr::from_str(field));
+    let point = match point {
+      Some(point) if fields.len() == columns => point,
+      _ => continue,
+    };
+
+    let matches = point < points.len() && points[point].iter().zip(fields.iter().skip(1)).all(|(value, &field)| {
+      value.to_string().as_slice() == field
+    });
+    if !matches {
+      return Err(SweepError::Mismatch(format!("{} row for point {} doesn't match this sweep's ranges, steps or seed", path.display(), point)));
+    }
+    done.insert(point);
+    kept.push(row);
+  }
+
+  let mut rewritten = kept.as_slice().connect("\n");
+  rewritten.push('\n');
+  try!(File::create(path).and_then(|mut file| file.write_str(rewritten.as_slice())).map_err(|err| SweepError::Io(err)));
+  return Ok(done);
+}
+
+// Runs every point not already in the results file, appending a row as each finishes so
+// an interrupted sweep picks up where it stopped. Returns how many points were run now.
+pub fn run_sweep(base: &Scenario, ranges: &[ParamRange], mode: &SweepMode, threads: uint, results: &Path,
+                 on_progress: |uint, uint|) -> Result<uint, SweepError> {
+  let header = results_header(ranges);
+  let points = sweep_points(ranges, mode);
+  let done = try!(resume_results(results, header.as_slice(), points.as_slice()));
+
+  let jobs: Vec<(uint, Scenario)> = points.iter().enumerate()
+    .filter(|&(point, _)| !done.contains(&point))
+    .map(|(point, values)| (point, apply_point(base, ranges, values.as_slice())))
+    .collect();
+  let to_run = jobs.len();
+
+  let mut out = try!(File::open_mode(results, Append, Write).map_err(|err| SweepError::Io(err)));
+  let mut finished = 0u;
+  let mut error: Option<IoError> = None;
+  run_parallel(jobs, threads, |point, result| {
+    let mut fields = vec![point.to_string()];
+    fields.extend(points[point].iter().map(|value| value.to_string()));
+    match result {
+      Ok(summary) => {
+        fields.extend(summary.csv_fields().into_iter());
+        fields.push(String::new());
+      },
+      Err(message) => {
+        fields.extend(SUMMARY_COLUMNS.iter().map(|_| String::new()));
+        fields.push(message);
+      },
+    }
+    let written: IoResult<()> = writeln!(&mut out, "{}", fields.as_slice().connect(",")).and_then(|_| out.flush());
+    match written {
+      Err(err) => if error.is_none() { error = Some(err) },
+      Ok(()) => (),
+    }
+    finished = finished + 1;
+    on_progress(finished, to_run);
+  });
+
+  return match error {
+    Some(err) => Err(SweepError::Io(err)),
+    None => Ok(to_run),
+  };
+}
@@ -0,0 +1,92 @@
+extern crate cgmath;
+
+use swarm_ent::SwarmEnt;
+use entity_store::EntityId;
+
+use cgmath::{Vector, Vector3};
+
+use std::io::{BufferedWriter, File, IoResult};
+
+pub struct ForceContribution {
+  pub name: String,
+  // Already scaled by the force's registry weight and divided by the entity's mass
+  pub accel: Vector3<f32>,
+}
+
+pub struct ForceSample {
+  pub ent_id: EntityId,
+  pub pos: Vector3<f32>,
+  pub contributions: Vec<ForceContribution>,
+  pub total: Vector3<f32>,
+}
+
+impl ForceSample {
+  pub fn new(ent: &SwarmEnt, contributions: Vec<ForceContribution>) -> ForceSample {
+    let mut total = Vector3::new(0.0, 0.0, 0.0);
+    for contribution in contributions.iter() {
+      total = total.add_v(&contribution.accel);
+    }
+    return ForceSample{ent_id: ent.id, pos: ent.pos, contributions: contributions, total: total};
+  }
+}
+
+// Per-force accelerations of every entity on the most recent tick
+pub struct ForceTelemetry {
+  pub enabled: bool,
+  // The field tick the samples belong to, so rows line up with metrics and trajectories
+  pub tick: u64,
+  samples: Vec<ForceSample>,
+}
+
+impl ForceTelemetry {
+  pub fn new() -> ForceTelemetry {
+    return ForceTelemetry{enabled: false, tick: 0, samples: Vec::new()};
+  }
+
+  pub fn record(&mut self, tick: u64, samples: Vec<ForceSample>) -> () {
+    self.tick = tick;
+    self.samples = samples;
+  }
+
+  pub fn forget(&mut self, ent_id: EntityId) -> () {
+    self.samples.retain(|sample| sample.ent_id != ent_id);
+  }
+
+  pub fn samples(&self) -> &[ForceSample] {
+    return self.samples.as_slice();
+  }
+
+  pub fn for_entity(&self, ent_id: EntityId) -> Option<&ForceSample> {
+    return self.samples.iter().find(|sample| sample.ent_id == ent_id);
+  }
+
+  pub fn contribution(&self, ent_id: EntityId, name: &str) -> Option<Vector3<f32>> {
+    return self.for_entity(ent_id).and_then(|sample| {
+      sample.contributions.iter()
+        .find(|contribution| contribution.name.as_slice() == name)
+        .map(|contribution| contribution.accel)
+    });
+  }
+
+  pub fn write_csv<W: Writer>(&self, out: &mut W) -> IoResult<()> {
+    try!(writeln!(out, "tick,ent_id,force,ax,ay,az"));
+    for sample in self.samples.iter() {
+      for contribution in sample.contributions.iter() {
+        try!(ForceTelemetry::write_row(out, self.tick, sample.ent_id, contribution.name.as_slice(), contribution.accel));
+      }
+      try!(ForceTelemetry::write_row(out, self.tick, sample.ent_id, "total", sample.total));
+    }
+    return Ok(());
+  }
+
+  pub fn write_csv_file(&self, path: &Path) -> IoResult<()> {
+    let file = try!(File::create(path));
+    let mut out = BufferedWriter::new(file);
+    try!(self.write_csv(&mut out));
+    return out.flush();
+  }
+
+  fn write_row<W: Writer>(out: &mut W, tick: u64, ent_id: EntityId, name: &str, accel: Vector3<f32>) -> IoResult<()> {
+    return writeln!(out, "{},{},{},{},{},{}", tick, ent_id, name, accel.x, accel.y, accel.z);
+  }
+}
@@ -0,0 +1,296 @@
+extern crate cgmath;
+
+use entity_field::EntityField;
+use swarm_ent::SwarmEnt;
+
+use cgmath::Vector3;
+
+use std::io::{BufferedWriter, File, IoError, IoResult};
+
+static MAGIC: &'static [u8] = b"SWRMTRAJ";
+static VERSION: u32 = 1;
+// Rows held before a chunk is written out, so memory stays flat however long the run
+pub static DEFAULT_CHUNK_ROWS: uint = 4096;
+
+// Column types in the columnar header
+static TYPE_U64: u8 = 0;
+static TYPE_U32: u8 = 1;
+static TYPE_F32: u8 = 2;
+
+static COLUMNS: [(&'static str, u8), ..14] = [
+  ("tick", TYPE_U64), ("time", TYPE_F32), ("index", TYPE_U32), ("generation", TYPE_U32), ("group", TYPE_U32),
+  ("x", TYPE_F32), ("y", TYPE_F32), ("z", TYPE_F32),
+  ("vx", TYPE_F32), ("vy", TYPE_F32), ("vz", TYPE_F32),
+  ("ax", TYPE_F32), ("ay", TYPE_F32), ("az", TYPE_F32),
+];
+
+pub enum TrajectoryFormat {
+  // One row per entity per sample, with a header line
+  Csv,
+  // Header, then chunks. The header is the magic "SWRMTRAJ", a u32 version, a u32 column count,
+  // then for each column a u32 name length, the name and a u8 type (0 u64, 1 u32, 2 f32).
+  // Each chunk is a u32 row count followed by every column's values for those rows in turn.
+  // All little endian. The file is complete at every chunk boundary, there's no footer.
+  Columnar,
+}
+
+impl TrajectoryFormat {
+  // .csv gets CSV, anything else the columnar format
+  pub fn for_path(path: &Path) -> TrajectoryFormat {
+    return match path.extension_str() {
+      Some("csv") => TrajectoryFormat::Csv,
+      _ => TrajectoryFormat::Columnar,
+    };
+  }
+}
+
+// Which entities get sampled
+#[deriving(Clone)]
+pub enum EntitySelection {
+  All,
+  Groups(Vec<uint>),
+  // Store slot indices, stable for an entity's whole life
+  Slots(Vec<uint>),
+}
+
+impl EntitySelection {
+  pub fn includes(&self, entity: &SwarmEnt) -> bool {
+    return match *self {
+      EntitySelection::All => true,
+      EntitySelection::Groups(ref groups) => groups.contains(&entity.group),
+      EntitySelection::Slots(ref slots) => slots.contains(&entity.id.index),
+    };
+  }
+}
+
+struct Row {
+  tick: u64,
+  time: f32,
+  index: u32,
+  generation: u32,
+  group: u32,
+  pos: Vector3<f32>,
+  vel: Vector3<f32>,
+  accel: Vector3<f32>,
+}
+
+// Appends sampled entity state to a file as the simulation ticks. Call after_tick
+// after every tick, it keeps only the ticks that land on the interval.
+pub struct TrajectoryWriter<W> {
+  out: W,
+  format: TrajectoryFormat,
+  // Ticks between samples
+  pub every: u64,
+  pub selection: EntitySelection,
+  pub chunk_rows: uint,
+  pending: Vec<Row>,
+  rows: u64,
+  // The first write that failed, after which nothing more is written
+  error: Option<IoError>,
+}
+
+pub type TrajectoryFile = TrajectoryWriter<BufferedWriter<File>>;
+
+impl TrajectoryWriter<BufferedWriter<File>> {
+  pub fn create(path: &Path, every: u64, selection: EntitySelection) -> IoResult<TrajectoryFile> {
+    let file = try!(File::create(path));
+    return TrajectoryWriter::new(BufferedWriter::new(file), TrajectoryFormat::for_path(path), every, selection);
+  }
+}
+
+impl<W: Writer> TrajectoryWriter<W> {
+  pub fn new(out: W, format: TrajectoryFormat, every: u64, selection: EntitySelection) -> IoResult<TrajectoryWriter<W>> {
+    let mut writer = TrajectoryWriter{
+      out: out,
+      format: format,
+      every: if every == 0 { 1 } else { every },
+      selection: selection,
+      chunk_rows: DEFAULT_CHUNK_ROWS,
+      pending: Vec::new(),
+      rows: 0,
+      error: None,
+    };
+    try!(writer.write_header());
+    return Ok(writer);
+  }
+
+  // Rows written or waiting to be
+  pub fn rows(&self) -> u64 {
+    return self.rows;
+  }
+
+  pub fn after_tick(&mut self, field: &EntityField) -> () {
+    if self.error.is_some() || field.ticks % self.every != 0 {
+      return;
+    }
+
+    // accels runs in swarm order, but anything spawned since the tick isn't in it yet
+    let mut accels = field.accels.iter().peekable();
+    for entity in field.swarm.iter() {
+      let accel = match accels.peek() {
+        Some(&&(id, accel)) if id == entity.id => {
+          accels.next();
+          accel
+        },
+        _ => Vector3::new(0.0, 0.0, 0.0),
+      };
+      if !self.selection.includes(entity) {
+        continue;
+      }
+      self.pending.push(Row{
+        tick: field.ticks,
+        time: field.time,
+        index: entity.id.index as u32,
+        generation: entity.id.generation as u32,
+        group: entity.group as u32,
+        pos: entity.pos,
+        vel: entity.vel,
+        accel: accel,
+      });
+      self.rows = self.rows + 1;
+    }
+
+    if self.pending.len() >= self.chunk_rows {
+      let result = self.write_chunk();
+      self.keep_error(result);
+    }
+  }
+
+  // Writes whatever is still pending and hands back the output, or the first error hit along the way
+  pub fn finish(mut self) -> IoResult<W> {
+    let result = self.write_chunk();
+    self.keep_error(result);
+    let result = self.out.flush();
+    self.keep_error(result);
+    return match self.error {
+      Some(err) => Err(err),
+      None => Ok(self.out),
+    };
+  }
+
+  fn keep_error(&mut self, result: IoResult<()>) -> () {
+    match result {
+      Err(err) => if self.error.is_none() { self.error = Some(err) },
+      Ok(()) => (),
+    }
+  }
+
+  fn write_header(&mut self) -> IoResult<()> {
+    let out = &mut self.out;
+    return match self.format {
+      TrajectoryFormat::Csv => {
+        let names: Vec<&str> = COLUMNS.iter().map(|&(name, _)| name).collect();
+        writeln!(out, "{}", names.as_slice().connect(","))
+      },
+      TrajectoryFormat::Columnar => {
+        try!(out.write(MAGIC));
+        try!(out.write_le_u32(VERSION));
+        try!(out.write_le_u32(COLUMNS.len() as u32));
+        for &(name, kind) in COLUMNS.iter() {
+          try!(out.write_le_u32(name.len() as u32));
+          try!(out.write_str(name));
+          try!(out.write_u8(kind));
+        }
+        Ok(())
+      },
+    };
+  }
+
+  fn write_chunk(&mut self) -> IoResult<()> {
+    if self.pending.is_empty() {
+      return Ok(());
+    }
+    let rows = ::std::mem::replace(&mut self.pending, Vec::new());
+    let out = &mut self.out;
+    match self.format {
+      TrajectoryFormat::Csv => {
+        for row in rows.iter() {
+          try!(writeln!(out, "{},{},{},{},{},{},{},{},{},{},{},{},{},{}", row.tick, row.time, row.index, row.generation, row.group,
+                        row.pos.x, row.pos.y, row.pos.z, row.vel.x, row.vel.y, row.vel.z,
+                        row.accel.x, row.accel.y, row.accel.z));
+        }
+      },
+      TrajectoryFormat::Columnar => {
+        try!(out.write_le_u32(rows.len() as u32));
+        for row in rows.iter() { try!(out.write_le_u64(row.tick)); }
+        for row in rows.iter() { try!(out.write_le_f32(row.time)); }
+        for row in rows.iter() { try!(out.write_le_u32(row.index)); }
+        for row in rows.iter() { try!(out.write_le_u32(row.generation)); }
+        for row in rows.iter() { try!(out.write_le_u32(row.group)); }
+        for column in range(0u, 9) {
+          for row in rows.iter() {
+            let value = match column {
+              0 => row.pos.x, 1 => row.pos.y, 2 => row.pos.z,
+              3 => row.vel.x, 4 => row.vel.y, 5 => row.vel.z,
+              6 => row.accel.x, 7 => row.accel.y, _ => row.accel.z,
+            };
+            try!(out.write_le_f32(value));
+          }
+        }
+      },
+    }
+    // Readers can pick up every complete chunk while the run is still going
+    return out.flush();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{TrajectoryWriter, TrajectoryFormat, EntitySelection, COLUMNS, MAGIC, VERSION};
+  use fixed_step::FixedStepRunner;
+  use scenario::Scenario;
+
+  use std::io::{BufReader, MemWriter};
+
+  static ENTITIES: uint = 3;
+  static TICKS: uint = 3;
+  // Bytes per row in a columnar chunk, a u64 tick and thirteen 4 byte columns
+  static ROW_BYTES: uint = 8 + 13 * 4;
+
+  // ENTITIES rows a tick for TICKS ticks, a chunk written after each tick
+  fn write(format: TrajectoryFormat) -> Vec<u8> {
+    let scenario = Scenario::with_swarm_size(ENTITIES);
+    let mut field = scenario.build_field().ok().unwrap();
+    let mut runner = FixedStepRunner::with_rate(scenario.tick_rate);
+    let mut writer = TrajectoryWriter::new(MemWriter::new(), format, 1, EntitySelection::All).ok().unwrap();
+    writer.chunk_rows = ENTITIES;
+    runner.run_ticks_with(&mut field, TICKS, |field| writer.after_tick(field));
+    assert_eq!(writer.rows(), (ENTITIES * TICKS) as u64);
+    return writer.finish().ok().unwrap().unwrap();
+  }
+
+  #[test]
+  fn csv_has_a_header_and_a_line_per_row() {
+    let text = String::from_utf8(write(TrajectoryFormat::Csv)).ok().unwrap();
+    let lines: Vec<&str> = text.as_slice().lines().collect();
+    let names: Vec<&str> = COLUMNS.iter().map(|&(name, _)| name).collect();
+    assert_eq!(lines[0], names.as_slice().connect(",").as_slice());
+    assert_eq!(lines.len(), 1 + ENTITIES * TICKS);
+    assert!(lines[1].starts_with("1,"));
+    assert!(lines[lines.len() - 1].starts_with(format!("{},", TICKS).as_slice()));
+  }
+
+  #[test]
+  fn columnar_has_a_header_then_a_chunk_per_tick() {
+    let bytes = write(TrajectoryFormat::Columnar);
+    let mut input = BufReader::new(bytes.as_slice());
+    assert_eq!(input.read_exact(MAGIC.len()).ok().unwrap().as_slice(), MAGIC);
+    assert_eq!(input.read_le_u32().ok().unwrap(), VERSION);
+    assert_eq!(input.read_le_u32().ok().unwrap(), COLUMNS.len() as u32);
+    for &(name, kind) in COLUMNS.iter() {
+      let len = input.read_le_u32().ok().unwrap() as uint;
+      assert_eq!(input.read_exact(len).ok().unwrap().as_slice(), name.as_bytes());
+      assert_eq!(input.read_u8().ok().unwrap(), kind);
+    }
+
+    for tick in range(1, TICKS + 1) {
+      assert_eq!(input.read_le_u32().ok().unwrap(), ENTITIES as u32);
+      // The tick column comes first, every row in the chunk shares one
+      for _ in range(0, ENTITIES) {
+        assert_eq!(input.read_le_u64().ok().unwrap(), tick as u64);
+      }
+      input.read_exact(ENTITIES * (ROW_BYTES - 8)).ok().unwrap();
+    }
+    assert!(input.eof());
+  }
+}
@@ -0,0 +1,616 @@
+// Graphics
+extern crate cgmath;
+extern crate gfx;
+extern crate device;
+extern crate render;
+extern crate glfw;
+extern crate genmesh;
+extern crate time;
+
+use swarm::entity_field::EntityField;
+use swarm::scenario::SpawnGroup;
+use swarm::replay::{Input, Recorder};
+use swarm::fixed_step::FixedStepRunner;
+use swarm::snapshot::Snapshot;
+use swarm::trajectory::TrajectoryFile;
+use swarm::metrics::{MetricsFile, MetricsLog, SwarmMetrics};
+use swarm::spawn::{Distribution, EntitySpec};
+use swarm::kinematics::{Kinematics, heading_dir};
+use swarm::behaviour::{BehaviourState, state_color};
+use cli::{CliError, Options};
+
+use cgmath::FixedArray;
+use cgmath:: {Matrix4, Point3};
+use cgmath::{Vector, Vector2, Vector3};
+use cgmath::{Transform, AffineMatrix3};
+use gfx::{Device, DeviceHelper, ToSlice};
+use device::BufferUsage;
+use render::mesh::Mesh;
+use glfw::Context;
+
+use genmesh::{Vertices, Triangulate};
+use genmesh::generators::{Plane, SharedVertex, IndexedPolygon};
+
+use std::io::File;
+use std::num::Float;
+
+// Graphics
+#[vertex_format]
+struct Vertex {
+  #[name = "u_pos"]
+  pos: [f32, ..3],
+
+  #[name = "u_normal"]
+  normal: [f32, ..3],
+
+  #[name = "u_uv"]
+  uv: [f32, ..2],
+}
+
+#[shader_param(Entity)]
+struct Params {
+  #[name= "u_Model"]
+  model: [[f32, ..4], ..4],
+
+  #[name= "u_View"]
+  view: [[f32, ..4], ..4],
+
+  #[name= "u_Proj"]
+  proj: [[f32, ..4], ..4],
+
+  #[name= "t_Color"]
+  color: gfx::shade::TextureParam,
+
+  #[name = "world_light_pos"]
+  light_pos: [f32, ..3],
+}
+
+// --------- Main -----------
+
+static ARROW_SCALE: f32 = 0.1;
+static ARROW_MARKERS: uint = 6;
+static ARROW_MARKER_SIZE: f32 = 0.15;
+// HUD bars, in screen units where the window spans -1..1
+static HUD_LEFT: f32 = -0.95;
+static HUD_TOP: f32 = 0.9;
+static HUD_ROW: f32 = 0.06;
+static HUD_BAR_HEIGHT: f32 = 0.04;
+static HUD_BAR_WIDTH: f32 = 0.5;
+// F5 saves here, F9 loads it back
+static SNAPSHOT_FILE: &'static str = "snapshot.bin";
+
+// Force arrows are drawn as a trail of small cubes leading away from the entity
+fn arrow_markers(origin: Vector3<f32>, accel: Vector3<f32>) -> Vec<Matrix4<f32>> {
+  let arrow = accel.mul_s(ARROW_SCALE);
+  return range(1, ARROW_MARKERS + 1).map(|step| {
+    scaled_model(origin.add_v(&arrow.mul_s(step as f32 / ARROW_MARKERS as f32)), ARROW_MARKER_SIZE)
+  }).collect();
+}
+
+// Each metric's latest value as a fraction of the largest seen in the history, so bars show the trend
+fn hud_fractions(log: &MetricsLog) -> Vec<f32> {
+  let samples = log.samples();
+  let values = |sample: &SwarmMetrics| -> Vec<f32> {
+    vec![sample.polarization, sample.angular_momentum.abs(), sample.mean_nearest_distance, sample.spread,
+         sample.kinetic_energy, sample.potential_energy, sample.collisions as f32]
+  };
+  let latest = match samples.last() {
+    Some(sample) => values(*sample),
+    None => return Vec::new(),
+  };
+  let mut largest = Vec::from_elem(latest.len(), 0.0f32);
+  for sample in samples.iter() {
+    for (max, value) in largest.iter_mut().zip(values(*sample).into_iter()) {
+      *max = max.max(value);
+    }
+  }
+  // Polarization already runs 0..1
+  largest[0] = 1.0;
+  return latest.iter().zip(largest.iter()).map(|(&value, &max)| if max > 0.0 { value / max } else { 0.0 }).collect();
+}
+
+// A cube stretched into a bar from (left, centre_y), full width and height given
+fn bar_model(left: f32, centre_y: f32, width: f32, height: f32) -> Matrix4<f32> {
+  return Matrix4::new(width / 2.0, 0.0, 0.0, 0.0,
+                      0.0, height / 2.0, 0.0, 0.0,
+                      0.0, 0.0, 0.01, 0.0,
+                      left + width / 2.0, centre_y, 0.0, 1.0);
+}
+
+// The cube model spans -1..1, so the scale is the half width
+fn scaled_model(pos: Vector3<f32>, s: f32) -> Matrix4<f32> {
+  return oriented_model(pos, 0.0, s);
+}
+
+// Scaled, then turned about z by heading, then moved into place
+fn oriented_model(pos: Vector3<f32>, heading: f32, s: f32) -> Matrix4<f32> {
+  let c = ::std::num::FloatMath::cos(heading) * s;
+  let n = ::std::num::FloatMath::sin(heading) * s;
+  return Matrix4::new(  c,   n, 0.0, 0.0,
+                       -n,   c, 0.0, 0.0,
+                      0.0, 0.0,   s, 0.0,
+                      pos.x, pos.y, pos.z, 1.0);
+}
+
+fn generate_model() -> Vec<Vertex> {
+  vec![
+      Vertex { pos: [-1.0, -1.0,  1.0], normal: [0.0, 0.0, 1.0], uv: [0.0, 0.0]},
+      Vertex { pos: [ 1.0, -1.0,  1.0], normal: [0.0, 0.0, 1.0], uv: [0.0, 1.0]},
+      Vertex { pos: [ 1.0,  1.0,  1.0], normal: [0.0, 0.0, 1.0], uv: [1.0, 0.0]},
+      Vertex { pos: [-1.0,  1.0,  1.0], normal: [0.0, 0.0, 1.0], uv: [1.0, 1.0]},
+
+      // bottom (0, 0, -1)
+      Vertex { pos: [-1.0,  1.0, -1.0], normal: [0.0, 0.0, -1.0], uv: [0.0, 0.0]},
+      Vertex { pos: [ 1.0,  1.0, -1.0], normal: [0.0, 0.0, -1.0], uv: [0.0, 1.0]},
+      Vertex { pos: [ 1.0, -1.0, -1.0], normal: [0.0, 0.0, -1.0], uv: [1.0, 0.0]},
+      Vertex { pos: [-1.0, -1.0, -1.0], normal: [0.0, 0.0, -1.0], uv: [1.0, 1.0]},
+      // right (1, 0, 0)
+      Vertex { pos: [ 1.0, -1.0, -1.0], normal: [1.0, 0.0, 0.0], uv: [0.0, 0.0]},
+      Vertex { pos: [ 1.0,  1.0, -1.0], normal: [1.0, 0.0, 0.0], uv: [0.0, 1.0]},
+      Vertex { pos: [ 1.0,  1.0,  1.0], normal: [1.0, 0.0, 0.0], uv: [1.0, 0.0]},
+      Vertex { pos: [ 1.0, -1.0,  1.0], normal: [1.0, 0.0, 0.0], uv: [1.0, 1.0]},
+      // left (-1, 0, 0)
+      Vertex { pos: [-1.0, -1.0,  1.0], normal: [-1.0, 0.0, 0.0], uv: [0.0, 0.0]},
+      Vertex { pos: [-1.0,  1.0,  1.0], normal: [-1.0, 0.0, 0.0], uv: [0.0, 1.0]},
+      Vertex { pos: [-1.0,  1.0, -1.0], normal: [-1.0, 0.0, 0.0], uv: [1.0, 0.0]},
+      Vertex { pos: [-1.0, -1.0, -1.0], normal: [-1.0, 0.0, 0.0], uv: [1.0, 1.0]},
+      // front (0, 1, 0)
+      Vertex { pos: [ 1.0,  1.0, -1.0], normal: [0.0, 1.0, 0.0], uv: [0.0, 0.0]},
+      Vertex { pos: [-1.0,  1.0, -1.0], normal: [0.0, 1.0, 0.0], uv: [0.0, 1.0]},
+      Vertex { pos: [-1.0,  1.0,  1.0], normal: [0.0, 1.0, 0.0], uv: [1.0, 0.0]},
+      Vertex { pos: [ 1.0,  1.0,  1.0], normal: [0.0, 1.0, 0.0], uv: [1.0, 1.0]},
+      // back (0, -1, 0)
+      Vertex { pos: [ 1.0, -1.0,  1.0], normal: [0.0, -1.0, 0.0], uv: [0.0, 0.0]},
+      Vertex { pos: [-1.0, -1.0,  1.0], normal: [0.0, -1.0, 0.0], uv: [0.0, 1.0]},
+      Vertex { pos: [-1.0, -1.0, -1.0], normal: [0.0, -1.0, 0.0], uv: [1.0, 0.0]},
+      Vertex { pos: [ 1.0, -1.0, -1.0], normal: [0.0, -1.0, 0.0], uv: [1.0, 1.0]},
+  ]
+}
+
+// With a recorder, every tick and input is recorded and written to record_to once the window closes
+pub fn run(options: &Options, field: EntityField, runner: FixedStepRunner, trajectory: Option<TrajectoryFile>,
+           metrics: Option<MetricsFile>, recorder: Option<Recorder>, record_to: Option<&Path>) -> Result<(), CliError> {
+  let glfw = match glfw::init(glfw::FAIL_ON_ERRORS) {
+    Ok(glfw) => glfw,
+    Err(err) => return Err(CliError::Graphics(format!("Couldn't start GLFW: {}", err))),
+  };
+
+  glfw.window_hint(glfw::ContextVersion(3, 2));
+  glfw.window_hint(glfw::OpenglForwardCompat(true));
+  glfw.window_hint(glfw::OpenglProfile(glfw::OpenGlProfileHint::Core));
+
+  let (window, events) = match glfw.create_window(options.width, options.height, "Physics example", glfw::Windowed) {
+    Some(created) => created,
+    None => return Err(CliError::Graphics(format!("Couldn't create a {}x{} window", options.width, options.height))),
+  };
+
+  window.make_current();
+  glfw.set_error_callback(glfw::FAIL_ON_ERRORS);
+  window.set_key_polling(true);
+
+  let (w, h) = window.get_framebuffer_size();
+  let frame = gfx::Frame::new(w as u16, h as u16);
+
+  let mut device = gfx::GlDevice::new(|s| window.get_proc_address(s));
+
+  let ent_data: Vec<Vertex> = generate_model();
+  let anchor_data: Vec<Vertex> = generate_model();
+
+  let ent_mesh = device.create_mesh(ent_data.as_slice());
+  let anchor_mesh = device.create_mesh(anchor_data.as_slice());
+
+  let plane = Plane::subdivide(512,512);
+  let mut plane_vertex_data: Vec<Vertex> = plane.shared_vertex_iter()
+      .map(|(x, y)| {
+        Vertex{
+          pos: [ x*100.0, y*100.0, 1.0],
+          normal: [0.0, 0.0, 1.0],
+          uv: [0.7, 0.7]
+        }
+      })
+      .collect();
+
+  let plane_index_data: Vec<u32> = plane.indexed_polygon_iter()
+      .triangulate()
+      .vertices()
+      .map(|i| i as u32)
+      .collect();
+
+  // Using direct instantiation instead of easy mode helpers, these are static though
+  let mut plane_idx_buffer = device.create_buffer::<u32>(plane_index_data.len(), device::BufferUsage::Static);
+  device.update_buffer(plane_idx_buffer, plane_index_data.as_slice(), 0u);
+  let plane_slice = plane_idx_buffer.to_slice(gfx::TriangleList);
+
+  // Dynamic vertices here
+  let mut plane_vert_buffer = device.create_buffer(plane_vertex_data.len(), device::BufferUsage::Stream);
+  device.update_buffer(plane_vert_buffer, plane_vertex_data.as_slice(), 0u);
+  let plane_mesh = render::mesh::Mesh::from_format(plane_vert_buffer, plane_vertex_data.len() as device::VertexCount);
+
+  let index_data: Vec<u32> = vec![
+       0,  1,  2,  2,  3,  0, // top
+       4,  5,  6,  6,  7,  4, // bottom
+       8,  9, 10, 10, 11,  8, // right
+       12, 13, 14, 14, 15, 12, // left
+       16, 17, 18, 18, 19, 16, // front
+       20, 21, 22, 22, 23, 20, // back
+  ];
+
+  let slice = device
+      .create_buffer_static::<u32>(index_data.as_slice())
+      .to_slice(gfx::TriangleList);
+
+  let texture_info = gfx::tex::TextureInfo {
+    width: 1,
+    height: 1,
+    depth: 1,
+    levels: 1,
+    kind: gfx::tex::Texture2D,
+    format: gfx::tex::RGBA8,
+  };
+
+  let image_info = texture_info.to_image_info();
+  let texture = match device.create_texture(texture_info) {
+    Ok(texture) => texture,
+    Err(err) => return Err(CliError::Graphics(format!("Couldn't create a texture: {}", err))),
+  };
+  match device.update_texture(&texture, &image_info, &[0x20u8, 0xA0u8, 0xC0u8, 0x00u8]) {
+    Ok(()) => (),
+    Err(err) => return Err(CliError::Graphics(format!("Couldn't fill a texture: {}", err))),
+  }
+
+  let sampler = device.create_sampler(
+    gfx::tex::SamplerInfo::new(gfx::tex::Bilinear, gfx::tex::Clamp)
+  );
+
+  // One flat colour per behaviour state, indexed by BehaviourState::index
+  let mut state_colors: Vec<gfx::shade::TextureParam> = Vec::new();
+  for state in BehaviourState::all().iter() {
+    let state_texture = match device.create_texture(texture_info) {
+      Ok(texture) => texture,
+      Err(err) => return Err(CliError::Graphics(format!("Couldn't create a texture: {}", err))),
+    };
+    match device.update_texture(&state_texture, &image_info, &state_color(*state)) {
+      Ok(()) => (),
+      Err(err) => return Err(CliError::Graphics(format!("Couldn't fill a texture: {}", err))),
+    }
+    state_colors.push((state_texture, Some(sampler)));
+  }
+
+  let vertex_shader_text = try!(read_shader(&options.vertex_shader));
+  let fragment_shader_text = try!(read_shader(&options.fragment_shader));
+
+  let vertex_shader: gfx::ShaderSource = shaders! {
+    GLSL_150: vertex_shader_text.as_slice()
+  };
+
+  let fragment_shader: gfx::ShaderSource = shaders! {
+    GLSL_150: fragment_shader_text.as_slice()
+  };
+
+  let program = match device.link_program(vertex_shader.clone(), fragment_shader.clone()) {
+    Ok(program) => program,
+    Err(err) => return Err(CliError::Graphics(format!("Couldn't build the shader program: {}", err))),
+  };
+
+  let state = gfx::DrawState::new().depth(gfx::state::LessEqual, true);
+  let mut graphics = gfx::Graphics::new(device);
+
+  let ent_batch: Entity = match graphics.make_batch(&program, &ent_mesh, slice, &state) {
+    Ok(batch) => batch,
+    Err(err) => return Err(CliError::Graphics(format!("Couldn't make the ent batch: {}", err))),
+  };
+  let anchor_batch: Entity = match graphics.make_batch(&program, &anchor_mesh, slice, &state) {
+    Ok(batch) => batch,
+    Err(err) => return Err(CliError::Graphics(format!("Couldn't make the anchor batch: {}", err))),
+  };
+  let plane_batch: Entity = match graphics.make_batch(&program, &plane_mesh, plane_slice, &state) {
+    Ok(batch) => batch,
+    Err(err) => return Err(CliError::Graphics(format!("Couldn't make the plane batch: {}", err))),
+  };
+
+  let aspect = w as f32 / h as f32;
+  let mut data = Params {
+      light_pos: Vector3::new(0.0, 0.0, -3.0).into_fixed(),
+      color: (texture, Some(sampler)),
+      model: Matrix4::identity().into_fixed(),
+      view: Matrix4::identity().into_fixed(),
+      proj: cgmath::perspective(cgmath::deg(60.0f32), aspect, 0.1, 1000.0).into_fixed(),
+  };
+
+  let clear_data = gfx::ClearData {
+      color: [0.1, 0.1, 0.1, 1.0],
+      depth: 1.0,
+      stencil: 0,
+  };
+
+  let mut last_time = time::precise_time_ns();
+  let mut everything = field;
+  let mut runner = runner;
+  let mut recorder = recorder;
+  let mut trajectory = trajectory;
+  let mut metrics = metrics;
+  let mut show_hud = false;
+  ::configure_metrics(options, &mut everything, show_hud);
+  // Someone is watching, so say when entities change state
+  everything.log_transitions = true;
+  println!("Seed: {}", everything.rng.seed());
+  let mut camera_setting = 0.0;
+  let mut range_setting = 16.0;
+
+  let mut going_left = false;
+  let mut going_right = false;
+  let mut going_fore = false;
+  let mut going_back = false;
+
+  while !window.should_close() {
+
+    for vertex in plane_vertex_data.iter_mut() {
+      let height = everything.world.height_at(Vector2::new(vertex.pos[0], vertex.pos[1]));
+      vertex.pos = [vertex.pos[0], vertex.pos[1], height/20.0];
+    }
+    graphics.device.update_buffer(plane_vert_buffer, plane_vertex_data.as_slice(), 0u);
+
+    let current_time = time::precise_time_ns();
+    let delta_t = ((current_time - last_time) as f32) / 1_000_000_000.0 ;
+    last_time = current_time;
+
+    runner.advance_with(&mut everything, delta_t, |field| {
+      match recorder {
+        Some(ref mut recorder) => recorder.after_tick(field),
+        None => (),
+      }
+      match trajectory {
+        Some(ref mut trajectory) => trajectory.after_tick(field),
+        None => (),
+      }
+      match metrics {
+        Some(ref mut metrics) => metrics.after_tick(field),
+        None => (),
+      }
+    });
+
+    if going_left  {
+      camera_setting = camera_setting + (1.5 * delta_t)
+    }
+
+    if going_right  {
+      camera_setting = camera_setting - (1.5 * delta_t)
+    }
+
+    if going_fore  {
+      range_setting = range_setting - (15.0 * delta_t)
+    }
+
+    if going_back  {
+      range_setting = range_setting + (15.0 * delta_t)
+    }
+
+
+    glfw.poll_events();
+    for (_, event) in glfw::flush_messages(&events) {
+      match event {
+        glfw::KeyEvent(glfw::Key::Escape, _, glfw::Press, _) =>
+          window.set_should_close(true),
+        glfw::KeyEvent(glfw::Key::H, _, glfw::Press, _) =>
+          going_left = true,
+        glfw::KeyEvent(glfw::Key::L, _, glfw::Press, _) =>
+          going_right = true,
+        glfw::KeyEvent(glfw::Key::H, _, glfw::Release, _) =>
+          going_left = false,
+        glfw::KeyEvent(glfw::Key::L, _, glfw::Release, _) =>
+          going_right = false,
+        glfw::KeyEvent(glfw::Key::J, _, glfw::Press, _) =>
+          going_fore = true,
+        glfw::KeyEvent(glfw::Key::K, _, glfw::Press, _) =>
+          going_back = true,
+        glfw::KeyEvent(glfw::Key::J, _, glfw::Release, _) =>
+          going_fore = false,
+        glfw::KeyEvent(glfw::Key::K, _, glfw::Release, _) =>
+          going_back = false,
+        glfw::KeyEvent(glfw::Key::Z, _, glfw::Press, _) => {
+          for &(ent_id, dominant) in everything.dominant_zones.iter() {
+            match dominant {
+              Some(zone_idx) => println!("ent {}: dominated by zone {}", ent_id, zone_idx),
+              None => println!("ent {}: no zone", ent_id),
+            }
+          }
+        },
+        glfw::KeyEvent(glfw::Key::N, _, glfw::Press, _) =>
+          apply_input(&mut everything, &mut recorder, Input::Spawn(cluster_spawn(None))),
+        glfw::KeyEvent(glfw::Key::M, _, glfw::Press, _) =>
+          // Same as N, but running the default state machine
+          apply_input(&mut everything, &mut recorder, Input::Spawn(cluster_spawn(Some(0)))),
+        glfw::KeyEvent(glfw::Key::D, _, glfw::Press, _) => {
+          // Lowest slot first. Freed slots get reused, so this isn't necessarily the oldest entity.
+          match everything.swarm.ids().iter().next() {
+            Some(&id) => apply_input(&mut everything, &mut recorder, Input::Despawn(id)),
+            None => (),
+          }
+        },
+        glfw::KeyEvent(glfw::Key::B, _, glfw::Press, _) => {
+          // Cycle field only -> boids only -> half and half
+          let (manifold, boids) = match everything.forces.weight("boids") {
+            Some(weight) if weight == 0.0 => (0.0, 1.0),
+            Some(weight) if weight == 1.0 => (0.1, 0.5),
+            _ => (0.2, 0.0),
+          };
+          apply_input(&mut everything, &mut recorder, Input::SetForceWeight("manifold".to_string(), manifold));
+          apply_input(&mut everything, &mut recorder, Input::SetForceWeight("boids".to_string(), boids));
+          println!("manifold weight {}, boids weight {}", manifold, boids);
+        },
+        glfw::KeyEvent(glfw::Key::X, _, glfw::Press, _) => {
+          let enabled = !everything.collisions_enabled;
+          apply_input(&mut everything, &mut recorder, Input::SetCollisions(enabled));
+        },
+        glfw::KeyEvent(glfw::Key::T, _, glfw::Press, _) =>
+          everything.telemetry.enabled = !everything.telemetry.enabled,
+        glfw::KeyEvent(glfw::Key::C, _, glfw::Press, _) => {
+          match everything.telemetry.write_csv_file(&Path::new("force_telemetry.csv")) {
+            Ok(()) => println!("Wrote force telemetry to force_telemetry.csv"),
+            Err(e) => println!("Failed to write force telemetry: {}", e),
+          }
+        },
+        glfw::KeyEvent(glfw::Key::R, _, glfw::Press, _) => {
+          apply_input(&mut everything, &mut recorder, Input::Scatter(10.0));
+          runner.sync(&everything);
+        },
+        glfw::KeyEvent(glfw::Key::G, _, glfw::Press, _) => {
+          // Bars top to bottom: polarization, angular momentum, nearest neighbour distance,
+          // spread, kinetic energy, spring energy, collisions
+          show_hud = !show_hud;
+          ::configure_metrics(options, &mut everything, show_hud);
+          match everything.metrics.latest() {
+            Some(metrics) => println!("{}", metrics),
+            None => (),
+          }
+        },
+        glfw::KeyEvent(glfw::Key::F5, _, glfw::Press, _) => {
+          match Snapshot::capture(&everything, runner.step).save(&Path::new(SNAPSHOT_FILE)) {
+            Ok(()) => println!("Saved tick {} to {}", everything.ticks, SNAPSHOT_FILE),
+            Err(e) => println!("Failed to save snapshot: {}", e),
+          }
+        },
+        glfw::KeyEvent(glfw::Key::F9, _, glfw::Press, _) => {
+          // A recording can only be replayed from its scenario, so no jumping about mid recording
+          if recorder.is_some() {
+            println!("Can't load a snapshot while recording");
+          } else {
+            match Snapshot::load(&Path::new(SNAPSHOT_FILE)).and_then(|snapshot| {
+              snapshot.restore().map(|field| (field, snapshot.step))
+            }) {
+              Ok((field, step)) => {
+                everything = field;
+                ::configure_metrics(options, &mut everything, show_hud);
+                runner = FixedStepRunner::with_step(step);
+                println!("Loaded tick {} from {}", everything.ticks, SNAPSHOT_FILE);
+              },
+              Err(e) => println!("Failed to load snapshot: {}", e),
+            }
+          }
+        },
+        _ => {},
+      }
+    }
+
+    let x = ::std::num::FloatMath::sin(camera_setting);
+    let y = ::std::num::FloatMath::cos(camera_setting);
+
+    let view:AffineMatrix3<f32> = Transform::look_at(
+      &Point3::new(2.0*range_setting*x, 2.0*range_setting*y, range_setting),
+      &Point3::new(0.0, 0.0, 0.0),
+      &Vector3::unit_z(),
+    );
+    data.view = view.mat.into_fixed();
+
+    graphics.clear(clear_data, gfx::COLOR | gfx::DEPTH, &frame);
+    
+    // Draw plane
+    data.model = Matrix4::from_translation(&Vector3::new(0.0, 0.0, -10.0)).into_fixed();
+    graphics.draw(&plane_batch, &data, &frame);
+
+    // Draw anchors
+    for anchor in everything.anchors.iter() {
+      data.model = Matrix4::from_translation(&anchor.pos).into_fixed();
+      graphics.draw(&anchor_batch, &data, &frame);
+    }
+
+    // Draw entities
+    let ent_positions = runner.interpolated_positions(&everything);
+    for (entity, ent_pos) in everything.swarm.iter().zip(ent_positions.iter()) {
+      data.color = match entity.behaviour {
+        Some(status) => state_colors[status.state.index()],
+        None => (texture, Some(sampler)),
+      };
+      data.model = oriented_model(*ent_pos, entity.heading, entity.radius).into_fixed();
+      graphics.draw(&ent_batch, &data, &frame);
+
+      // Vehicles get a nose so you can tell which way they're facing
+      match entity.kinematics {
+        Kinematics::PointMass => (),
+        _ => {
+          let nose = ent_pos.add_v(&heading_dir(entity.heading).mul_s(1.3 * entity.radius));
+          data.model = oriented_model(nose, entity.heading, ARROW_MARKER_SIZE).into_fixed();
+          graphics.draw(&ent_batch, &data, &frame);
+        },
+      }
+    }
+
+    data.color = (texture, Some(sampler));
+
+    // Draw force arrows
+    if everything.telemetry.enabled {
+      for sample in everything.telemetry.samples().iter() {
+        for contribution in sample.contributions.iter() {
+          for marker in arrow_markers(sample.pos, contribution.accel).iter() {
+            data.model = marker.into_fixed();
+            graphics.draw(&ent_batch, &data, &frame);
+          }
+        }
+      }
+    }
+
+    // Draw metrics HUD, flat on the screen over everything else
+    if show_hud {
+      let scene_view = data.view;
+      let scene_proj = data.proj;
+      data.view = Matrix4::identity().into_fixed();
+      data.proj = Matrix4::identity().into_fixed();
+      graphics.clear(clear_data, gfx::DEPTH, &frame);
+      for (row, fraction) in hud_fractions(&everything.metrics).iter().enumerate() {
+        data.color = state_colors[row % state_colors.len()];
+        let centre_y = HUD_TOP - row as f32 * HUD_ROW;
+        data.model = bar_model(HUD_LEFT, centre_y, HUD_BAR_WIDTH * fraction.max(0.005), HUD_BAR_HEIGHT).into_fixed();
+        graphics.draw(&ent_batch, &data, &frame);
+      }
+      data.color = (texture, Some(sampler));
+      data.view = scene_view;
+      data.proj = scene_proj;
+    }
+
+    graphics.end_frame();
+
+    window.swap_buffers();
+  }
+
+  try!(::finish_trajectory(options, trajectory));
+  try!(::finish_metrics(options, metrics));
+  match (recorder, record_to) {
+    (Some(recorder), Some(path)) => {
+      let recording = recorder.finish();
+      match recording.write_file(path) {
+        Ok(()) => println!("Recorded {} ticks to {}", recording.ticks(), path.display()),
+        Err(err) => return Err(CliError::Io(path.clone(), err)),
+      }
+    },
+    _ => (),
+  }
+  return Ok(());
+}
+
+// Anything that changes the simulation goes through here so a recording sees it
+fn apply_input(field: &mut EntityField, recorder: &mut Option<Recorder>, input: Input) -> () {
+  match *recorder {
+    Some(ref mut recorder) => match recorder.apply(field, input) {
+      Ok(()) => (),
+      Err(e) => println!("Failed to record input: {}", e),
+    },
+    None => input.apply(field),
+  }
+}
+
+// Ten entities in a Gaussian cluster about the origin
+fn cluster_spawn(behaviour: Option<uint>) -> SpawnGroup {
+  return SpawnGroup{
+    count: 10,
+    distribution: Some(Distribution::GaussianCluster(Vector3::new(0.0, 0.0, 0.0), 3.0)),
+    spec: EntitySpec::default(),
+    behaviour: behaviour,
+  };
+}
+
+fn read_shader(path: &Path) -> Result<Vec<u8>, CliError> {
+  return match File::open(path).read_to_end() {
+    Ok(text) => Ok(text),
+    Err(err) => Err(CliError::Io(path.clone(), err)),
+  };
+}
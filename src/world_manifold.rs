@@ -3,14 +3,21 @@ extern crate cgmath;
 use cgmath::{Vector2, Vector3};
 use std::collections::HashMap;
 use std::num::Float;
-use std::num::SignedInt;
 
 static SQRT_2: f32 = 1.414213562;
-
+// Sets the "spread"
+pub static KERNEL_SIGMA: f32 = 2.0;
+// Caps the range of the gaussian
+static KERNEL_RANGE: uint = 8;
+// What the field array costs, and so every EntityField, about 400MB
+pub static FIELD_BYTES: uint = 10000 * 10000 * 4;
+
+#[deriving(Clone)]
 pub struct Deformation {
-  magnitude: int,
-  x: f32,
-  y: f32
+  pub magnitude: int,
+  pub group: uint,
+  pub x: f32,
+  pub y: f32
 }
 
 pub struct WorldManifold {
@@ -18,27 +25,67 @@ pub struct WorldManifold {
   field: Box<[[f32, ..10000], ..10000]>,
   deform_memo: HashMap<int, Vec<Vec<f32>>>,
   deform_stack: Vec<Deformation>,
+  // Whether deform keeps group_layers up to date. Off saves a map update per kernel cell
+  // for every entity, worth it whenever every group sees the manifold the same way.
+  pub split_groups: bool,
+  // The same heights as field, split out by group. Sparse, since a full layer each would
+  // cost as much as the field itself.
+  group_layers: Vec<HashMap<(uint, uint), f32>>,
+  kernel_sigma: f32,
 }
 
 impl WorldManifold {
   pub fn default() -> WorldManifold {
-    return WorldManifold{field: box [[0.0, ..10000], ..10000], deform_memo: HashMap::new(), deform_stack: Vec::new()};
+    return WorldManifold::with_sigma(KERNEL_SIGMA);
+  }
+
+  pub fn with_sigma(kernel_sigma: f32) -> WorldManifold {
+    return WorldManifold{field: box [[0.0, ..10000], ..10000], deform_memo: HashMap::new(), deform_stack: Vec::new(), split_groups: false, group_layers: Vec::new(), kernel_sigma: kernel_sigma};
   }
 
+  pub fn kernel_sigma(&self) -> f32 {
+    return self.kernel_sigma;
+  }
+
+  // Only takes effect for deformations made from now on, flatten first to change it cleanly
+  pub fn set_kernel_sigma(&mut self, kernel_sigma: f32) -> () {
+    self.kernel_sigma = kernel_sigma;
+    self.deform_memo.clear();
+  }
+
+  // Zeroes each footprint rather than deforming it back out with the opposite magnitude.
+  // Subtracting in a different order than the adds leaves float residue, so after a few
+  // ticks the field depends on its whole history. A snapshot only keeps this tick's
+  // stack, and restoring it onto a fresh field has to give the same heights bit for bit.
+  // deform is the only thing that writes the field, so everything outside the footprints
+  // is already zero.
   pub fn flatten(&mut self) -> () {
-    for deformation in self.deform_stack.clone().iter() {
-      self.deform(Vector3::new(deformation.x, deformation.y, 0.0), -deformation.magnitude);
+    for deformation in self.deform_stack.iter() {
+      let pos = WorldManifold::world_pos_to_field_pos(Vector3::new(deformation.x, deformation.y, 0.0));
+      for y in range(pos.y - KERNEL_RANGE, pos.y + KERNEL_RANGE + 1) {
+        for x in range(pos.x - KERNEL_RANGE, pos.x + KERNEL_RANGE + 1) {
+          self.field[y][x] = 0.0;
+        }
+      }
     }
     self.deform_stack.clear();
+    for layer in self.group_layers.iter_mut() {
+      layer.clear();
+    }
+  }
+
+  // Everything deformed since the last flatten, in order
+  pub fn deformations(&self) -> &[Deformation] {
+    return self.deform_stack.as_slice();
   }
 
-  pub fn deform(&mut self, pos: Vector3<f32>, magnitude: int) -> () {
+  pub fn deform(&mut self, pos: Vector3<f32>, magnitude: int, group: uint) -> () {
     // TODO: Something. At all.
     // Probably apply a gaussian deformation onto a 2d array
-    self.deform_stack.push(Deformation{magnitude: magnitude, x: pos.x, y: pos.y});
+    self.deform_stack.push(Deformation{magnitude: magnitude, group: group, x: pos.x, y: pos.y});
 
     // A deformation matrix
-    let deformation = WorldManifold::find_deformation(&mut self.deform_memo, magnitude);
+    let deformation = WorldManifold::find_deformation(&mut self.deform_memo, magnitude, self.kernel_sigma);
     //println!("{}", deformation);
 
     let pos = WorldManifold::world_pos_to_field_pos(pos);
@@ -49,7 +96,22 @@ impl WorldManifold {
       for (mat_x, field_str) in row.iter().enumerate() {
         //println!("x: {}, y: {}", mat_x, mat_y);
         // Sorry m8
-        self.field[mat_y - half_deformation_size + pos.y][mat_x - half_deformation_size + pos.x] = *field_str + self.field[mat_y - half_deformation_size + pos.y][mat_x - half_deformation_size + pos.x]
+        self.field[mat_y - half_deformation_size + pos.y][mat_x - half_deformation_size + pos.x] = *field_str + self.field[mat_y - half_deformation_size + pos.y][mat_x - half_deformation_size + pos.x];
+      }
+    }
+
+    if !self.split_groups {
+      return;
+    }
+    while self.group_layers.len() <= group {
+      self.group_layers.push(HashMap::new());
+    }
+    let layer = &mut self.group_layers[group];
+    for (mat_y, row) in deformation.iter().enumerate() {
+      for (mat_x, field_str) in row.iter().enumerate() {
+        let cell = (mat_x - half_deformation_size + pos.x, mat_y - half_deformation_size + pos.y);
+        let height = layer.get(&cell).map_or(0.0, |&height| height) + *field_str;
+        layer.insert(cell, height);
       }
     }
   }
@@ -58,28 +120,60 @@ impl WorldManifold {
     let pos = WorldManifold::world_pos_to_field_pos(pos);
 
     // TODO: A proper kernel or swappable differential kernel
-    //println!("new mat: ");
-    //println!("[ {}, {}, {} ]", self.field[pos.y-1][pos.x-1], self.field[pos.y-1][pos.x], self.field[pos.y-1][pos.x+1]);
-    //println!("[ {}, {}, {} ]", self.field[pos.y][pos.x-1], self.field[pos.y][pos.x], self.field[pos.y][pos.x+1]);
-    //println!("[ {}, {}, {} ]", self.field[pos.y+1][pos.x-1], self.field[pos.y+1][pos.x], self.field[pos.y+1][pos.x+1]);
-    let dx = -(SQRT_2 * self.field[pos.y+1][pos.x+1] +
-              self.field[pos.y][pos.x+1] +
-              SQRT_2 * self.field[pos.y-1][pos.x+1])
+    return WorldManifold::sobel(|dx, dy| {
+      self.field[(pos.y as int + dy) as uint][(pos.x as int + dx) as uint]
+    });
+    //return Vector3::new( 0.0, 0.0, 0.0);
+  }
+
+  // Same as gradient_at, but each group's deformations are scaled by weights[group].
+  // Groups without a weight count fully. Reads every group's layer, so only worth it
+  // when the weights aren't all 1, and needs split_groups on since the last flatten.
+  pub fn group_gradient_at(&self, pos: Vector3<f32>, weights: &[f32]) -> Vector3<f32> {
+    let pos = WorldManifold::world_pos_to_field_pos(pos);
+    return WorldManifold::sobel(|dx, dy| {
+      self.weighted_height_at_cell(pos.x as int + dx, pos.y as int + dy, weights)
+    });
+  }
+
+  fn sobel(height: |int, int| -> f32) -> Vector3<f32> {
+    let dx = -(SQRT_2 * height(1, 1) +
+              height(1, 0) +
+              SQRT_2 * height(1, -1))
               +
-              (SQRT_2 * self.field[pos.y+1][pos.x-1] +
-              self.field[pos.y][pos.x-1] +
-              SQRT_2 * self.field[pos.y-1][pos.x-1]);
+              (SQRT_2 * height(-1, 1) +
+              height(-1, 0) +
+              SQRT_2 * height(-1, -1));
 
-    let dy = -(SQRT_2 *self.field[pos.y+1][pos.x+1] +
-              self.field[pos.y+1][pos.x] +
-              SQRT_2 * self.field[pos.y+1][pos.x-1])
+    let dy = -(SQRT_2 * height(1, 1) +
+              height(0, 1) +
+              SQRT_2 * height(-1, 1))
               +
-              (SQRT_2 * self.field[pos.y-1][pos.x+1] +
-              self.field[pos.y-1][pos.x] +
-              SQRT_2 * self.field[pos.y-1][pos.x-1]);
+              (SQRT_2 * height(1, -1) +
+              height(0, -1) +
+              SQRT_2 * height(-1, -1));
 
     return Vector3::new( dx as f32, dy as f32, 0.0);
-    //return Vector3::new( 0.0, 0.0, 0.0);
+  }
+
+  fn weighted_height_at_cell(&self, x: int, y: int, weights: &[f32]) -> f32 {
+    if x < 0 || y < 0 {
+      return 0.0;
+    }
+    let cell = (x as uint, y as uint);
+
+    let mut height = 0.0;
+    for (group, layer) in self.group_layers.iter().enumerate() {
+      let weight = if group < weights.len() { weights[group] } else { 1.0 };
+      if weight == 0.0 {
+        continue;
+      }
+      match layer.get(&cell) {
+        Some(&layer_height) => height = height + weight * layer_height,
+        None => (),
+      }
+    }
+    return height;
   }
 
   pub fn height_at(&self, pos: Vector2<f32>) -> f32 {
@@ -87,7 +181,7 @@ impl WorldManifold {
     return self.field[pos.y][pos.x].clone();
   }
 
-  fn find_deformation(deform_memo: &mut HashMap<int, Vec<Vec<f32>>>, magnitude: int) -> Vec<Vec<f32>> {
+  fn find_deformation(deform_memo: &mut HashMap<int, Vec<Vec<f32>>>, magnitude: int, sigma: f32) -> Vec<Vec<f32>> {
 
     match deform_memo.get(&magnitude) {
       Some(deformation) => {
@@ -96,17 +190,12 @@ impl WorldManifold {
       _ => ()
     };
     //println!("calc deform: {} ", WorldManifold::calculate_deformation(magnitude));
-    deform_memo.insert(magnitude, WorldManifold::calculate_deformation(magnitude));
+    deform_memo.insert(magnitude, WorldManifold::calculate_deformation(magnitude, sigma));
     return deform_memo.get(&magnitude).unwrap().clone();
   }
 
-  fn calculate_deformation(magnitude: int) -> Vec<Vec<f32>> {
-    // Sets the "spread"
-    let sigma: f32 = 2.0;
-    let two_sigma_square: f32 = 18.0*(sigma*sigma);
-
-    // Caps the range of the gaussian
-    let maximum_range: uint = 8;
+  fn calculate_deformation(magnitude: int, sigma: f32) -> Vec<Vec<f32>> {
+    let maximum_range = KERNEL_RANGE;
 
     return Vec::from_fn(maximum_range*2 + 1, |row| {
       Vec::from_fn( maximum_range*2 + 1, |column| {
@@ -115,14 +204,27 @@ impl WorldManifold {
         let y: f32 =  ((maximum_range as f32) - (row as f32));
         //println!("      {},{}", x, y);
 
-        let gaussian: f32 = (-(((x*x)/two_sigma_square) + ((y*y)/two_sigma_square))).exp();
-        let field_strength: f32 = (magnitude as f32)*gaussian;
-
-        field_strength
+        WorldManifold::kernel_value(magnitude, sigma, x, y)
       })
     });
   }
 
+  // Strength of a single deformation at an offset (in field cells) from its centre
+  fn kernel_value(magnitude: int, sigma: f32, x: f32, y: f32) -> f32 {
+    let two_sigma_square: f32 = 18.0*(sigma*sigma);
+    let gaussian: f32 = (-(((x*x)/two_sigma_square) + ((y*y)/two_sigma_square))).exp();
+    return (magnitude as f32)*gaussian;
+  }
+
+  // Whether an entity here can press into the field, and so be ticked, without running off its edge
+  pub fn in_bounds(pos: Vector3<f32>) -> bool {
+    let x: f32 = pos.x * 3.0 + 5000.0;
+    let y: f32 = pos.y * 3.0 + 5000.0;
+    // The whole kernel footprint, plus the Sobel border, has to land on the field. NaN fails too.
+    let margin = (KERNEL_RANGE + 1) as f32;
+    return x >= margin && x < 10000.0 - margin && y >= margin && y < 10000.0 - margin;
+  }
+
   fn world_pos_to_field_pos(pos: Vector3<f32>) -> Vector3<uint> {
     let result = WorldManifold::world_pos_to_field_pos_2d(Vector2::new(pos.x, pos.y));
     Vector3::new(result.x, result.y, 0u)
@@ -0,0 +1,271 @@
+extern crate cgmath;
+
+use world_manifold::WorldManifold;
+
+use cgmath::{Vector, Vector2, Vector3, EuclideanVector};
+
+use std::num::Float;
+
+// How far outside its edge a keep-out zone starts pushing, fading to nothing at the far side
+pub static REPEL_BAND: f32 = 2.0;
+
+#[deriving(Clone)]
+pub enum ZoneShape {
+  Circle(Vector2<f32>, f32),
+  Polygon(Vec<Vector2<f32>>),
+  // Everywhere the manifold is higher than the given height
+  ManifoldMask(f32),
+}
+
+#[deriving(Clone)]
+pub enum ZoneKind {
+  Attract,
+  Repel,
+}
+
+#[deriving(Clone)]
+pub struct ZoneEnt {
+  pub shape: ZoneShape,
+  pub kind: ZoneKind,
+  pub strength: f32,
+  pub priority: int,
+}
+
+#[deriving(Clone)]
+pub struct ZoneInfluence {
+  pub accel: Vector3<f32>,
+  // Index of the zone that won out for this position, if any acted at all
+  pub dominant: Option<uint>,
+}
+
+impl ZoneEnt {
+  pub fn circle(center: Vector2<f32>, radius: f32, kind: ZoneKind, strength: f32, priority: int) -> ZoneEnt {
+    return ZoneEnt{shape: ZoneShape::Circle(center, radius), kind: kind, strength: strength, priority: priority};
+  }
+
+  pub fn polygon(points: Vec<Vector2<f32>>, kind: ZoneKind, strength: f32, priority: int) -> ZoneEnt {
+    return ZoneEnt{shape: ZoneShape::Polygon(points), kind: kind, strength: strength, priority: priority};
+  }
+
+  pub fn manifold_mask(threshold: f32, kind: ZoneKind, strength: f32, priority: int) -> ZoneEnt {
+    return ZoneEnt{shape: ZoneShape::ManifoldMask(threshold), kind: kind, strength: strength, priority: priority};
+  }
+
+  pub fn contains(&self, pos: Vector3<f32>, world: &WorldManifold) -> bool {
+    let flat_pos = Vector2::new(pos.x, pos.y);
+    match self.shape {
+      ZoneShape::Circle(center, radius) => flat_pos.sub_v(&center).length() <= radius,
+      ZoneShape::Polygon(ref points) => polygon_contains(points.as_slice(), flat_pos),
+      ZoneShape::ManifoldMask(threshold) => world.height_at(flat_pos) > threshold,
+    }
+  }
+
+  pub fn force_at(&self, pos: Vector3<f32>, world: &WorldManifold) -> Vector3<f32> {
+    let inside = self.contains(pos, world);
+    let no_force = Vector3::new(0.0, 0.0, 0.0);
+
+    // Goals stop pulling once reached. Keep-out zones push at full strength inside and
+    // fade out across REPEL_BAND beyond the edge, so the swarm is turned away before it's in.
+    let scale = match self.kind {
+      ZoneKind::Attract if inside => return no_force,
+      ZoneKind::Attract => -1.0,
+      ZoneKind::Repel if inside => 1.0,
+      ZoneKind::Repel => match self.distance_outside(pos) {
+        Some(dist) if dist < REPEL_BAND => 1.0 - dist / REPEL_BAND,
+        _ => return no_force,
+      },
+    };
+
+    return self.outward_dir(pos, world).mul_s(scale * self.strength);
+  }
+
+  // How far pos is beyond the zone's edge, None for shapes without a measurable edge
+  fn distance_outside(&self, pos: Vector3<f32>) -> Option<f32> {
+    let flat_pos = Vector2::new(pos.x, pos.y);
+    return match self.shape {
+      ZoneShape::Circle(center, radius) => Some((flat_pos.sub_v(&center).length() - radius).max(0.0)),
+      ZoneShape::Polygon(ref points) => nearest_edge(points.as_slice(), flat_pos).map(|(_, dist)| dist),
+      ZoneShape::ManifoldMask(_) => None,
+    };
+  }
+
+  // Unit vector pointing away from the zone, or zero if there is no sensible direction
+  fn outward_dir(&self, pos: Vector3<f32>, world: &WorldManifold) -> Vector3<f32> {
+    let flat_pos = Vector2::new(pos.x, pos.y);
+    let dir = match self.shape {
+      ZoneShape::Circle(center, _) => flat_pos.sub_v(&center),
+      ZoneShape::Polygon(ref points) => {
+        nearest_edge(points.as_slice(), flat_pos).map_or(Vector2::new(0.0, 0.0), |(normal, _)| normal)
+      },
+      ZoneShape::ManifoldMask(_) => {
+        // The manifold gradient already points downhill, out of the raised region
+        let gradient = world.gradient_at(pos);
+        Vector2::new(gradient.x, gradient.y)
+      },
+    };
+
+    if dir.length() == 0.0 {
+      return Vector3::new(0.0, 0.0, 0.0);
+    }
+    let dir = dir.normalize();
+    return Vector3::new(dir.x, dir.y, 0.0);
+  }
+}
+
+pub fn combined_zone_force_at(zones: &[ZoneEnt], pos: Vector3<f32>, world: &WorldManifold) -> ZoneInfluence {
+  let mut accel = Vector3::new(0.0, 0.0, 0.0);
+  let mut dominant: Option<uint> = None;
+  let mut dominant_priority: int = 0;
+  let mut dominant_magnitude: f32 = 0.0;
+
+  for (idx, zone) in zones.iter().enumerate() {
+    let force = zone.force_at(pos, world);
+    let magnitude = force.length();
+    if magnitude == 0.0 {
+      continue;
+    }
+
+    let outranks = match dominant {
+      None => true,
+      Some(_) => zone.priority > dominant_priority,
+    };
+
+    if outranks {
+      // Lower priority zones are suppressed entirely
+      accel = force;
+      dominant = Some(idx);
+      dominant_priority = zone.priority;
+      dominant_magnitude = magnitude;
+    } else if zone.priority == dominant_priority {
+      accel = accel.add_v(&force);
+      if magnitude > dominant_magnitude {
+        dominant = Some(idx);
+        dominant_magnitude = magnitude;
+      }
+    }
+  }
+
+  return ZoneInfluence{accel: accel, dominant: dominant};
+}
+
+fn polygon_contains(points: &[Vector2<f32>], pos: Vector2<f32>) -> bool {
+  // Even-odd ray cast along +x
+  let mut inside = false;
+  let count = points.len();
+  for i in range(0, count) {
+    let a = points[i];
+    let b = points[(i + count - 1) % count];
+    if (a.y > pos.y) != (b.y > pos.y) {
+      let cross_x = (b.x - a.x) * (pos.y - a.y) / (b.y - a.y) + a.x;
+      if pos.x < cross_x {
+        inside = !inside;
+      }
+    }
+  }
+  return inside;
+}
+
+// Outward normal of the edge closest to pos, and the distance to it. Unlike heading away from
+// the centroid the normal still points straight out of the nearest wall for long or concave polygons.
+fn nearest_edge(points: &[Vector2<f32>], pos: Vector2<f32>) -> Option<(Vector2<f32>, f32)> {
+  let count = points.len();
+  if count < 2 {
+    return None;
+  }
+
+  // Positive for counter clockwise winding, where the outward normal is on the right of each edge
+  let mut twice_area = 0.0;
+  for i in range(0, count) {
+    let a = points[i];
+    let b = points[(i + 1) % count];
+    twice_area = twice_area + (a.x * b.y - b.x * a.y);
+  }
+  let winding = if twice_area < 0.0 { -1.0 } else { 1.0 };
+
+  let mut nearest = None;
+  let mut nearest_dist = Float::infinity();
+  for i in range(0, count) {
+    let a = points[i];
+    let b = points[(i + 1) % count];
+    let edge = b.sub_v(&a);
+    let edge_len_sq = edge.dot(&edge);
+    if edge_len_sq == 0.0 {
+      continue;
+    }
+
+    let along = (pos.sub_v(&a).dot(&edge) / edge_len_sq).max(0.0).min(1.0);
+    let dist = pos.sub_v(&a.add_v(&edge.mul_s(along))).length();
+    if dist < nearest_dist {
+      nearest_dist = dist;
+      nearest = Some((Vector2::new(edge.y, -edge.x).mul_s(winding), dist));
+    }
+  }
+  return nearest;
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ZoneEnt, ZoneKind, REPEL_BAND, combined_zone_force_at};
+  use world_manifold::WorldManifold;
+
+  use cgmath::{Vector, Vector2, Vector3};
+
+  fn square(kind: ZoneKind, priority: int) -> ZoneEnt {
+    let points = vec![Vector2::new(-1.0, -1.0), Vector2::new(1.0, -1.0), Vector2::new(1.0, 1.0), Vector2::new(-1.0, 1.0)];
+    return ZoneEnt::polygon(points, kind, 1.0, priority);
+  }
+
+  #[test]
+  fn polygon_containment() {
+    let world = WorldManifold::default();
+    let zone = square(ZoneKind::Repel, 0);
+    assert!(zone.contains(Vector3::new(0.0, 0.0, 0.0), &world));
+    assert!(zone.contains(Vector3::new(0.9, -0.9, 0.0), &world));
+    assert!(!zone.contains(Vector3::new(1.1, 0.0, 0.0), &world));
+    assert!(!zone.contains(Vector3::new(0.0, -3.0, 0.0), &world));
+  }
+
+  #[test]
+  fn attract_pulls_in_and_repel_pushes_out() {
+    let world = WorldManifold::default();
+    let outside = Vector3::new(3.0, 0.0, 0.0);
+    let attract = square(ZoneKind::Attract, 0).force_at(outside, &world);
+    assert!(attract.x < 0.0 && attract.y == 0.0);
+    assert_eq!(square(ZoneKind::Attract, 0).force_at(Vector3::new(0.0, 0.5, 0.0), &world), Vector3::new(0.0, 0.0, 0.0));
+
+    // Nearest wall is the top one, whichever side of the centre we're on
+    let repel = square(ZoneKind::Repel, 0).force_at(Vector3::new(0.5, 0.8, 0.0), &world);
+    assert!(repel.y > 0.0 && repel.x == 0.0);
+  }
+
+  #[test]
+  fn repel_fades_across_the_band() {
+    let world = WorldManifold::default();
+    let zone = ZoneEnt::circle(Vector2::new(0.0, 0.0), 1.0, ZoneKind::Repel, 4.0, 0);
+    let near = zone.force_at(Vector3::new(1.0 + 0.25 * REPEL_BAND, 0.0, 0.0), &world);
+    let far = zone.force_at(Vector3::new(1.0 + 0.75 * REPEL_BAND, 0.0, 0.0), &world);
+    assert!(near.x > far.x && far.x > 0.0);
+    assert_eq!(zone.force_at(Vector3::new(1.0 + 2.0 * REPEL_BAND, 0.0, 0.0), &world), Vector3::new(0.0, 0.0, 0.0));
+  }
+
+  #[test]
+  fn higher_priority_suppresses_and_equal_priority_sums() {
+    let world = WorldManifold::default();
+    let pos = Vector3::new(3.0, 0.0, 0.0);
+    let low = ZoneEnt::circle(Vector2::new(10.0, 0.0), 1.0, ZoneKind::Attract, 1.0, 0);
+    let high = ZoneEnt::circle(Vector2::new(0.0, 10.0), 1.0, ZoneKind::Attract, 1.0, 5);
+
+    let influence = combined_zone_force_at(&[low.clone(), high.clone()], pos, &world);
+    assert_eq!(influence.dominant, Some(1));
+    assert_eq!(influence.accel, high.force_at(pos, &world));
+
+    let mut stronger = low.clone();
+    stronger.strength = 3.0;
+    let mut tied = high.clone();
+    tied.priority = 0;
+    let influence = combined_zone_force_at(&[tied.clone(), stronger.clone()], pos, &world);
+    assert_eq!(influence.dominant, Some(1));
+    let expected = tied.force_at(pos, &world).add_v(&stronger.force_at(pos, &world));
+    assert!((influence.accel.x - expected.x).abs() < 1e-6 && (influence.accel.y - expected.y).abs() < 1e-6);
+  }
+}